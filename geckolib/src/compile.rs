@@ -0,0 +1,209 @@
+//! Cross-compiles the Rom Hack's crate with cargo, so its staticlib can be linked into the game.
+
+use crate::config::Link;
+#[cfg(feature = "progress")]
+use crate::UPDATER;
+use eyre::Context;
+use serde_derive::Deserialize;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The PowerPC target the Rom Hack is compiled for, unless the config specifies another one
+pub const DEFAULT_TARGET: &str = "powerpc-unknown-eabi";
+
+/// The cargo profile the Rom Hack is compiled with, as scaffolded in its Cargo.toml
+pub const DEFAULT_PROFILE: &str = "release";
+
+/// The parts of cargo's JSON messages needed to find the produced staticlib
+#[derive(Deserialize)]
+struct Message {
+    reason: String,
+    #[serde(default)]
+    target: Option<Target>,
+    #[serde(default)]
+    filenames: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    kind: Vec<String>,
+}
+
+/// Returns the staticlib produced by the build, given cargo's JSON messages
+fn find_staticlib<R: BufRead>(messages: R) -> eyre::Result<Option<PathBuf>> {
+    let mut staticlib = None;
+    for line in messages.lines() {
+        let line = line?;
+        let Ok(message) = serde_json::from_str::<Message>(&line) else {
+            continue;
+        };
+        if message.reason != "compiler-artifact"
+            || !message
+                .target
+                .is_some_and(|t| t.kind.iter().any(|k| k == "staticlib"))
+        {
+            continue;
+        }
+        // The Rom Hack's own crate is built last, after its dependencies
+        if let Some(path) = message
+            .filenames
+            .into_iter()
+            .find(|f| f.extension().is_some_and(|e| e == "a"))
+        {
+            staticlib = Some(path);
+        }
+    }
+    Ok(staticlib)
+}
+
+/// Compiles the crate in `project_dir` and returns the path to its staticlib
+pub fn compile(project_dir: &Path, link: &Link) -> eyre::Result<PathBuf> {
+    let target = link.target.as_deref().unwrap_or(DEFAULT_TARGET);
+    let profile = link.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+
+    crate::info!("Compiling the Rom Hack for {} ({})", target, profile);
+
+    #[cfg(feature = "progress")]
+    if let Ok(mut updater) = UPDATER.lock() {
+        updater.set_title("Compiling...".into())?;
+        updater.set_message("".into())?;
+    }
+
+    let mut child = Command::new("cargo")
+        .current_dir(project_dir)
+        .args([
+            "build",
+            "--target",
+            target,
+            "--profile",
+            profile,
+            "-Zbuild-std=core",
+            "--message-format=json-render-diagnostics",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Couldn't run cargo. Is it installed and in your PATH?")?;
+
+    // Cargo's progress and rendered diagnostics are on stderr, the JSON messages on stdout
+    let stderr = BufReader::new(
+        child
+            .stderr
+            .take()
+            .ok_or_else(|| eyre::eyre!("Couldn't read cargo's output"))?,
+    );
+    let output = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        for line in stderr.lines().map_while(Result::ok) {
+            crate::info!("{}", line);
+
+            #[cfg(feature = "progress")]
+            if let Ok(mut updater) = UPDATER.lock() {
+                let _ = updater.set_message(line.trim().to_owned());
+            }
+
+            output.push(line);
+        }
+        output
+    });
+
+    let stdout = BufReader::new(
+        child
+            .stdout
+            .take()
+            .ok_or_else(|| eyre::eyre!("Couldn't read cargo's output"))?,
+    );
+    let staticlib = find_staticlib(stdout)?;
+    let status = child.wait()?;
+    let output = output.join().unwrap_or_default();
+
+    if !status.success() {
+        eyre::bail!(
+            "Couldn't compile the Rom Hack. Cargo's output was:\n{}",
+            output.join("\n")
+        );
+    }
+    staticlib.ok_or_else(|| {
+        eyre::eyre!(
+        "Cargo didn't produce a staticlib. Is `crate-type = [\"staticlib\"]` set in the Cargo.toml?"
+    )
+    })
+}
+
+/// Whether to compile the crate in `project_dir` before linking, see [`Link::build`]
+pub fn is_needed(project_dir: &Path, link: &Link) -> bool {
+    link.build
+        .unwrap_or_else(|| project_dir.join("Cargo.toml").is_file())
+}
+
+/// Compiles the Rom Hack and puts its staticlib first in the libraries to link
+pub fn compile_into(project_dir: &Path, link: &mut Link) -> eyre::Result<()> {
+    let staticlib = compile(project_dir, link)?;
+    replace_staticlib(project_dir, &mut link.libs, staticlib)
+}
+
+/// Puts `staticlib` first in `libs`, removing it from where older configs list it themselves
+fn replace_staticlib(
+    project_dir: &Path,
+    libs: &mut Vec<PathBuf>,
+    staticlib: PathBuf,
+) -> eyre::Result<()> {
+    let canonical = staticlib
+        .canonicalize()
+        .context(format!("Couldn't find \"{}\"", staticlib.display()))?;
+    libs.retain(|lib| project_dir.join(lib).canonicalize().ok().as_ref() != Some(&canonical));
+    libs.insert(0, staticlib);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staticlib_is_found_in_messages() {
+        let messages = r#"{"reason":"compiler-artifact","target":{"kind":["lib"],"name":"gcn"},"filenames":["/p/target/deps/libgcn.rlib"]}
+{"reason":"build-script-executed","package_id":"x"}
+{"reason":"compiler-artifact","target":{"kind":["staticlib"],"name":"hack"},"filenames":["/p/target/powerpc-unknown-eabi/release/libhack.a"]}
+{"reason":"build-finished","success":true}"#;
+        assert_eq!(
+            find_staticlib(messages.as_bytes()).unwrap(),
+            Some(PathBuf::from(
+                "/p/target/powerpc-unknown-eabi/release/libhack.a"
+            ))
+        );
+    }
+
+    #[test]
+    fn crates_are_compiled_unless_disabled() {
+        let dir = std::env::temp_dir().join(format!("geckolib-compile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut link = Link {
+            entries: Vec::new(),
+            base: "0x80401000".to_owned(),
+            libs: vec![PathBuf::from("libgame.a")],
+            target: None,
+            profile: None,
+            build: None,
+        };
+        assert!(!is_needed(&dir, &link));
+
+        std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"my-hack\"\n").unwrap();
+        assert!(is_needed(&dir, &link));
+        // Listing the staticlib doesn't skip the compilation, it's replaced by the fresh one
+        link.libs
+            .push("./target/powerpc-unknown-eabi/release/libmy_hack.a".into());
+        assert!(is_needed(&dir, &link));
+        link.build = Some(false);
+        assert!(!is_needed(&dir, &link));
+
+        let staticlib = dir.join("target/powerpc-unknown-eabi/release/libmy_hack.a");
+        std::fs::create_dir_all(staticlib.parent().unwrap()).unwrap();
+        std::fs::write(&staticlib, []).unwrap();
+        replace_staticlib(&dir, &mut link.libs, staticlib.clone()).unwrap();
+        assert_eq!(link.libs, vec![staticlib, PathBuf::from("libgame.a")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
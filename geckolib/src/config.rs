@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 use serde_derive::{Deserialize, Serialize};
@@ -10,6 +10,17 @@ pub struct Config {
     pub src: Src,
     #[serde(default)]
     pub files: HashMap<String, PathBuf>,
+    #[serde(default)]
+    pub remove: Remove,
+    /// New paths of the disc's files and directories, by their original path
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+    #[serde(default)]
+    pub placement: Placement,
+    #[serde(default)]
+    pub header: Header,
+    #[serde(default)]
+    pub wii: Wii,
     pub build: Build,
     pub link: Option<Link>,
 }
@@ -21,6 +32,106 @@ pub struct Src {
     pub iso: PathBuf,
     pub patch: Option<PathBuf>,
     pub map: Option<String>,
+    /// Folders and zips of other mods, applied in order before `[files]`
+    #[serde(default)]
+    pub overlays: Vec<PathBuf>,
+}
+
+/// Files and directories of the disc to remove, to make room or drop unused assets
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Remove {
+    /// Paths or globs of the disc's files and directories
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+/// Where the files' data is placed on the disc
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Placement {
+    /// Paths or globs of the files to place first, in this order
+    #[serde(default)]
+    pub order: Vec<String>,
+    /// Alignments in bytes, by path or glob, of the files needing more than 32 bytes
+    #[serde(default)]
+    pub align: BTreeMap<String, u64>,
+    /// Keeps the game's unchanged files, its DOL and its FST at their original offsets when
+    /// they still fit
+    #[serde(default)]
+    pub preserve_offsets: bool,
+}
+
+/// Identity of the disc, so the Rom Hack's saves and settings don't collide with the game's
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Header {
+    /// The 2 characters following the console's in the game ID, `ZL` of `GZLE01`
+    pub game_code: Option<String>,
+    /// The character of the game ID naming the region, `E` of `GZLE01`
+    pub region_code: Option<String>,
+    /// The 2 characters naming the publisher, `01` of `GZLE01`
+    pub maker_code: Option<String>,
+    pub disc_number: Option<u8>,
+    pub disc_version: Option<u8>,
+    /// The game's internal title, in Shift-JIS on Japanese discs
+    pub title: Option<String>,
+    /// `bi2.bin`'s size of the debug monitor
+    pub debug_monitor_size: Option<u32>,
+    /// `bi2.bin`'s size of the simulated memory
+    pub simulated_memory_size: Option<u32>,
+    /// `bi2.bin`'s debug flag
+    pub debug_flag: Option<u32>,
+    /// Also makes the game ID the title ID of the Wii partition's ticket and TMD, which are
+    /// signed again
+    #[serde(default)]
+    pub wii_title_id: bool,
+}
+
+/// The Wii partition's title metadata and the disc's region, signed again when written
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Wii {
+    /// IOS the game runs on, set in the TMD's system version
+    pub ios: Option<u8>,
+    /// Title ID of the ticket and TMD, 16 hexadecimal digits like `00010000525a4445`. Takes
+    /// precedence over `[header]`'s `wii-title-id`
+    pub title_id: Option<String>,
+    pub region: Option<WiiRegion>,
+    /// Minimum ages by rating board, 0x80 when the board doesn't rate the game
+    #[serde(default)]
+    pub age_ratings: AgeRatings,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WiiRegion {
+    Japan,
+    Usa,
+    Europe,
+    Korea,
+    /// Boots on the consoles of every region
+    Free,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct AgeRatings {
+    /// CERO
+    pub jp: Option<u8>,
+    /// ESRB
+    pub us: Option<u8>,
+    /// USK
+    pub de: Option<u8>,
+    pub pegi: Option<u8>,
+    /// PEGI Finland
+    pub fi: Option<u8>,
+    /// PEGI Portugal
+    pub pt: Option<u8>,
+    /// BBFC
+    pub gb: Option<u8>,
+    /// OFLC
+    pub au: Option<u8>,
+    /// GRB
+    pub kr: Option<u8>,
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
@@ -35,14 +146,51 @@ pub struct Info {
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub struct Build {
     pub map: Option<PathBuf>,
     pub iso: PathBuf,
+    #[serde(default)]
+    pub symbols: Vec<SymbolExport>,
+    /// Fills the space between the files with the junk data of retail discs instead of zeros
+    #[serde(default)]
+    pub junk_padding: bool,
+    /// Leaves the groups of a Wii partition without files as unencrypted zeros, for the smallest
+    /// image to share. GameCube discs are unchanged, their unused space is zeros already.
+    #[serde(default)]
+    pub scrub: bool,
+}
+
+/// An additional symbol table to write, combining the game's and the Rom Hack's symbols
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SymbolExport {
+    pub format: SymbolFormat,
+    pub path: PathBuf,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolFormat {
+    /// Symbol map loadable by Dolphin's debugger
+    Dolphin,
+    /// Python script to run in Ghidra's script manager
+    Ghidra,
+    /// IDC script for IDA
+    Ida,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Link {
     pub entries: Vec<String>,
     pub base: String,
+    /// Additional libraries, linked after the staticlib compiled from the Rom Hack's crate
+    #[serde(default)]
     pub libs: Vec<PathBuf>,
+    /// Target to compile the Rom Hack for, `powerpc-unknown-eabi` by default
+    pub target: Option<String>,
+    /// Cargo profile to compile the Rom Hack with, `release` by default
+    pub profile: Option<String>,
+    /// Whether to compile the crate next to the config before linking. By default it's
+    /// compiled when there's a Cargo.toml, replacing its staticlib if `libs` lists it
+    pub build: Option<bool>,
 }
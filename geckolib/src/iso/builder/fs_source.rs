@@ -1,9 +1,9 @@
-use std::path::Path;
-#[cfg(not(target_os = "unknown"))]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::{Read, Seek};
 use zip::{read::ZipFile, ZipArchive};
 
+use crate::vfs::FileDataSource;
+
 /// A file from an arbitrary source
 pub enum File<'a> {
     Zip(Box<ZipFile<'a>>),
@@ -34,14 +34,29 @@ impl<'a> Read for File<'a> {
 /// A source of files for the builder
 #[derive(Debug)]
 pub enum FSSource<R> {
-    Zip(Box<ZipArchive<R>>),
+    /// A zip, and its path when it's on the host
+    Zip(Box<ZipArchive<R>>, Option<PathBuf>),
     #[cfg(not(target_os = "unknown"))]
     FS(PathBuf),
 }
 
 impl<R> FSSource<R> {
     pub fn with_zip(zip: ZipArchive<R>) -> Self {
-        Self::Zip(Box::new(zip))
+        Self::Zip(Box::new(zip), None)
+    }
+
+    /// A zip on the host, whose files can be read lazily
+    pub fn with_host_zip<P: AsRef<Path>>(zip: ZipArchive<R>, path: P) -> Self {
+        Self::Zip(Box::new(zip), Some(path.as_ref().to_path_buf()))
+    }
+
+    /// Path of `path` on the host, when the files aren't in a zip
+    pub fn host_path<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        match self {
+            FSSource::Zip(..) => None,
+            #[cfg(not(target_os = "unknown"))]
+            FSSource::FS(inner_path) => Some(inner_path.join(path)),
+        }
     }
 
     #[cfg(not(target_os = "unknown"))]
@@ -53,7 +68,7 @@ impl<R> FSSource<R> {
 impl<R: Read + Seek> FSSource<R> {
     pub fn exists<P: AsRef<Path>>(&self, path: P) -> bool {
         match self {
-            FSSource::Zip(zip) => zip.index_for_path(path).is_some(),
+            FSSource::Zip(zip, _) => zip.index_for_path(path).is_some(),
             #[cfg(not(target_os = "unknown"))]
             FSSource::FS(inner_path) => {
                 let p = inner_path.join(path);
@@ -64,7 +79,7 @@ impl<R: Read + Seek> FSSource<R> {
 
     pub fn is_dir<P: AsRef<Path>>(&mut self, path: P) -> bool {
         match self {
-            FSSource::Zip(zip) => zip
+            FSSource::Zip(zip, _) => zip
                 .index_for_path(path)
                 .and_then(|idx| zip.by_index(idx).ok())
                 .map_or(false, |entry| entry.is_dir()),
@@ -78,7 +93,7 @@ impl<R: Read + Seek> FSSource<R> {
 
     pub fn is_file<P: AsRef<Path>>(&mut self, path: P) -> bool {
         match self {
-            FSSource::Zip(zip) => zip
+            FSSource::Zip(zip, _) => zip
                 .index_for_path(path)
                 .and_then(|idx| zip.by_index(idx).ok())
                 .map_or(false, |entry| entry.is_file()),
@@ -92,7 +107,7 @@ impl<R: Read + Seek> FSSource<R> {
 
     pub fn get_file<P: AsRef<Path>>(&mut self, path: P) -> eyre::Result<File> {
         match self {
-            FSSource::Zip(zip) => Ok(File::Zip(Box::new(
+            FSSource::Zip(zip, _) => Ok(File::Zip(Box::new(
                 zip.by_name(
                     path.as_ref()
                         .to_str()
@@ -113,6 +128,73 @@ impl<R: Read + Seek> FSSource<R> {
             }
         }
     }
+
+    /// Describes the file so its data is only read when the disc is written. Returns `None`
+    /// when it has to be loaded in memory instead.
+    pub fn lazy_source<P: AsRef<Path>, RDisc>(
+        &mut self,
+        path: P,
+        name: String,
+    ) -> eyre::Result<Option<FileDataSource<RDisc>>> {
+        match self {
+            FSSource::Zip(zip, Some(zip_path)) => Ok(zip.index_for_path(path).and_then(|index| {
+                FileDataSource::from_zip_entry(zip, &*zip_path, index, name).ok()
+            })),
+            FSSource::Zip(_, None) => Ok(None),
+            #[cfg(not(target_os = "unknown"))]
+            FSSource::FS(inner_path) => Ok(Some(FileDataSource::from_host_file(
+                inner_path.join(path),
+                name,
+            )?)),
+        }
+    }
+
+    /// Lists the paths of the files below the directory `path`, relative to it
+    pub fn list_files<P: AsRef<Path>>(&self, path: P) -> eyre::Result<Vec<String>> {
+        let path = path.as_ref();
+        let mut files = Vec::new();
+        match self {
+            FSSource::Zip(zip, _) => {
+                let prefix = path.to_string_lossy().trim_end_matches('/').to_owned() + "/";
+                files.extend(
+                    zip.file_names()
+                        .filter(|name| !name.ends_with('/'))
+                        .filter_map(|name| match prefix.as_str() {
+                            "/" => Some(name),
+                            prefix => name.strip_prefix(prefix),
+                        })
+                        .map(str::to_owned),
+                );
+                if files.is_empty() {
+                    return Err(eyre::eyre!("{:?} is empty or doesn't exist", path));
+                }
+            }
+            #[cfg(not(target_os = "unknown"))]
+            FSSource::FS(inner_path) => {
+                let root = inner_path.join(path);
+                let mut stack = vec![root.clone()];
+                while let Some(dir) = stack.pop() {
+                    for entry in std::fs::read_dir(&dir)? {
+                        let entry_path = entry?.path();
+                        if entry_path.is_dir() {
+                            stack.push(entry_path);
+                        } else {
+                            let relative = entry_path.strip_prefix(&root)?;
+                            files.push(
+                                relative
+                                    .components()
+                                    .map(|c| c.as_os_str().to_string_lossy())
+                                    .collect::<Vec<_>>()
+                                    .join("/"),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
 }
 
 impl<R> FSSource<R> {
@@ -120,7 +202,7 @@ impl<R> FSSource<R> {
     pub fn get_names<P: AsRef<Path>>(&self, path: P) -> eyre::Result<Vec<String>> {
         let mut names: Vec<String> = Vec::new();
         match self {
-            FSSource::Zip(_) => {
+            FSSource::Zip(..) => {
                 return Err(eyre::eyre!(
                     "Unsupported operation on ZipArchive: get_names"
                 ))
@@ -137,4 +219,4 @@ impl<R> FSSource<R> {
         }
         Ok(names)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,303 @@
+//! The disc's identity, edited from `[header]` and `[wii]`.
+
+use crate::config::{Header, Wii, WiiRegion};
+use crate::iso::disc::{
+    disc_get_header, disc_set_header, WiiDisc, WiiDiscRegion, WiiDiscRegions, WiiPartition,
+};
+use byteorder::{ByteOrder, BE};
+use encoding_rs::SHIFT_JIS;
+
+const TITLE_OFFSET: usize = 0x20;
+/// Room for the title in `boot.bin`, it's cut to 0x40 bytes on Wii where the flags follow
+const GC_TITLE_LEN: usize = 0x3E0;
+const WII_TITLE_LEN: usize = 0x40;
+const BI2_OFFSET: usize = 0x440;
+/// Title IDs of the IOSes are `00000001-000000XX`
+const IOS_TITLE_ID: u64 = 0x0000_0001_0000_0000;
+
+fn check_code(field: &str, code: &str, len: usize) -> eyre::Result<()> {
+    if code.len() != len
+        || !code
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+    {
+        eyre::bail!(
+            "The {} \"{}\" must be {} uppercase letters or digits",
+            field,
+            code,
+            len
+        );
+    }
+    Ok(())
+}
+
+/// Encodes the title, in Shift-JIS on Japanese discs and ASCII otherwise, leaving room for
+/// its terminator
+fn encode_title(title: &str, is_japanese: bool, max_len: usize) -> eyre::Result<Vec<u8>> {
+    let bytes = if is_japanese {
+        let (bytes, _, had_errors) = SHIFT_JIS.encode(title);
+        if had_errors {
+            eyre::bail!("The title \"{}\" can't be written in Shift-JIS", title);
+        }
+        bytes.into_owned()
+    } else {
+        if !title.bytes().all(|b| b.is_ascii() && !b.is_ascii_control()) {
+            eyre::bail!(
+                "The title \"{}\" must be printable ASCII, only Japanese discs use Shift-JIS",
+                title
+            );
+        }
+        title.as_bytes().to_vec()
+    };
+    if bytes.len() >= max_len {
+        eyre::bail!(
+            "The title \"{}\" takes {} bytes, it must take less than {}",
+            title,
+            bytes.len(),
+            max_len
+        );
+    }
+    Ok(bytes)
+}
+
+/// Writes `[header]` to `iso.hdr`, `boot.bin` followed by `bi2.bin`
+pub(super) fn apply(header: &Header, hdr: &mut [u8], is_wii: bool) -> eyre::Result<()> {
+    if hdr.len() < BI2_OFFSET + 0x10 {
+        eyre::bail!("The disc's header is too short");
+    }
+    if let Some(game_code) = &header.game_code {
+        check_code("game code", game_code, 2)?;
+        hdr[1..3].copy_from_slice(game_code.as_bytes());
+    }
+    if let Some(region_code) = &header.region_code {
+        check_code("region code", region_code, 1)?;
+        hdr[3] = region_code.as_bytes()[0];
+    }
+    if let Some(maker_code) = &header.maker_code {
+        check_code("maker code", maker_code, 2)?;
+        hdr[4..6].copy_from_slice(maker_code.as_bytes());
+    }
+    if let Some(disc_number) = header.disc_number {
+        hdr[6] = disc_number;
+    }
+    if let Some(disc_version) = header.disc_version {
+        hdr[7] = disc_version;
+    }
+    if let Some(title) = &header.title {
+        let max_len = if is_wii { WII_TITLE_LEN } else { GC_TITLE_LEN };
+        let bytes = encode_title(title, hdr[3] == b'J', max_len)?;
+        let field = &mut hdr[TITLE_OFFSET..TITLE_OFFSET + max_len];
+        field.fill(0);
+        field[..bytes.len()].copy_from_slice(&bytes);
+    }
+
+    let bi2 = &mut hdr[BI2_OFFSET..];
+    if let Some(size) = header.debug_monitor_size {
+        BE::write_u32(&mut bi2[0x0..], size);
+    }
+    if let Some(size) = header.simulated_memory_size {
+        BE::write_u32(&mut bi2[0x4..], size);
+    }
+    if let Some(flag) = header.debug_flag {
+        BE::write_u32(&mut bi2[0xC..], flag);
+    }
+    Ok(())
+}
+
+/// Gives the Wii disc's header, and on request its data partition's title ID, the identity
+/// of `hdr`. The writer signs the ticket and TMD again.
+pub(super) fn apply_wii(header: &Header, hdr: &[u8], disc: &mut WiiDisc) -> eyre::Result<()> {
+    let mut raw = [0u8; 0x400];
+    disc_set_header(&mut raw, &disc.disc_header);
+    raw[..TITLE_OFFSET].copy_from_slice(&hdr[..TITLE_OFFSET]);
+    raw[TITLE_OFFSET..TITLE_OFFSET + WII_TITLE_LEN]
+        .copy_from_slice(&hdr[TITLE_OFFSET..TITLE_OFFSET + WII_TITLE_LEN]);
+    disc.disc_header = disc_get_header(&raw);
+
+    if header.wii_title_id {
+        let part = data_partition(disc)?;
+        part.header.ticket.title_id[4..].copy_from_slice(&hdr[..4]);
+        part.tmd.title_id[4..].copy_from_slice(&hdr[..4]);
+    }
+    Ok(())
+}
+
+fn data_partition(disc: &mut WiiDisc) -> eyre::Result<&mut WiiPartition> {
+    let idx = disc.partitions.data_idx;
+    disc.partitions
+        .partitions
+        .get_mut(idx)
+        .ok_or(eyre::eyre!("The disc has no data partition"))
+}
+
+/// Writes `[wii]` to the disc's region and its data partition's ticket and TMD
+pub(super) fn apply_wii_config(wii: &Wii, disc: &mut WiiDisc) -> eyre::Result<()> {
+    if let Some(title_id) = &wii.title_id {
+        if title_id.len() != 16 || !title_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            eyre::bail!(
+                "The title ID \"{}\" must be 16 hexadecimal digits",
+                title_id
+            );
+        }
+        let title_id = u64::from_str_radix(title_id, 16)?.to_be_bytes();
+        let part = data_partition(disc)?;
+        part.header.ticket.title_id = title_id;
+        part.tmd.title_id = title_id;
+    }
+    if let Some(ios) = wii.ios {
+        data_partition(disc)?.tmd.system_version = IOS_TITLE_ID | ios as u64;
+    }
+
+    let region = &mut disc.disc_region;
+    if let Some(new_region) = wii.region {
+        region.region = match new_region {
+            WiiRegion::Japan => WiiDiscRegions::NTSCJ,
+            WiiRegion::Usa => WiiDiscRegions::NTSCU,
+            WiiRegion::Europe => WiiDiscRegions::PAL,
+            WiiRegion::Korea => WiiDiscRegions::KOR,
+            WiiRegion::Free => WiiDiscRegions::Free,
+        };
+    }
+    let ratings = &wii.age_ratings;
+    let age_rating = &mut region.age_rating;
+    for (rating, age) in [
+        (ratings.jp, &mut age_rating.jp),
+        (ratings.us, &mut age_rating.us),
+        (ratings.de, &mut age_rating.de),
+        (ratings.pegi, &mut age_rating.pegi),
+        (ratings.fi, &mut age_rating.fi),
+        (ratings.pt, &mut age_rating.pt),
+        (ratings.gb, &mut age_rating.gb),
+        (ratings.au, &mut age_rating.au),
+        (ratings.kr, &mut age_rating.kr),
+    ] {
+        if let Some(rating) = rating {
+            *age = rating;
+        }
+    }
+    Ok(())
+}
+
+/// Whether two Wii discs have the same header, region, title IDs and IOS
+pub(super) fn same_identity(a: &WiiDisc, b: &WiiDisc) -> bool {
+    let identity = |disc: &WiiDisc| {
+        let mut header = [0u8; 0x400];
+        disc_set_header(&mut header, &disc.disc_header);
+        let mut region = [0u8; <WiiDiscRegion as crate::crypto::Unpackable>::BLOCK_SIZE];
+        disc.disc_region.compose_into(&mut region);
+        let title = disc
+            .partitions
+            .partitions
+            .get(disc.partitions.data_idx)
+            .map(|part| {
+                (
+                    part.header.ticket.title_id,
+                    part.tmd.title_id,
+                    part.tmd.system_version,
+                )
+            });
+        (header, region, title)
+    };
+    identity(a) == identity(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_fields_are_validated_and_written() {
+        let mut hdr = vec![0u8; crate::iso::consts::HEADER_LENGTH];
+        hdr[..6].copy_from_slice(b"GZLE01");
+        hdr[0x20..0x30].fill(b'x');
+        let header = Header {
+            game_code: Some("ZX".to_owned()),
+            maker_code: Some("8P".to_owned()),
+            disc_version: Some(2),
+            title: Some("Hack".to_owned()),
+            debug_flag: Some(3),
+            ..Default::default()
+        };
+        apply(&header, &mut hdr, false).unwrap();
+        assert_eq!(&hdr[..8], b"GZXE8P\0\x02");
+        assert_eq!(&hdr[0x20..0x25], b"Hack\0");
+        assert_eq!(&hdr[0x44C..0x450], &[0, 0, 0, 3]);
+
+        for invalid in [
+            Header {
+                game_code: Some("zx".to_owned()),
+                ..Default::default()
+            },
+            Header {
+                region_code: Some("EU".to_owned()),
+                ..Default::default()
+            },
+            Header {
+                title: Some("ゼルダ".to_owned()),
+                ..Default::default()
+            },
+            Header {
+                title: Some("x".repeat(WII_TITLE_LEN)),
+                ..Default::default()
+            },
+        ] {
+            assert!(apply(&invalid, &mut hdr, true).is_err());
+        }
+
+        hdr[3] = b'J';
+        let japanese = Header {
+            title: Some("ゼルダ".to_owned()),
+            ..Default::default()
+        };
+        apply(&japanese, &mut hdr, false).unwrap();
+        assert_eq!(&hdr[0x20..0x27], b"\x83[\x83\x8B\x83_\0");
+    }
+
+    #[test]
+    fn wii_title_metadata_and_region_are_written() {
+        use crate::config::AgeRatings;
+        use crate::iso::disc::{PartHeader, PartitionType, TitleMetaData, WiiPartitions};
+
+        let mut disc = WiiDisc {
+            disc_header: Default::default(),
+            disc_region: Default::default(),
+            partitions: WiiPartitions {
+                data_idx: 0,
+                part_info: Default::default(),
+                partitions: vec![WiiPartition {
+                    part_type: PartitionType::Data,
+                    part_offset: 0x50000,
+                    header: PartHeader::default(),
+                    tmd: TitleMetaData::default(),
+                    cert: Box::new([]),
+                }],
+            },
+        };
+        let original = disc.clone();
+        let wii = Wii {
+            ios: Some(58),
+            title_id: Some("00010000525A4445".to_owned()),
+            region: Some(WiiRegion::Free),
+            age_ratings: AgeRatings {
+                pegi: Some(7),
+                ..Default::default()
+            },
+        };
+        apply_wii_config(&wii, &mut disc).unwrap();
+        let part = &disc.partitions.partitions[0];
+        assert_eq!(part.tmd.system_version, 0x0000_0001_0000_003A);
+        assert_eq!(&part.header.ticket.title_id, b"\0\x01\0\0RZDE");
+        assert_eq!(part.tmd.title_id, part.header.ticket.title_id);
+        let mut region = [0u8; 0x20];
+        disc.disc_region.compose_into(&mut region);
+        assert_eq!(&region[..4], &[0, 0, 0, 3]);
+        assert_eq!(region[0x14], 7);
+        assert!(!same_identity(&original, &disc));
+
+        let invalid = Wii {
+            title_id: Some("RZDE".to_owned()),
+            ..Default::default()
+        };
+        assert!(apply_wii_config(&invalid, &mut disc).is_err());
+    }
+}
@@ -3,7 +3,7 @@ use async_std::fs::read;
 use async_std::io::{prelude::*, Read as AsyncRead, Seek as AsyncSeek};
 use eyre::Context;
 use futures::AsyncWrite;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 #[cfg(not(target_os = "unknown"))]
 use std::{
     fs::File as StdFile,
@@ -21,15 +21,25 @@ use crate::patch::banner::Banner;
 use crate::config::Config;
 
 use crate::patch::dol::DolFile;
-use crate::iso::write::DiscWriter;
-use crate::vfs::{self, Directory, GeckoFS};
+use crate::iso::write::{DiscWriter, WiiImagePatcher};
+use crate::vfs::glob::Glob;
+use crate::vfs::overlay::{Layer, Overlay};
+use crate::vfs::placement::Placement;
+use crate::vfs::{self, GeckoFS, Layout, NodeType, Padding};
 #[cfg(feature = "progress")]
 use crate::UPDATER;
 use crate::{patch::{framework_map, linker}, warn};
 
-use super::{disc::DiscType, read::DiscReader};
+use super::{
+    disc::{DiscType, WiiDisc},
+    read::DiscReader,
+};
 
 mod fs_source;
+mod header;
+mod plan;
+
+pub use plan::{DolSection, Plan};
 
 pub trait Builder {
     type Error;
@@ -44,6 +54,11 @@ pub struct IsoBuilder<R1, R2, W> {
     gfs: GeckoFS<R2>,
     reader: DiscReader<R2>,
     writer: W,
+    original_dol: Option<DolFile>,
+    previous_image: Option<DiscReader<R2>>,
+    previous_layout: Option<Layout>,
+    layout: Option<Layout>,
+    image_len: Option<u64>,
 }
 
 impl<RConfig, RDisc, W> IsoBuilder<RConfig, RDisc, W> {
@@ -54,7 +69,7 @@ impl<RConfig, RDisc, W> IsoBuilder<RConfig, RDisc, W> {
         reader: DiscReader<RDisc>,
         writer: W,
     ) -> Self {
-        Self::internal_new(config, FSSource::Zip(Box::new(zip)), gfs, reader, writer)
+        Self::internal_new(config, FSSource::with_zip(zip), gfs, reader, writer)
     }
 
     #[cfg(not(target_os = "unknown"))]
@@ -87,7 +102,52 @@ impl<RConfig, RDisc, W> IsoBuilder<RConfig, RDisc, W> {
             gfs,
             reader,
             writer,
+            original_dol: None,
+            previous_image: None,
+            previous_layout: None,
+            layout: None,
+            image_len: None,
+        }
+    }
+
+    /// Path of the patch on the host, so its replacement files are streamed from it while the
+    /// disc is written instead of being loaded in memory
+    pub fn with_patch_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        if let FSSource::Zip(_, zip_path) = &mut self.fs {
+            *zip_path = Some(path.as_ref().to_path_buf());
         }
+        self
+    }
+
+    /// Uses an already parsed DOL of the original game instead of parsing it from the disc
+    pub fn with_original_dol(mut self, dol: DolFile) -> Self {
+        self.original_dol = Some(dol);
+        self
+    }
+
+    /// Patches the previous output in place instead of writing the whole image again, when no
+    /// file moved. The writer has to write into the image read by `image`.
+    pub fn with_previous_image(mut self, image: DiscReader<RDisc>) -> Self {
+        self.previous_image = Some(image);
+        self
+    }
+
+    /// Layout of the previous output, as returned by [`IsoBuilder::layout`], to know exactly
+    /// which files changed. Rewriting a Wii image in place also takes the previous image.
+    pub fn with_previous_layout(mut self, layout: Layout) -> Self {
+        self.previous_layout = Some(layout);
+        self
+    }
+
+    /// Layout of the image written by the last build
+    pub fn layout(&self) -> Option<&Layout> {
+        self.layout.as_ref()
+    }
+
+    /// Size of the image written by the last build, to truncate a larger previous image it
+    /// was written over
+    pub fn image_len(&self) -> Option<u64> {
+        self.image_len
     }
 
     pub fn config_mut(&mut self) -> &mut Config {
@@ -99,10 +159,10 @@ impl<RConfig, RDisc, W> IsoBuilder<RConfig, RDisc, W> {
     }
 }
 
-fn add_file_to_iso<R: AsyncRead + AsyncSeek + 'static, R2: std::io::Read + std::io::Seek, P: AsRef<Path>>(
-    iso_path: &String,
+fn add_file_to_layer<R, R2: std::io::Read + std::io::Seek, P: AsRef<Path>>(
+    iso_path: &str,
     actual_path: &P,
-    iso: &mut Directory<R>,
+    layer: &mut Layer<R>,
     files: &mut FSSource<R2>,
 ) -> eyre::Result<()> {
     if files.is_file(actual_path) {
@@ -110,36 +170,32 @@ fn add_file_to_iso<R: AsyncRead + AsyncSeek + 'static, R2: std::io::Read + std::
             updater.set_message(iso_path.to_string())?;
         }
 
-        let mut file = files.get_file(actual_path)?;
-        if let Some(f) = iso.resolve_node_mut(iso_path).and_then(|n| n.as_file_mut()) {
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)?;
-            f.set_data(buffer.into_boxed_slice())?;
-        } else {
-            let mut p = PathBuf::from(iso_path);
-            let file_name = p
-                .file_name()
-                .expect("File name is invalid")
-                .to_string_lossy()
-                .into_owned();
-            p.pop();
-            let dir = iso.mkdirs(p)?;
-            let mut data = Vec::new();
-            file.read_to_end(&mut data)?;
-            dir.add_file(vfs::File::new(vfs::FileDataSource::Box {
-                data: data.into_boxed_slice(),
-                name: file_name,
-            }));
-        }
+        let file_name = Path::new(iso_path)
+            .file_name()
+            .expect("File name is invalid")
+            .to_string_lossy()
+            .into_owned();
+        let source = match files.lazy_source(actual_path, file_name.clone())? {
+            Some(source) => source,
+            None => {
+                let mut data = Vec::new();
+                files.get_file(actual_path)?.read_to_end(&mut data)?;
+                vfs::FileDataSource::Box {
+                    data: data.into_boxed_slice(),
+                    name: file_name,
+                }
+            }
+        };
+        layer.add_file(iso_path, vfs::File::new(source))?;
     }
     Ok(())
 }
 
 #[cfg(not(target_os = "unknown"))]
-fn add_node_to_iso<R: AsyncRead + AsyncSeek + 'static, R2: Read + Seek>(
-    iso_path: &String,
+fn add_node_to_layer<R, R2: Read + Seek>(
+    iso_path: &str,
     actual_path: &PathBuf,
-    iso: &mut Directory<R>,
+    layer: &mut Layer<R>,
     files: &mut FSSource<R2>,
 ) -> eyre::Result<()> {
     if files.is_dir(actual_path) {
@@ -148,38 +204,329 @@ fn add_node_to_iso<R: AsyncRead + AsyncSeek + 'static, R2: Read + Seek>(
             let iso_path = String::from(iso_path) + &String::from('/') + &name;
             let mut actual_path = actual_path.clone();
             actual_path.push(name);
-            add_node_to_iso(&iso_path, &actual_path, iso, files)?;
+            add_node_to_layer(&iso_path, &actual_path, layer, files)?;
         }
     } else {
-        add_file_to_iso(iso_path, actual_path, iso, files)?;
+        add_file_to_layer(iso_path, actual_path, layer, files)?;
     }
     Ok(())
 }
 
-fn patch_instructions(
-    mut original: DolFile,
-    intermediate: Option<DolFile>,
-    instructions: &[Instruction],
-) -> eyre::Result<Vec<u8>> {
-    if let Some(intermediate) = intermediate {
-        original.append(intermediate);
+/// Applies `[remove]` and `[rename]` to the disc
+fn restructure_iso<R>(config: &Config, iso: &mut GeckoFS<R>) -> eyre::Result<()>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Clone + 'static,
+{
+    for pattern in &config.remove.paths {
+        remove_from_iso(pattern, iso)?;
     }
-    original
-        .patch(instructions)
-        .context("Couldn't patch the DOL")?;
+    for (from, to) in &config.rename {
+        crate::info!("Moving {} to {}", from, to);
+        iso.move_node(from, to)
+            .context(format!("Couldn't rename \"{}\" to \"{}\"", from, to))?;
+    }
+    Ok(())
+}
 
-    Ok(original.to_bytes())
+/// Converts `[placement]` into the rules the FileSystem is serialized with
+fn placement_from_config(config: &Config) -> eyre::Result<Placement> {
+    let mut placement = Placement {
+        order: config
+            .placement
+            .order
+            .iter()
+            .map(|pattern| Glob::new(pattern))
+            .collect::<eyre::Result<_>>()?,
+        preserve_offsets: config.placement.preserve_offsets,
+        ..Default::default()
+    };
+    for (pattern, alignment) in &config.placement.align {
+        placement.add_alignment(pattern, *alignment)?;
+    }
+    Ok(placement)
 }
 
-impl<RConfig, RDisc, W> Builder for IsoBuilder<RConfig, RDisc, W>
+/// Maps the files matching `pattern` in the source to the disc path filled with their captures
+fn expand_host_pattern<R2: Read + Seek>(
+    iso_path: &str,
+    pattern: &str,
+    source: &FSSource<R2>,
+) -> eyre::Result<Vec<(String, PathBuf)>> {
+    let glob = Glob::new(pattern)?;
+    let mut resolved = Vec::new();
+    for name in source.list_files(glob.base())? {
+        let path = match glob.base() {
+            "" => name,
+            base => format!("{}/{}", base, name),
+        };
+        if let Some(captures) = glob.captures(&path) {
+            resolved.push((Glob::substitute(iso_path, &captures)?, PathBuf::from(path)));
+        }
+    }
+    Ok(resolved)
+}
+
+/// Expands the patterns of `[files]` into the files they map, sorted by disc path.
+///
+/// With wildcards on the host side, the matching host files are mapped to the disc path
+/// filled with their captures. With wildcards on the disc side only, the matching files of
+/// the disc are replaced by the host path filled with their captures.
+fn resolve_file_patterns<R, R2: Read + Seek>(
+    files: &HashMap<String, PathBuf>,
+    iso: &GeckoFS<R>,
+    source: &FSSource<R2>,
+) -> eyre::Result<Vec<(String, PathBuf)>>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Clone + 'static,
+{
+    let mut resolved = Vec::new();
+    for (iso_path, actual_path) in files {
+        let pattern = actual_path.to_string_lossy();
+        let mapped = if Glob::is_glob(&pattern) {
+            expand_host_pattern(iso_path, &pattern, source)?
+        } else if Glob::is_glob(iso_path) {
+            let glob = Glob::new(iso_path)?;
+            let mut mapped = Vec::new();
+            for path in iso.root().paths() {
+                let is_file = iso
+                    .root()
+                    .resolve_node(&path)
+                    .is_some_and(|node| node.get_type() == NodeType::File);
+                if let Some(captures) = glob.captures(&path).filter(|_| is_file) {
+                    let actual_path = PathBuf::from(Glob::substitute(&pattern, &captures)?);
+                    mapped.push((path, actual_path));
+                }
+            }
+            mapped
+        } else {
+            vec![(iso_path.clone(), actual_path.clone())]
+        };
+        if mapped.is_empty() {
+            warn!("\"{}\" = \"{}\" doesn't match any file", iso_path, pattern);
+        }
+        resolved.extend(mapped);
+    }
+    resolved.sort();
+    Ok(resolved)
+}
+
+/// Removes the file or directory at `pattern`, or every one matching it when it's a glob
+fn remove_from_iso<R>(pattern: &str, iso: &mut GeckoFS<R>) -> eyre::Result<()>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Clone + 'static,
+{
+    if !Glob::is_glob(pattern) {
+        crate::info!("Removing {}", pattern);
+        iso.remove(pattern)
+            .context(format!("Couldn't remove \"{}\"", pattern))?;
+        return Ok(());
+    }
+
+    let glob = Glob::new(pattern)?;
+    let matches = iso
+        .root()
+        .paths()
+        .into_iter()
+        .filter(|path| glob.is_match(path))
+        .collect::<Vec<_>>();
+    if matches.is_empty() {
+        warn!("\"{}\" doesn't match any file to remove", pattern);
+    }
+    for path in matches {
+        // Directories come before their content, which is removed along with them
+        if iso.root().resolve_node(&path).is_some() {
+            crate::info!("Removing {}", path);
+            iso.remove(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds every file below `dir` to the layer, at the same path under `iso_path`
+fn add_tree_to_layer<R, R2: Read + Seek>(
+    iso_path: &str,
+    dir: &Path,
+    layer: &mut Layer<R>,
+    files: &mut FSSource<R2>,
+) -> eyre::Result<()> {
+    for name in files.list_files(dir)? {
+        let actual_path = dir.join(&name);
+        let iso_path = match iso_path.trim_end_matches('/') {
+            "" => name,
+            iso_path => format!("{}/{}", iso_path, name),
+        };
+        add_file_to_layer(&iso_path, &actual_path, layer, files)?;
+    }
+    Ok(())
+}
+
+/// Adds the files of a zipped mod to the layer
+fn add_zip_to_layer<R, R2: Read + Seek>(
+    path: &Path,
+    layer: &mut Layer<R>,
+    zip: &mut FSSource<R2>,
+) -> eyre::Result<()> {
+    if zip.is_file("RomHack.toml") {
+        let mut config = String::new();
+        zip.get_file("RomHack.toml")?.read_to_string(&mut config)?;
+        let config: Config = toml::from_str(&config)
+            .context(format!("Couldn't parse the RomHack.toml of {:?}", path))?;
+        for (iso_path, actual_path) in &config.files {
+            if zip.is_file(actual_path) {
+                add_file_to_layer(iso_path, actual_path, layer, zip)?;
+            } else {
+                add_tree_to_layer(iso_path, actual_path, layer, zip)?;
+            }
+        }
+    } else {
+        add_tree_to_layer("", Path::new(""), layer, zip)?;
+    }
+    Ok(())
+}
+
+/// Loads a mod as a layer. Folders are laid out like the disc, while zips are either laid out
+/// like the disc or patches with their own RomHack.toml, whose `[files]` are used.
+fn load_overlay<R, R2: Read + Seek>(
+    path: &Path,
+    files: &mut FSSource<R2>,
+) -> eyre::Result<Layer<R>> {
+    let mut layer = Layer::new(path.display().to_string());
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("zip")) {
+        if let Some(host_path) = files.host_path(path) {
+            let zip = std::fs::File::open(&host_path)
+                .context(format!("Couldn't open the overlay {:?}", path))?;
+            let mut zip = FSSource::with_host_zip(ZipArchive::new(zip)?, host_path);
+            add_zip_to_layer(path, &mut layer, &mut zip)?;
+        } else {
+            let mut data = Vec::new();
+            files
+                .get_file(path)
+                .context(format!("Couldn't open the overlay {:?}", path))?
+                .read_to_end(&mut data)?;
+            let mut zip = FSSource::with_zip(ZipArchive::new(std::io::Cursor::new(data))?);
+            add_zip_to_layer(path, &mut layer, &mut zip)?;
+        }
+    } else {
+        add_tree_to_layer("", path, &mut layer, files)
+            .context(format!("Couldn't read the overlay {:?}", path))?;
+    }
+    Ok(layer)
+}
+
+impl<RConfig, RDisc, W> IsoBuilder<RConfig, RDisc, W>
 where
-    RConfig: Read + Seek,
     RDisc: AsyncRead + AsyncSeek + Clone + Unpin + 'static,
     W: AsyncWrite + AsyncSeek + Clone + Unpin,
 {
-    type Error = eyre::Report;
+    /// Rewrites the parts of the previous output that changed, returning `None` when it takes
+    /// a full rebuild
+    async fn rewrite_in_place(&mut self) -> eyre::Result<Option<(Layout, u64)>> {
+        let disc_type = self.reader.get_type();
+        if self
+            .previous_image
+            .as_ref()
+            .is_some_and(|image| image.get_type() != disc_type)
+        {
+            return Ok(None);
+        }
+        let previous_layout = match (self.previous_layout.take(), &mut self.previous_image) {
+            (Some(layout), _) => layout,
+            (None, Some(image)) => Layout::read(image).await?,
+            (None, None) => return Ok(None),
+        };
+        if disc_type == DiscType::Wii && self.previous_image.is_none() {
+            return Ok(None);
+        }
+        let disc_info = self.disc_info().await?;
+        if let (Some(DiscReader::Wii(image)), Some(disc)) = (&self.previous_image, &disc_info) {
+            if !header::same_identity(&image.disc, disc) {
+                crate::info!("The disc's identity changed, rewriting the whole image");
+                return Ok(None);
+            }
+        }
 
-    async fn build(&mut self) -> eyre::Result<()> {
+        let Some((layout, changes)) = self
+            .gfs
+            .changes(
+                disc_type == DiscType::Wii,
+                &previous_layout,
+                self.previous_image.as_mut(),
+            )
+            .await?
+        else {
+            crate::info!("Files moved since the previous build, rewriting the whole image");
+            return Ok(None);
+        };
+
+        crate::info!("Rewriting the changed files only");
+        let image_len = match self.previous_image.take() {
+            Some(DiscReader::Wii(image)) => {
+                let mut patcher = WiiImagePatcher::new(image, self.writer.clone()).await?;
+                GeckoFS::write_changes_wii(&mut patcher, changes).await?;
+                let image_len = patcher.image_len();
+                patcher.finish().await?;
+                image_len
+            }
+            _ => {
+                GeckoFS::write_changes(&mut self.writer.clone(), changes).await?;
+                layout.len()
+            }
+        };
+        Ok(Some((layout, image_len)))
+    }
+
+    /// The Wii disc's information with `[header]` and `[wii]` applied
+    async fn disc_info(&mut self) -> eyre::Result<Option<WiiDisc>> {
+        let Some(mut disc) = self.reader.get_disc_info() else {
+            return Ok(None);
+        };
+        let mut hdr = Vec::new();
+        self.gfs
+            .sys_mut()
+            .get_file_mut("iso.hdr")?
+            .read_to_end(&mut hdr)
+            .await?;
+        header::apply_wii(&self.config.header, &hdr, &mut disc)?;
+        header::apply_wii_config(&self.config.wii, &mut disc).context("Invalid [wii]")?;
+        Ok(Some(disc))
+    }
+}
+
+impl<RConfig, RDisc, W> IsoBuilder<RConfig, RDisc, W>
+where
+    RConfig: Read + Seek,
+    RDisc: AsyncRead + AsyncSeek + Clone + Unpin + 'static,
+{
+    /// Lists the files `[files]` replaces once its patterns are resolved, after `[remove]` and
+    /// `[rename]`, without building
+    pub fn resolve_files(&self) -> eyre::Result<Vec<(String, PathBuf)>> {
+        let mut disc = self.gfs.deep_clone()?;
+        restructure_iso(&self.config, &mut disc)?;
+        resolve_file_patterns(&self.config.files, &disc, &self.fs)
+    }
+
+    /// Does everything [`Builder::build`] does but write the image, and reports what it would
+    /// write. The symbol maps and tables aren't written either.
+    pub async fn plan(&mut self) -> eyre::Result<Plan> {
+        let (dol, symbols) = self.patch_disc(true).await?;
+        let is_wii = self.reader.get_type() == DiscType::Wii;
+        let layout = self.gfs.layout(is_wii).await?;
+        Ok(Plan {
+            fst_offset: layout.fst_offset(),
+            fst_len: layout.fst_len(),
+            files: layout.files(is_wii)?,
+            dol_sections: plan::dol_sections(&dol),
+            symbols,
+            image_len: layout.image_len(is_wii),
+        })
+    }
+
+    /// Replaces the files of the disc and patches its DOL, returning the patched DOL and the
+    /// Rom Hack's linked symbols
+    async fn patch_disc(
+        &mut self,
+        dry_run: bool,
+    ) -> eyre::Result<(DolFile, BTreeMap<String, u32>)> {
         #[cfg(feature = "progress")]
         if let Ok(mut updater) = UPDATER.lock() {
             updater.set_message("Loading game...".into())?;
@@ -187,19 +534,56 @@ where
 
         let disc = &mut self.gfs;
 
+        restructure_iso(&self.config, disc)?;
+        disc.set_placement(placement_from_config(&self.config)?);
+        match (self.config.build.junk_padding, self.config.build.scrub) {
+            (true, true) => eyre::bail!("A disc can't be both padded with junk and scrubbed"),
+            (true, false) => disc.set_padding(Padding::Junk),
+            (false, true) => disc.set_padding(Padding::Scrub),
+            (false, false) => {}
+        }
+
+        let hdr_file = disc.sys_mut().get_file_mut("iso.hdr")?;
+        let mut hdr = Vec::new();
+        hdr_file.read_to_end(&mut hdr).await?;
+        let original_hdr = hdr.clone();
+        header::apply(
+            &self.config.header,
+            &mut hdr,
+            self.reader.get_type() == DiscType::Wii,
+        )
+        .context("Invalid [header]")?;
+        if hdr != original_hdr {
+            hdr_file.set_data(hdr.into())?;
+        }
+
         #[cfg(feature = "progress")]
         if let Ok(mut updater) = UPDATER.lock() {
             updater.set_message("".into())?;
             updater.set_title("Replacing files...".into())?;
         }
 
-        for (iso_path, actual_path) in &self.config.files {
+        let mut overlay = Overlay::new();
+        for path in &self.config.src.overlays {
+            crate::info!("Loading overlay {}", path.display());
+            overlay.push(load_overlay(path, &mut self.fs)?);
+        }
+
+        let mut replaced = Layer::new("[files]");
+        for (iso_path, actual_path) in resolve_file_patterns(&self.config.files, disc, &self.fs)? {
             #[cfg(target_os = "unknown")]
-            add_file_to_iso(iso_path, actual_path, disc.root_mut(), &mut self.fs)?;
+            add_file_to_layer(&iso_path, &actual_path, &mut replaced, &mut self.fs)?;
             #[cfg(not(target_os = "unknown"))]
-            add_node_to_iso(iso_path, actual_path, disc.root_mut(), &mut self.fs)?;
+            add_node_to_layer(&iso_path, &actual_path, &mut replaced, &mut self.fs)?;
         }
+        overlay.push(replaced);
 
+        for conflict in overlay.conflicts() {
+            warn!("{}", conflict);
+        }
+        overlay.apply(disc)?;
+
+        let mut original_map = None;
         let original_symbols = if let Some(framework_map) = self
             .config
             .src
@@ -214,7 +598,11 @@ where
                 updater.set_title("Parsing symbol map...".into())?;
             }
 
-            framework_map::parse(framework_map).await?
+            let mut buf = Vec::new();
+            framework_map.read_to_end(&mut buf).await?;
+            let (format, symbols) = framework_map::parse_map(&buf)?;
+            original_map = Some((format, buf));
+            symbols
         } else {
             #[cfg(feature = "progress")]
             if let Ok(mut updater) = UPDATER.lock() {
@@ -231,7 +619,7 @@ where
         }
 
         let mut libs_to_link;
-        let linked = if let Some(link) = &self.config.link {
+        let mut linked = if let Some(link) = &self.config.link {
             libs_to_link = Vec::with_capacity(link.libs.len() + 1);
 
             for lib_path in &link.libs {
@@ -240,7 +628,7 @@ where
                     self.fs
                         .get_file(lib_path)
                         .context(format!(
-                            "Couldn't load \"{}\". Did you build the project correctly?",
+                            "Couldn't load the library \"{}\"",
                             lib_path.display()
                         ))?
                         .read_to_end(&mut buf)?;
@@ -267,12 +655,34 @@ where
             None
         };
 
+        let original = match self.original_dol.take() {
+            Some(original) => original,
+            None => {
+                DolFile::parse(
+                    disc.sys_mut()
+                        .get_file_mut("Start.dol")
+                        .context("Dol file not found")?,
+                )
+                .await?
+            }
+        };
+
         #[cfg(feature = "progress")]
         if let Ok(mut updater) = UPDATER.lock() {
             updater.set_message("".into())?;
             updater.set_title("Creating symbol map...".into())?;
         }
 
+        if !dry_run {
+            framework_map::create(
+                &self.config,
+                original_map.as_ref().map(|(format, buf)| (*format, &buf[..])),
+                &original_symbols,
+                &original,
+                linked.as_ref().map_or(&[], |l| &l.sections),
+            )?;
+        }
+
         let instructions = if let Some(patch) = self.config.src.patch.take() {
             #[cfg(feature = "progress")]
             if let Ok(mut updater) = UPDATER.lock() {
@@ -289,7 +699,11 @@ where
 
             let lines = &buf.lines().collect::<Vec<_>>();
 
-            let mut assembler = Assembler::new(linked.as_ref().map(|l| l.symbol_table.clone()), &original_symbols);
+            let mut assembler = Assembler::new(
+                linked.as_ref().map(|l| l.symbol_table.clone()),
+                &original_symbols,
+                Some(&original),
+            );
             assembler
                 .assemble_all_lines(lines)
                 .context("Couldn't assemble the patch file lines")?
@@ -297,7 +711,16 @@ where
             Vec::new()
         };
 
-        {
+        let symbols = linked
+            .as_ref()
+            .map(|l| {
+                l.symbol_table
+                    .iter()
+                    .map(|(name, address)| (name.to_string(), *address))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let dol = {
             #[cfg(feature = "progress")]
             if let Ok(mut updater) = UPDATER.lock() {
                 updater.set_message("".into())?;
@@ -309,13 +732,28 @@ where
                 .get_file_mut("Start.dol")
                 .context("Dol file not found")?;
 
-            let original = DolFile::parse(main_dol).await?;
-            main_dol.set_data(
-                patch_instructions(original, linked.map(|l| l.dol), &instructions)
-                    .context("Couldn't patch the game")?
-                    .into(),
-            )?;
-        }
+            let intermediate = linked.as_mut().map(|l| std::mem::take(&mut l.dol));
+            let dol = patch_instructions(original, intermediate, &instructions)
+                .context("Couldn't patch the game")?;
+            main_dol.set_data(dol.to_bytes()?.into())?;
+
+            if !dry_run {
+                let declared = original_map
+                    .as_ref()
+                    .map_or_else(HashMap::new, |(_, buf)| framework_map::declarations(buf));
+                framework_map::export(
+                    &self.config,
+                    &framework_map::symbol_table(
+                        &original_symbols,
+                        &declared,
+                        linked.as_ref(),
+                        &dol,
+                    ),
+                )
+                .context("Couldn't export the symbol tables")?;
+            }
+            dol
+        };
 
         if self.reader.get_type() == DiscType::Gamecube {
             #[cfg(feature = "progress")]
@@ -369,14 +807,68 @@ where
             }
         }
 
-        // Finalize disc and write it back into a file
+        Ok((dol, symbols))
+    }
+}
+
+fn patch_instructions(
+    mut original: DolFile,
+    intermediate: Option<DolFile>,
+    instructions: &[Instruction],
+) -> eyre::Result<DolFile> {
+    if let Some(intermediate) = intermediate {
+        original
+            .append(intermediate)
+            .context("Couldn't add the Rom Hack's sections to the DOL")?;
+    }
+    original
+        .patch(instructions)
+        .context("Couldn't patch the DOL")?;
+
+    Ok(original)
+}
+
+impl<RConfig, RDisc, W> Builder for IsoBuilder<RConfig, RDisc, W>
+where
+    RConfig: Read + Seek,
+    RDisc: AsyncRead + AsyncSeek + Clone + Unpin + 'static,
+    W: AsyncWrite + AsyncSeek + Clone + Unpin,
+{
+    type Error = eyre::Report;
+
+    async fn build(&mut self) -> eyre::Result<()> {
+        self.patch_disc(false).await?;
 
-        let out: DiscWriter<W> = DiscWriter::from_reader(self.writer.clone(), &self.reader);
-        std::pin::pin!(out.clone()).init().await?;
-        // let out = DiscWriter::Gamecube(self.writer.clone());
+        // Finalize disc and write it back into a file
 
-        let mut out = std::pin::pin!(out);
-        disc.serialize(&mut out).await?;
+        let (layout, image_len) = match self.rewrite_in_place().await? {
+            Some(rewritten) => rewritten,
+            None => {
+                let out: DiscWriter<W> =
+                    DiscWriter::new(self.writer.clone(), self.disc_info().await?);
+                // let out = DiscWriter::Gamecube(self.writer.clone());
+
+                let mut out = std::pin::pin!(out);
+                let layout = self.gfs.serialize_with_layout(&mut out).await?;
+                let image_len = out.image_len().await.unwrap_or(layout.len());
+                // Scrubbed discs aren't junk-padded, they only differ from the zero-padded
+                // build by the groups left unencrypted
+                if self.config.build.scrub {
+                    match out.get_type() {
+                        DiscType::Gamecube => crate::info!(
+                            "Scrubbing left the disc as is, a GameCube disc's unused space is zeros already"
+                        ),
+                        DiscType::Wii => crate::info!(
+                            "Scrubbing left {:.1} MiB of the partition as unencrypted zeros",
+                            out.scrubbed_len().await as f64 / (1024.0 * 1024.0)
+                        ),
+                    }
+                }
+                (layout, image_len)
+            }
+        };
+        self.layout = Some(layout);
+        self.image_len = Some(image_len);
 
         #[cfg(feature = "progress")]
         if let Ok(mut updater) = UPDATER.lock() {
@@ -487,10 +979,52 @@ impl Builder for PatchBuilder {
         let mut new_map = HashMap::new();
         let mut index = 0;
         for (iso_path, actual_path) in config.files.iter() {
-            add_entry_to_zip(&mut index, iso_path, actual_path, &mut zip, &mut new_map)?;
+            let pattern = actual_path.to_string_lossy();
+            if Glob::is_glob(&pattern) {
+                let source = FSSource::<StdFile>::with_fs("");
+                for (iso_path, actual_path) in expand_host_pattern(iso_path, &pattern, &source)? {
+                    add_entry_to_zip(&mut index, &iso_path, &actual_path, &mut zip, &mut new_map)?;
+                }
+            } else if Glob::is_glob(iso_path) && pattern.contains('$') {
+                // The disc's files are only known when the patch is applied
+                eyre::bail!(
+                    "\"{}\" = \"{}\" can't be stored in a patch, put the wildcards on the host side",
+                    iso_path,
+                    pattern
+                );
+            } else {
+                add_entry_to_zip(&mut index, iso_path, actual_path, &mut zip, &mut new_map)?;
+            }
         }
         config.files = new_map;
 
+        let mut overlays = Vec::new();
+        for (index, path) in config.src.overlays.iter().enumerate() {
+            crate::info!("Storing overlay {:?}", path);
+
+            #[cfg(feature = "progress")]
+            if let Ok(mut updater) = UPDATER.lock() {
+                updater.set_message(format!("Storing overlay {:?}...", path))?;
+            }
+
+            if path.is_file() {
+                let zip_path = format!("overlay{}.zip", index);
+                write_file_to_zip(&mut zip, zip_path.clone(), &read(path).await?)?;
+                overlays.push(PathBuf::from(zip_path));
+            } else {
+                let zip_path = format!("overlay{}", index);
+                for name in FSSource::<StdFile>::with_fs(path).list_files("")? {
+                    write_file_to_zip(
+                        &mut zip,
+                        format!("{}/{}", zip_path, name),
+                        &read(path.join(&name)).await?,
+                    )?;
+                }
+                overlays.push(PathBuf::from(zip_path));
+            }
+        }
+        config.src.overlays = overlays;
+
         if let Some(link) = &mut config.link {
             crate::info!("Storing libraries");
     
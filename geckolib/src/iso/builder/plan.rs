@@ -0,0 +1,91 @@
+//! What a build writes, reported without writing it.
+//!
+//! Only `romhack build --dry-run` reports it, the GUI's progress doesn't use it.
+
+use crate::patch::dol::DolFile;
+use crate::vfs::PlacedFile;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A section of the patched DOL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DolSection {
+    /// `.text0`…, `.data0`… or `.bss`
+    pub name: String,
+    pub address: u32,
+    pub len: u32,
+}
+
+/// What a build writes, see [`IsoBuilder::plan`](super::IsoBuilder::plan)
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub fst_offset: u64,
+    pub fst_len: u64,
+    /// The files of the new FST, in its order
+    pub files: Vec<PlacedFile>,
+    pub dol_sections: Vec<DolSection>,
+    /// The Rom Hack's linked symbols and their addresses
+    pub symbols: BTreeMap<String, u32>,
+    /// Size of the written image
+    pub image_len: u64,
+}
+
+pub(super) fn dol_sections(dol: &DolFile) -> Vec<DolSection> {
+    let sections = |kind: &str, sections: &[crate::patch::dol::Section]| {
+        sections
+            .iter()
+            .enumerate()
+            .map(|(i, section)| DolSection {
+                name: format!(".{}{}", kind, i),
+                address: section.address,
+                len: section.data.len() as u32,
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut all = sections("text", &dol.text_sections);
+    all.extend(sections("data", &dol.data_sections));
+    all.push(DolSection {
+        name: ".bss".to_owned(),
+        address: dol.bss_address,
+        len: dol.bss_size,
+    });
+    all
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "FST: {:#010X} ({:#X} bytes)",
+            self.fst_offset, self.fst_len
+        )?;
+        writeln!(f, "Files:")?;
+        for file in &self.files {
+            writeln!(
+                f,
+                "  {:#010X} {:>#10X} {}",
+                file.offset, file.len, file.path
+            )?;
+        }
+        writeln!(f, "DOL sections:")?;
+        for section in &self.dol_sections {
+            writeln!(
+                f,
+                "  {:<7} {:#010X} {:>#10X}",
+                section.name, section.address, section.len
+            )?;
+        }
+        if !self.symbols.is_empty() {
+            writeln!(f, "Linked symbols:")?;
+            for (name, address) in &self.symbols {
+                writeln!(f, "  {:#010X} {}", address, name)?;
+            }
+        }
+        write!(
+            f,
+            "Image size: {:#X} bytes ({:.1} MiB)",
+            self.image_len,
+            self.image_len as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
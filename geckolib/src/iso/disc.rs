@@ -148,25 +148,27 @@ pub fn disc_set_header(buffer: &mut [u8], dh: &WiiDiscHeader) {
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct WiiDiscRegionAgeRating {
-    jp: u8,
-    us: u8,
+    pub jp: u8,
+    pub us: u8,
     unknown1: u8,
-    de: u8,
-    pegi: u8,
-    fi: u8,
-    pt: u8,
-    gb: u8,
-    au: u8,
-    kr: u8,
+    pub de: u8,
+    pub pegi: u8,
+    pub fi: u8,
+    pub pt: u8,
+    pub gb: u8,
+    pub au: u8,
+    pub kr: u8,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum WiiDiscRegions {
     #[default]
-    NTSCJ,
-    NTSCU,
-    PAL,
-    KOR,
+    NTSCJ = 0,
+    NTSCU = 1,
+    PAL = 2,
+    /// Not checked by the console
+    Free = 3,
+    KOR = 4,
 }
 
 impl From<u32> for WiiDiscRegions {
@@ -175,7 +177,8 @@ impl From<u32> for WiiDiscRegions {
             0 => WiiDiscRegions::NTSCJ,
             1 => WiiDiscRegions::NTSCU,
             2 => WiiDiscRegions::PAL,
-            3 => WiiDiscRegions::KOR,
+            3 => WiiDiscRegions::Free,
+            4 => WiiDiscRegions::KOR,
             _ => WiiDiscRegions::default(),
         }
     }
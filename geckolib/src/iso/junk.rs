@@ -0,0 +1,192 @@
+//! The pseudo-random data retail discs are padded with.
+//!
+//! Nintendo's mastering fills the space no file uses with the output of a lagged Fibonacci
+//! generator, seeded from the game ID, the disc number and the offset of each 0x8000 bytes
+//! block, so it can be generated again. The generator is only checked against a retail disc by
+//! the ignored `junk_matches_a_retail_disc` test, given one in `GECKOLIB_RETAIL_DISC`.
+
+const LFG_K: usize = 521;
+const LFG_J: usize = 32;
+const SEED_SIZE: usize = 17;
+/// The generator is seeded again for each block of this size
+pub const JUNK_BLOCK_SIZE: u64 = 0x8000;
+
+/// Generates the junk data of a disc
+#[derive(Clone)]
+pub struct JunkGenerator {
+    game_id: [u8; 4],
+    disc_number: u8,
+    buffer: [u32; LFG_K],
+    /// Offset of the next byte the buffer gives, if it was seeded for its block
+    cursor: Option<u64>,
+    /// Position in the buffer, in bytes
+    position: usize,
+}
+
+impl std::fmt::Debug for JunkGenerator {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter
+            .debug_struct("JunkGenerator")
+            .field("game_id", &String::from_utf8_lossy(&self.game_id))
+            .field("disc_number", &self.disc_number)
+            .finish()
+    }
+}
+
+impl JunkGenerator {
+    pub fn new(game_id: [u8; 4], disc_number: u8) -> Self {
+        Self {
+            game_id,
+            disc_number,
+            buffer: [0; LFG_K],
+            cursor: None,
+            position: 0,
+        }
+    }
+
+    /// Reads the game ID and the disc number from the disc's header
+    pub fn from_header(header: &[u8]) -> eyre::Result<Self> {
+        if header.len() < 7 {
+            eyre::bail!("The disc's header is too short to hold its game ID");
+        }
+        Ok(Self::new(
+            [header[0], header[1], header[2], header[3]],
+            header[6],
+        ))
+    }
+
+    fn seed(&mut self, block: u32) {
+        let id = self.game_id;
+        let mut n = (u32::from_be_bytes([
+            id[2],
+            id[1],
+            id[3].wrapping_add(id[2]),
+            id[0].wrapping_add(id[1]),
+        ]) ^ self.disc_number as u32)
+            .wrapping_mul(0x260BCD5)
+            ^ block.wrapping_mul(0x1EF29123);
+        for value in &mut self.buffer[..SEED_SIZE] {
+            *value = 0;
+            for _ in 0..32 {
+                n = n.wrapping_mul(0x5D588B65).wrapping_add(1);
+                *value = (*value >> 1) | (n & 0x80000000);
+            }
+        }
+        self.buffer[16] ^= (self.buffer[0] >> 9) ^ (self.buffer[16] << 23);
+        for i in SEED_SIZE..LFG_K {
+            self.buffer[i] =
+                (self.buffer[i - 17] << 23) ^ (self.buffer[i - 16] >> 9) ^ self.buffer[i - 1];
+        }
+        // The output skips 2 bits of the third byte
+        for value in &mut self.buffer {
+            *value = (*value & 0xFF00FFFF) | ((*value >> 2) & 0x00FF0000);
+        }
+        for _ in 0..4 {
+            self.forward();
+        }
+        self.position = 0;
+    }
+
+    fn forward(&mut self) {
+        for i in 0..LFG_J {
+            self.buffer[i] ^= self.buffer[i + LFG_K - LFG_J];
+        }
+        for i in LFG_J..LFG_K {
+            self.buffer[i] ^= self.buffer[i - LFG_J];
+        }
+    }
+
+    fn skip(&mut self, mut count: usize) {
+        while count > 0 {
+            if self.position == LFG_K * 4 {
+                self.forward();
+                self.position = 0;
+            }
+            let step = std::cmp::min(count, LFG_K * 4 - self.position);
+            self.position += step;
+            count -= step;
+        }
+    }
+
+    /// Fills `buf` with the junk found at `offset` of the disc, or of the partition's data
+    /// on Wii
+    pub fn fill(&mut self, mut offset: u64, buf: &mut [u8]) {
+        let mut written = 0;
+        while written < buf.len() {
+            let in_block = offset % JUNK_BLOCK_SIZE;
+            if self.cursor != Some(offset) || in_block == 0 {
+                self.seed((offset / JUNK_BLOCK_SIZE) as u32);
+                self.skip(in_block as usize);
+            }
+            let len = std::cmp::min(buf.len() - written, (JUNK_BLOCK_SIZE - in_block) as usize);
+            for byte in &mut buf[written..written + len] {
+                if self.position == LFG_K * 4 {
+                    self.forward();
+                    self.position = 0;
+                }
+                *byte = self.buffer[self.position / 4].to_be_bytes()[self.position % 4];
+                self.position += 1;
+            }
+            written += len;
+            offset += len as u64;
+            self.cursor = Some(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn junk_depends_only_on_the_offset() {
+        let mut generator = JunkGenerator::new(*b"GALE", 0);
+        let mut whole = vec![0u8; 0x10000];
+        generator.fill(0x38000, &mut whole);
+        assert!(whole.iter().any(|&b| b != 0));
+
+        let mut parts = vec![0u8; 0x10000];
+        let mut other = JunkGenerator::new(*b"GALE", 0);
+        other.fill(0x38000 + 0x1234, &mut parts[0x1234..0x9000]);
+        other.fill(0x38000, &mut parts[..0x1234]);
+        other.fill(0x38000 + 0x9000, &mut parts[0x9000..]);
+        assert_eq!(whole, parts);
+
+        let mut disc_two = vec![0u8; 0x100];
+        JunkGenerator::new(*b"GALE", 1).fill(0x38000, &mut disc_two);
+        assert_ne!(whole[..0x100], disc_two[..]);
+    }
+
+    #[test]
+    #[ignore = "needs a retail GameCube disc, whose path is in GECKOLIB_RETAIL_DISC"]
+    fn junk_matches_a_retail_disc() {
+        use crate::iso::read::DiscReader;
+        use crate::vfs::Layout;
+        use async_std::io::{prelude::SeekExt, ReadExt, SeekFrom};
+
+        let path = std::env::var("GECKOLIB_RETAIL_DISC")
+            .expect("GECKOLIB_RETAIL_DISC should be the path of a retail GameCube disc");
+        async_std::task::block_on(async {
+            let file = async_std::fs::File::open(&path).await.unwrap();
+            let mut reader = DiscReader::new(file).await.unwrap();
+            let mut header = [0u8; 7];
+            reader.seek(SeekFrom::Start(0)).await.unwrap();
+            reader.read_exact(&mut header).await.unwrap();
+            // The disc is junk from the block following its last file on
+            let offset = Layout::read(&mut reader)
+                .await
+                .unwrap()
+                .len()
+                .next_multiple_of(JUNK_BLOCK_SIZE);
+            let mut retail = vec![0u8; 0x1000];
+            reader.seek(SeekFrom::Start(offset)).await.unwrap();
+            reader.read_exact(&mut retail).await.unwrap();
+
+            let mut junk = vec![0u8; 0x1000];
+            JunkGenerator::from_header(&header)
+                .unwrap()
+                .fill(offset, &mut junk);
+            assert_eq!(retail, junk);
+        });
+    }
+}
@@ -4,6 +4,7 @@ use crate::crypto::Unpackable;
 
 pub mod builder;
 pub mod disc;
+pub mod junk;
 pub mod read;
 pub mod write;
 
@@ -26,6 +27,15 @@ pub mod consts {
     pub const DOL_ALIGNMENT: usize = 1 << DOL_ALIGNMENT_BIT;
     pub const FST_ALIGNMENT_BIT: usize = 8;
     pub const FST_ALIGNMENT: usize = 1 << FST_ALIGNMENT_BIT;
+    pub const GC_DISC_SIZE: u64 = 0x57058000;
+    /// Size of a single layer Wii disc
+    pub const WII_DISC_SIZE: u64 = 0x118240000;
+    pub const WII_DUAL_LAYER_DISC_SIZE: u64 = 0x1FB4E0000;
+    /// Where the written Wii image's data partition starts
+    pub const WII_PARTITION_OFFSET: u64 = 0x50000;
+    /// Where the partition's data starts, as its header, TMD and certificates always fit in
+    /// this space
+    pub const WII_PARTITION_DATA_OFFSET: u64 = 0x20000;
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
@@ -240,6 +250,17 @@ impl FstNode {
         }
     }
 
+    pub fn set_relative_file_name(&mut self, name: String) {
+        match self {
+            Self::File {
+                relative_file_name, ..
+            } => *relative_file_name = name,
+            Self::Directory {
+                relative_file_name, ..
+            } => *relative_file_name = name,
+        }
+    }
+
     pub fn get_file_offset(&self) -> Option<u64> {
         match self {
             Self::File { file_offset, .. } => Some(*file_offset),
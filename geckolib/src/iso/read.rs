@@ -197,6 +197,12 @@ where
             get_partitions(&mut pin!(&mut this.reader).as_mut(), &part_info).await?;
         Ok(this)
     }
+
+    /// Reads the encrypted disc at `offset`, bypassing the data partition's decryption
+    pub(crate) async fn read_raw(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        pin!(&mut self.reader).seek(SeekFrom::Start(offset)).await?;
+        pin!(&mut self.reader).read_exact(buf).await
+    }
 }
 
 impl<R> Clone for WiiDiscReader<R>
@@ -1,7 +1,7 @@
 #[cfg(feature = "progress")]
 use crate::UPDATER;
 use async_std::{
-    io::{prelude::*, Seek as AsyncSeek, Write as AsyncWrite},
+    io::{prelude::*, Read as AsyncRead, Seek as AsyncSeek, Write as AsyncWrite},
     sync::Mutex,
 };
 use byteorder::{ByteOrder, BE};
@@ -16,14 +16,14 @@ use std::{sync::Arc, task::Poll};
 use crate::{
     crypto::{aes_encrypt_inplace, consts, AesKey, Unpackable},
     iso::disc::{
-        align_addr, disc_set_header, to_raw_addr, PartHeader, TMDContent, TitleMetaData,
-        WiiDiscHeader,
+        align_addr, disc_set_header, to_raw_addr, to_virtual_addr, PartHeader, TMDContent,
+        TitleMetaData, WiiDiscHeader,
     },
 };
 
 use super::{disc::{
     decrypt_title_key, DiscType, WiiDisc, WiiGroup, WiiPartition, WiiSector, WiiSectorHash,
-}, read::DiscReader};
+}, read::{DiscReader, WiiDiscReader}};
 
 #[derive(Debug, Clone, Default)]
 enum WiiDiscWriterState {
@@ -55,6 +55,25 @@ struct WiiDiscWriterStatus {
     cursor: u64,
     state: WiiDiscWriterState,
     group: Box<WiiGroup>,
+    /// Ranges of the partition's data holding files, when the other groups are scrubbed
+    used: Option<Vec<(u64, u64)>>,
+    /// Number of groups left as unencrypted zeros
+    scrubbed_groups: u64,
+}
+
+impl WiiDiscWriterStatus {
+    /// Whether the group holds no data and is left as zeros
+    fn is_scrubbed(&self, group_idx: usize) -> bool {
+        let group_size = consts::WII_SECTOR_DATA_SIZE as u64 * 64;
+        let (start, end) = (
+            group_idx as u64 * group_size,
+            (group_idx as u64 + 1) * group_size,
+        );
+        self.used.as_ref().is_some_and(|used| {
+            used.iter()
+                .all(|&(used_start, used_end)| used_end <= start || used_start >= end)
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -220,6 +239,8 @@ where
                 state: WiiDiscWriterState::default(),
                 group: Box::new(WiiGroup::default()),
                 hashes: Vec::new(),
+                used: None,
+                scrubbed_groups: 0,
             })),
         }
     }
@@ -250,7 +271,7 @@ where
         let mut buf = [0u8; 0x28];
         BE::write_u32(&mut buf[..], 1);
         BE::write_u32(&mut buf[4..], 0x40020 >> 2);
-        let offset: u64 = 0x50000;
+        let offset: u64 = crate::iso::consts::WII_PARTITION_OFFSET;
         let i = 0;
         let part_type: u32 = disc.partitions.partitions[part_idx].part_type.into();
         crate::debug!("part_type: {}", part_type);
@@ -475,24 +496,33 @@ where
                     == 0
                 {
                     // We are at the start of a group. We can hash and encrypt the group and write it.
-                    crate::trace!("Hashing and encrypting group #{}", group_idx);
                     if status.hashes.len() <= group_idx {
                         status
                             .hashes
                             .resize(group_idx + 1, [0u8; consts::WII_HASH_SIZE]);
                     }
-                    let group_hash = hash_group(&mut status.group);
-                    status.hashes[group_idx].copy_from_slice(&group_hash);
-                    let part_key =
-                        decrypt_title_key(&status.disc.partitions.partitions[part_idx].header.ticket);
-                    if !status.disc.disc_header.disable_disc_encrypt {
-                        encrypt_group(&mut status.group, part_key);
-                    }
+                    let group_buf = if status.is_scrubbed(group_idx) {
+                        // Nothing reads the group, its hash is left empty
+                        crate::trace!("Scrubbing group #{}", group_idx);
+                        status.scrubbed_groups += 1;
+                        vec![0u8; consts::WII_SECTOR_SIZE * 64]
+                    } else {
+                        crate::trace!("Hashing and encrypting group #{}", group_idx);
+                        let group_hash = hash_group(&mut status.group);
+                        status.hashes[group_idx].copy_from_slice(&group_hash);
+                        let part_key = decrypt_title_key(
+                            &status.disc.partitions.partitions[part_idx].header.ticket,
+                        );
+                        if !status.disc.disc_header.disable_disc_encrypt {
+                            encrypt_group(&mut status.group, part_key);
+                        }
+                        status.group.to_vec()
+                    };
 
                     status.state = WiiDiscWriterState::Writing(
                         cursor,
                         group_idx,
-                        status.group.to_vec(),
+                        group_buf,
                         curr_buf.to_vec(),
                     );
                     status.group.reset();
@@ -749,6 +779,147 @@ where
     }
 }
 
+/// Rewrites parts of the data partition of an existing Wii image in place.
+///
+/// Only the groups touched by the new data get re-hashed and re-encrypted. Finishing updates
+/// the H3 table and fake signs the partition again.
+pub struct WiiImagePatcher<R, W> {
+    image: WiiDiscReader<R>,
+    writer: W,
+    part: WiiPartition,
+    hashes: Vec<[u8; consts::WII_HASH_SIZE]>,
+    group: Option<(usize, Box<WiiGroup>)>,
+}
+
+const WII_GROUP_DATA_SIZE: u64 = consts::WII_SECTOR_DATA_SIZE as u64 * 64;
+const WII_GROUP_SIZE: u64 = consts::WII_SECTOR_SIZE as u64 * 64;
+
+impl<R, W> WiiImagePatcher<R, W>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+    W: AsyncWrite + AsyncSeek + Unpin,
+{
+    /// Prepares to patch the image read by `image`, written through `writer`
+    pub async fn new(mut image: WiiDiscReader<R>, writer: W) -> Result<Self> {
+        let part = image.disc.partitions.partitions[image.disc.partitions.data_idx].clone();
+        let n_groups = (part.header.data_size / WII_GROUP_SIZE) as usize;
+        let mut h3 = vec![0u8; consts::WII_H3_SIZE];
+        image
+            .read_raw(part.part_offset + part.header.h3_offset, &mut h3)
+            .await?;
+        let hashes = h3
+            .chunks_exact(consts::WII_HASH_SIZE)
+            .take(n_groups)
+            .map(|hash| hash.try_into().unwrap())
+            .collect();
+        Ok(Self {
+            image,
+            writer,
+            part,
+            hashes,
+            group: None,
+        })
+    }
+
+    async fn load_group(&mut self, group_idx: usize) -> Result<Box<WiiGroup>> {
+        crate::trace!("Loading group #{}", group_idx);
+        let start = group_idx as u64 * WII_GROUP_DATA_SIZE;
+        let end = std::cmp::min(
+            start + WII_GROUP_DATA_SIZE,
+            to_virtual_addr(self.part.header.data_size),
+        );
+        let mut buf = vec![0u8; end.saturating_sub(start) as usize];
+        self.image.seek(SeekFrom::Start(start)).await?;
+        self.image.read_exact(&mut buf).await?;
+
+        let mut group = Box::<WiiGroup>::default();
+        for (i, chunk) in buf.chunks(consts::WII_SECTOR_DATA_SIZE).enumerate() {
+            group.sub_groups[i / 8].sectors[i % 8].data[..chunk.len()].copy_from_slice(chunk);
+        }
+        Ok(group)
+    }
+
+    async fn flush_group(&mut self) -> Result<()> {
+        let Some((group_idx, mut group)) = self.group.take() else {
+            return Ok(());
+        };
+        crate::trace!("Hashing and encrypting group #{}", group_idx);
+        self.hashes[group_idx] = hash_group(&mut group);
+        if !self.image.disc.disc_header.disable_disc_encrypt {
+            encrypt_group(&mut group, decrypt_title_key(&self.part.header.ticket));
+        }
+        let pos = self.part.part_offset
+            + self.part.header.data_offset
+            + group_idx as u64 * WII_GROUP_SIZE;
+        self.writer.seek(SeekFrom::Start(pos)).await?;
+        self.writer.write_all(&group.to_vec()).await?;
+        // Later groups may read this one back from the image
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Replaces the data of the partition at `offset`
+    pub async fn write_at(&mut self, mut offset: u64, mut data: &[u8]) -> Result<()> {
+        if offset + data.len() as u64 > self.hashes.len() as u64 * WII_GROUP_DATA_SIZE {
+            eyre::bail!("The data doesn't fit in the partition of the image");
+        }
+        while !data.is_empty() {
+            let group_idx = (offset / WII_GROUP_DATA_SIZE) as usize;
+            if self.group.as_ref().map(|(idx, _)| *idx) != Some(group_idx) {
+                self.flush_group().await?;
+                let group = self.load_group(group_idx).await?;
+                self.group = Some((group_idx, group));
+            }
+            let group = &mut self.group.as_mut().unwrap().1;
+
+            let pos = (offset % WII_GROUP_DATA_SIZE) as usize;
+            let sector_idx = pos / consts::WII_SECTOR_DATA_SIZE;
+            let pos_in_sector = pos % consts::WII_SECTOR_DATA_SIZE;
+            let size = std::cmp::min(data.len(), consts::WII_SECTOR_DATA_SIZE - pos_in_sector);
+            let chunk;
+            (chunk, data) = data.split_at(size);
+            group.sub_groups[sector_idx / 8].sectors[sector_idx % 8].data
+                [pos_in_sector..pos_in_sector + size]
+                .copy_from_slice(chunk);
+            offset += size as u64;
+        }
+        Ok(())
+    }
+
+    /// Size of the image
+    pub fn image_len(&self) -> u64 {
+        self.part.part_offset + self.part.header.data_offset + self.part.header.data_size
+    }
+
+    /// Writes the last group, the H3 table and the fake signed partition header
+    pub async fn finish(mut self) -> Result<()> {
+        self.flush_group().await?;
+        fake_sign(&mut self.part, &self.hashes);
+
+        // The certificate between the TMD and the H3 table is left as is
+        let mut buf = vec![0u8; PartHeader::BLOCK_SIZE + self.part.tmd.get_size()];
+        buf[..PartHeader::BLOCK_SIZE]
+            .copy_from_slice(&<[u8; PartHeader::BLOCK_SIZE]>::from(&self.part.header));
+        TitleMetaData::set_partition(&mut buf, PartHeader::BLOCK_SIZE, &self.part.tmd);
+        self.writer
+            .seek(SeekFrom::Start(self.part.part_offset))
+            .await?;
+        self.writer.write_all(&buf).await?;
+
+        let mut h3 = Vec::with_capacity(consts::WII_H3_SIZE);
+        h3.extend(self.hashes.iter().flatten());
+        h3.resize(consts::WII_H3_SIZE, 0);
+        self.writer
+            .seek(SeekFrom::Start(
+                self.part.part_offset + self.part.header.h3_offset,
+            ))
+            .await?;
+        self.writer.write_all(&h3).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+}
+
 // ---
 
 #[derive(Debug, Clone)]
@@ -831,16 +1002,51 @@ where
 
 impl<W> DiscWriter<W>
 where
-    W: AsyncWrite + AsyncSeek + Clone + Unpin,
+    W: AsyncWrite + AsyncSeek + Unpin,
 {
     pub async fn init(self: &mut Pin<&mut Self>) -> Result<()> {
         match self.as_mut().as_wii_disc_mut() {
-            Some(writer) => std::pin::pin!(writer.clone()).init().await,
+            Some(writer) => Pin::new(writer).init().await,
             None => Ok(()),
         }
     }
 }
 
+impl<W> DiscWriter<W> {
+    /// Size of the written Wii image, `None` for a GameCube image whose size is the size of
+    /// the serialized FileSystem
+    pub async fn image_len(&self) -> Option<u64> {
+        match self {
+            DiscWriter::Gamecube(_) => None,
+            DiscWriter::Wii(writer) => {
+                let status = writer.status.lock().await;
+                let part = &status.disc.partitions.partitions[status.disc.partitions.data_idx];
+                Some(part.part_offset + part.header.data_offset + part.header.data_size)
+            }
+        }
+    }
+}
+
+impl<W> DiscWriter<W> {
+    /// Leaves the groups of a Wii partition holding none of the `used` ranges of its data as
+    /// unencrypted zeros. The unused space of a GameCube image is zeros already.
+    pub async fn scrub(&self, used: Vec<(u64, u64)>) {
+        if let DiscWriter::Wii(writer) = self {
+            writer.status.lock().await.used = Some(used);
+        }
+    }
+
+    /// Size of the groups left as zeros by [`DiscWriter::scrub`]
+    pub async fn scrubbed_len(&self) -> u64 {
+        match self {
+            DiscWriter::Gamecube(_) => 0,
+            DiscWriter::Wii(writer) => {
+                writer.status.lock().await.scrubbed_groups * consts::WII_SECTOR_SIZE as u64 * 64
+            }
+        }
+    }
+}
+
 impl<W> DiscWriter<W>
 {
     fn as_wii_disc_mut(&mut self) -> Option<&mut WiiDiscWriter<W>> {
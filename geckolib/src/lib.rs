@@ -17,6 +17,8 @@ extern crate regex;
 extern crate syn;
 
 pub mod patch;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod compile;
 pub mod config;
 pub mod crypto;
 pub mod iso;
@@ -84,10 +86,53 @@ where
     ))
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+/// Compiles the crate next to the config, if the config links a Rom Hack built from it
+fn compile_rom_hack(config_file: &PathBuf, config: &mut Config) -> eyre::Result<()> {
+    if let Some(link) = &mut config.link {
+        let config_file: &std::path::Path = config_file.as_ref();
+        let project_dir = config_file
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(std::path::Path::new("."));
+        if compile::is_needed(project_dir, link) {
+            compile::compile_into(project_dir, link)?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 /// Open a config from a file on the FileSystem to return an IsoBuilder
 pub async fn open_config_from_fs_iso(
     config_file: &PathBuf,
+) -> eyre::Result<IsoBuilder<File, async_std::fs::File, async_std::fs::File>> {
+    open_fs_iso(config_file, false).await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Open a config from a file on the FileSystem to return an IsoBuilder which only rewrites
+/// the changed files of the previous output, unless files moved.
+/// Call [`trim_output`] after building.
+pub async fn open_config_from_fs_iso_in_place(
+    config_file: &PathBuf,
+) -> eyre::Result<IsoBuilder<File, async_std::fs::File, async_std::fs::File>> {
+    open_fs_iso(config_file, true).await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Opens a previous output to patch it in place, if it's a disc image
+async fn open_previous_image(
+    path: &std::path::Path,
+) -> Option<DiscReader<async_std::fs::File>> {
+    let file = async_std::fs::File::open(path).await.ok()?;
+    DiscReader::new(file).await.ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn open_fs_iso(
+    config_file: &PathBuf,
+    in_place: bool,
 ) -> eyre::Result<IsoBuilder<File, async_std::fs::File, async_std::fs::File>> {
     #[cfg(feature = "progress")]
     if let Ok(mut updater) = UPDATER.lock() {
@@ -95,25 +140,351 @@ pub async fn open_config_from_fs_iso(
         ...".into())?;
     }
 
-    let config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    let mut config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    compile_rom_hack(config_file, &mut config)?;
+    let previous_image = match in_place {
+        true => open_previous_image(&config.build.iso).await,
+        false => None,
+    };
     let writer = async_std::fs::OpenOptions::new()
         .write(true)
         .create(true)
-        .truncate(true)
+        .truncate(previous_image.is_none())
         .open(&config.build.iso)
         .await?;
     let disc_reader = DiscReader::new(async_std::fs::File::open(&config.src.iso).await?).await?;
     let gfs = GeckoFS::parse(disc_reader.clone()).await?;
-    Ok(IsoBuilder::new_with_fs(config, PathBuf::new(), gfs, disc_reader, writer))
+    let builder = IsoBuilder::new_with_fs(config, PathBuf::new(), gfs, disc_reader, writer);
+    Ok(match previous_image {
+        Some(image) => builder.with_previous_image(image),
+        None => builder,
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Truncates the output to the size of the image built over it, as a smaller image may have
+/// been written over a previous one
+pub fn trim_output<RConfig, RDisc, W>(builder: &IsoBuilder<RConfig, RDisc, W>) -> eyre::Result<()> {
+    if let Some(len) = builder.image_len() {
+        OpenOptions::new()
+            .write(true)
+            .open(&builder.config().build.iso)?
+            .set_len(len)?;
+    }
+    Ok(())
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 /// Open a config from a file on the FileSystem to return a PatchBuilder
 pub async fn open_config_from_fs_patch(config_file: &PathBuf) -> eyre::Result<PatchBuilder> {
-    let config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    let mut config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    compile_rom_hack(config_file, &mut config)?;
     Ok(PatchBuilder::with_config(config))
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+/// The original game, parsed once for all the builds of `watch`
+struct GameCache {
+    iso: std::path::PathBuf,
+    reader: DiscReader<async_std::fs::File>,
+    gfs: GeckoFS<async_std::fs::File>,
+    dol: patch::dol::DolFile,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl GameCache {
+    async fn open(iso: &std::path::Path) -> eyre::Result<Self> {
+        let reader = DiscReader::new(async_std::fs::File::open(iso).await?).await?;
+        let mut gfs = GeckoFS::parse(reader.clone()).await?;
+        let dol = patch::dol::DolFile::parse(
+            gfs.sys_mut()
+                .get_file_mut("Start.dol")
+                .context("Dol file not found")?,
+        )
+        .await?;
+        Ok(Self {
+            iso: iso.to_path_buf(),
+            reader,
+            gfs,
+            dol,
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Lists the files a build depends on: the config, the crate's sources, the patch file and
+/// the files replaced in the game
+fn watched_paths(config_file: &std::path::Path, config: &Config) -> Vec<std::path::PathBuf> {
+    let project_dir = config_file
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+
+    let mut paths = vec![config_file.to_path_buf()];
+    if let Some(link) = &config.link {
+        let compiles = compile::is_needed(project_dir, link);
+        if compiles {
+            paths.push(project_dir.join("Cargo.toml"));
+            paths.push(project_dir.join("src"));
+        }
+        // The staticlib compiled from `src` changes with every build, it isn't watched
+        let target_dir = project_dir.join("target");
+        paths.extend(
+            link.libs
+                .iter()
+                .map(|lib| project_dir.join(lib))
+                .filter(|lib| !compiles || !lib.starts_with(&target_dir)),
+        );
+    }
+    paths.extend(config.src.patch.iter().map(|p| project_dir.join(p)));
+    paths.extend(config.src.overlays.iter().map(|p| project_dir.join(p)));
+    paths.extend(config.files.values().map(|p| {
+        // Patterns are watched from the directory they start with
+        let pattern = p.to_string_lossy();
+        match vfs::glob::Glob::new(&pattern) {
+            Ok(glob) if vfs::glob::Glob::is_glob(&pattern) => project_dir.join(glob.base()),
+            _ => project_dir.join(p),
+        }
+    }));
+    paths.extend(config.info.image.iter().map(|p| project_dir.join(p)));
+    paths
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Collects the modification times of the paths, recursing into directories
+fn modification_times(
+    paths: &[std::path::PathBuf],
+) -> std::collections::BTreeMap<std::path::PathBuf, std::time::SystemTime> {
+    let mut times = std::collections::BTreeMap::new();
+    let mut stack = paths.to_vec();
+    while let Some(path) = stack.pop() {
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            if let Ok(entries) = std::fs::read_dir(&path) {
+                stack.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+            }
+        } else if let Ok(time) = metadata.modified() {
+            times.insert(path, time);
+        }
+    }
+    times
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Builds the Rom Hack, then rebuilds it whenever a file it depends on changes.
+///
+/// The original game is only parsed once, and only the files which changed get rewritten
+/// when no file moved. `on_build` gets the result of every build.
+pub async fn watch(
+    config_file: &PathBuf,
+    patch: bool,
+    mut on_build: impl FnMut(eyre::Result<()>),
+) -> eyre::Result<()> {
+    use iso::builder::Builder;
+
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+    let config_path: &std::path::Path = config_file.as_ref();
+    let mut cache: Option<GameCache> = None;
+    let mut previous: Option<(std::path::PathBuf, vfs::Layout)> = None;
+
+    loop {
+        let config = match fs::read_to_string(config_file).await {
+            Ok(text) => toml::from_str::<Config>(&text).context("Couldn't parse the config"),
+            Err(err) => Err(err).context("Couldn't read the config"),
+        };
+        // Only the config is watched until it can be read again
+        let paths = match &config {
+            Ok(config) => watched_paths(config_path, config),
+            Err(_) => vec![config_path.to_path_buf()],
+        };
+        let times = modification_times(&paths);
+
+        let result = match config {
+            Err(err) => Err(err),
+            Ok(_) if patch => match open_config_from_fs_patch(config_file).await {
+                Ok(mut builder) => builder.build().await,
+                Err(err) => Err(err),
+            },
+            Ok(mut config) => async {
+                compile_rom_hack(config_file, &mut config)?;
+
+                if !matches!(&cache, Some(c) if c.iso == config.src.iso) {
+                    crate::info!("Parsing {}", config.src.iso.display());
+                    cache = Some(GameCache::open(&config.src.iso).await?);
+                    previous = None;
+                }
+                let game = cache.as_ref().unwrap();
+
+                let output = config.build.iso.clone();
+                let previous_layout = previous
+                    .take()
+                    .filter(|(path, _)| *path == output)
+                    .map(|(_, layout)| layout);
+                let previous_image = match previous_layout {
+                    Some(_) => open_previous_image(&output).await,
+                    None => None,
+                };
+                let writer = async_std::fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(previous_image.is_none())
+                    .open(&output)
+                    .await?;
+
+                let mut builder = IsoBuilder::<File, _, _>::new_with_fs(
+                    config,
+                    std::path::PathBuf::new(),
+                    game.gfs.deep_clone()?,
+                    game.reader.clone(),
+                    writer,
+                )
+                .with_original_dol(game.dol.clone());
+                if let (Some(layout), Some(image)) = (previous_layout, previous_image) {
+                    builder = builder.with_previous_layout(layout).with_previous_image(image);
+                }
+                builder.build().await?;
+                trim_output(&builder)?;
+
+                if let Some(layout) = builder.layout() {
+                    previous = Some((output, layout.clone()));
+                }
+                Ok::<_, eyre::Report>(())
+            }
+            .await,
+        };
+        on_build(result);
+
+        crate::info!("Watching for changes...");
+        #[cfg(feature = "progress")]
+        if let Ok(mut updater) = UPDATER.lock() {
+            updater.set_title("Watching for changes...".into())?;
+        }
+        // Wait for the files to stop changing, so a build doesn't start in the middle of a save
+        let mut last = times;
+        loop {
+            async_std::task::sleep(POLL_INTERVAL).await;
+            let current = modification_times(&paths);
+            if current != last {
+                last = current;
+                loop {
+                    async_std::task::sleep(POLL_INTERVAL).await;
+                    let current = modification_times(&paths);
+                    if current == last {
+                        break;
+                    }
+                    last = current;
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Reads the symbol map and the main DOL of the game the config refers to
+async fn read_game(
+    config: &Config,
+) -> eyre::Result<(std::collections::HashMap<String, u32>, patch::dol::DolFile)> {
+    use async_std::io::ReadExt;
+    use patch::{dol::DolFile, framework_map};
+
+    let disc_reader = DiscReader::new(async_std::fs::File::open(&config.src.iso).await?).await?;
+    let mut gfs = GeckoFS::parse(disc_reader).await?;
+
+    let symbols = match config
+        .src
+        .map
+        .as_ref()
+        .and_then(|m| gfs.root_mut().resolve_node_mut(m))
+        .and_then(|n| n.as_file_mut())
+    {
+        Some(map) => {
+            let mut buf = Vec::new();
+            map.read_to_end(&mut buf).await?;
+            framework_map::parse(&mut async_std::io::Cursor::new(&buf)).await?
+        }
+        None => std::collections::HashMap::new(),
+    };
+    let dol = DolFile::parse(
+        gfs.sys_mut()
+            .get_file_mut("Start.dol")
+            .context("Dol file not found")?,
+    )
+    .await?;
+
+    Ok((symbols, dol))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Checks the original values the patch file expects against the game, without building it
+pub async fn verify_patch(config_file: &PathBuf) -> eyre::Result<Vec<patch::dol::Mismatch>> {
+    use patch::assembler::Assembler;
+
+    let config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    let Some(patch) = &config.src.patch else {
+        return Ok(Vec::new());
+    };
+    let (symbols, dol) = read_game(&config).await?;
+
+    let buf = fs::read_to_string(patch).await.context(format!(
+        "Couldn't read the patch file \"{}\".",
+        patch.display()
+    ))?;
+    let lines = buf.lines().collect::<Vec<_>>();
+    let expectations = Assembler::new(None, &symbols, Some(&dol))
+        .expectations(&lines)
+        .context("Couldn't parse the patch file lines")?;
+
+    Ok(dol.verify(&expectations))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Lists the files `[files]` replaces in the game once its patterns are resolved, without
+/// building
+pub async fn resolve_files(
+    config_file: &PathBuf,
+) -> eyre::Result<Vec<(String, std::path::PathBuf)>> {
+    let config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    let disc_reader = DiscReader::new(async_std::fs::File::open(&config.src.iso).await?).await?;
+    let gfs = GeckoFS::parse(disc_reader.clone()).await?;
+    IsoBuilder::<File, _, _>::new_with_fs(config, PathBuf::new(), gfs, disc_reader, ())
+        .resolve_files()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Goes through a build of the Rom Hack without writing the image, to report what it would
+/// write
+pub async fn plan_build(config_file: &PathBuf) -> eyre::Result<iso::builder::Plan> {
+    let mut config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    compile_rom_hack(config_file, &mut config)?;
+    let disc_reader = DiscReader::new(async_std::fs::File::open(&config.src.iso).await?).await?;
+    let gfs = GeckoFS::parse(disc_reader.clone()).await?;
+    IsoBuilder::<File, _, _>::new_with_fs(config, PathBuf::new(), gfs, disc_reader, ())
+        .plan()
+        .await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Writes Rust bindings to the symbols of the game's symbol map into a module of the Rom Hack
+pub async fn bindgen(config_file: &PathBuf, output: &std::path::Path) -> eyre::Result<()> {
+    let config: Config = toml::from_str(&fs::read_to_string(config_file).await?)?;
+    if config.src.map.is_none() {
+        eyre::bail!("The game's symbol map needs to be specified as `map` in `[src]`");
+    }
+    let (symbols, dol) = read_game(&config).await?;
+
+    let mut file = std::io::BufWriter::new(
+        File::create(output).context(format!("Couldn't create \"{}\"", output.display()))?,
+    );
+    patch::bindgen::generate(&mut file, &symbols, &dol)
+        .context("Couldn't write the bindings")?;
+    std::io::Write::flush(&mut file)?;
+    Ok(())
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn new(name: &str) -> eyre::Result<()> {
     use std::io::Write;
@@ -136,20 +507,69 @@ game-name = "{0}"
 [src]
 iso = "game.iso" # Provide the path of the game's ISO
 patch = "src/patch.asm"
-# Optionally specify the game's symbol map
+# Optionally specify the game's symbol map (CodeWarrior map, decomp-toolkit symbols.txt or ELF)
 # map = "maps/framework.map"
+# Then `romhack bindgen` writes Rust bindings to the game's symbols into src/game.rs
+# Optionally combine other mods' folders or zips onto the game, the later ones win. A file
+# named ".wh.<name>" in a mod removes <name> from the game
+# overlays = ["mods/some-mod", "mods/other-mod.zip"]
 
 [files]
 # You may replace or add new files to the game here
 # "path/to/file/in/iso" = "path/to/file/on/harddrive"
+# Patterns map many files at once, the wildcards of one side filled with what the other matched
+# "res/Stage/*.arc" = "stages/*.arc"
+# `romhack files` lists the files the patterns resolve to
+
+[remove]
+# You may remove files or directories of the game, by path or glob, to make room
+# paths = ["path/to/unused/file", "movies/*.thp"]
+
+[rename]
+# You may rename or move files or directories of the game, before the files above replace them
+# "path/to/file/in/iso" = "new/path/in/iso"
+
+[placement]
+# You may control where the files are placed on the disc, for example to keep streamed files
+# together or keep the game's files in place so patches stay small
+# order = ["files/stage/*"]
+# align = {{ "audio/**/*.adp" = 0x8000 }}
+# preserve-offsets = true
+
+[header]
+# You may give the Rom Hack its own game ID so its saves don't collide with the game's
+# game-code = "ZL"
+# region-code = "E"
+# maker-code = "01"
+# title = "{0}"
+# On Wii, also make it the title ID of the ticket and TMD
+# wii-title-id = true
+
+[wii]
+# On Wii, you may change the IOS the game runs on, its title ID, region and age ratings
+# ios = 58
+# title-id = "00010000525a4445"
+# region = "free" # japan, usa, europe, korea or free
+# age-ratings = {{ jp = 0, us = 0, pegi = 3 }}
 
 [build]
 map = "target/framework.map"
 iso = "target/{0}.iso"
+# Additional symbol tables for debuggers and disassemblers (dolphin, ghidra or ida)
+# symbols = [{{ format = "dolphin", path = "target/dolphin.map" }}]
+# Pad the disc with the junk data of retail discs instead of zeros, like the original game
+# junk-padding = true
+# Or leave the Wii partition's unused groups as unencrypted zeros, to share the smallest image
+# scrub = true
 
 [link]
 entries = ["init"] # Enter the exported function names here
 base = "0x8040_1000" # Enter the start address of the Rom Hack's code here
+# The crate is compiled with cargo before linking, optionally for another target or profile
+# target = "powerpc-unknown-eabi"
+# profile = "release"
+# build = false # Only link the libraries below, without compiling the crate first
+# libs = [] # Additional static libraries to link
 "#,
         name.replace('-', "_"),
     )
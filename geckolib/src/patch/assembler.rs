@@ -1,3 +1,5 @@
+use crate::patch::demangle::lookup;
+use crate::patch::dol::DolFile;
 use eyre::Context;
 use std::collections::{BTreeMap, HashMap};
 use syn::Error as ParseError;
@@ -5,22 +7,27 @@ use syn::Error as ParseError;
 pub struct Assembler<'a> {
     symbol_table: Option<BTreeMap<&'a str, u32>>,
     prelinked_symbols: &'a HashMap<String, u32>,
+    dol: Option<&'a DolFile>,
     program_counter: u32,
 }
 
 pub struct Instruction {
     pub address: u32,
     pub data: u32,
+    /// Value the game is expected to have at the address before patching
+    pub expected: Option<u32>,
 }
 
 impl<'a> Assembler<'a> {
     pub fn new(
         symbol_table: Option<BTreeMap<&'a str, u32>>,
         prelinked_symbols: &'a HashMap<String, u32>,
+        dol: Option<&'a DolFile>,
     ) -> Assembler<'a> {
         Assembler {
             symbol_table,
             prelinked_symbols,
+            dol,
             program_counter: 0,
         }
     }
@@ -28,26 +35,73 @@ impl<'a> Assembler<'a> {
     pub fn assemble_all_lines(&mut self, lines: &[&str]) -> eyre::Result<Vec<Instruction>> {
         let mut instructions = Vec::new();
 
-        let filtered_lines = lines
-            .iter()
-            .map(|l| reduce_line_to_code(l))
-            .filter(|l| !l.is_empty());
-
-        for line in filtered_lines {
-            if line.ends_with(':') {
+        for line in lines {
+            let expected = parse_expectation(line)?;
+            let (label, line) = split_label(reduce_line_to_code(line));
+            if let Some(label) = label {
+                if expected.is_some() && line.is_empty() {
+                    eyre::bail!(
+                        "Only instructions can expect an original value: \"{}\"",
+                        label
+                    );
+                }
                 self.program_counter = self
-                    .parse_program_counter_label(line)
+                    .parse_program_counter_label(label)
                     .context("Couldn't parse address label")?;
-            } else {
-                let instruction = self.parse_instruction(line)?;
-                instructions.push(instruction);
-                self.program_counter += 4;
             }
+            if line.is_empty() {
+                continue;
+            }
+            let mut instruction = self.parse_instruction(line)?;
+            instruction.expected = expected;
+            instructions.push(instruction);
+            self.program_counter += 4;
         }
 
         Ok(instructions)
     }
 
+    /// Lists the original values expected by the patch lines, as instructions with the
+    /// expected value as data. The instructions themselves aren't assembled, so symbols of the
+    /// Rom Hack aren't needed. Lines following a label that can't be resolved are skipped.
+    pub fn expectations(&mut self, lines: &[&str]) -> eyre::Result<Vec<Instruction>> {
+        let mut expectations = Vec::new();
+        let mut resolved = true;
+
+        for line in lines {
+            let expected = parse_expectation(line)?;
+            let (label, line) = split_label(reduce_line_to_code(line));
+            if let Some(label) = label {
+                match self.parse_program_counter_label(label) {
+                    Ok(address) => {
+                        self.program_counter = address;
+                        resolved = true;
+                    }
+                    Err(err) => {
+                        crate::debug!("Skipping the lines of \"{}\": {}", label, err);
+                        resolved = false;
+                    }
+                }
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(expected) = expected {
+                if !resolved {
+                    eyre::bail!("The address of \"{}\" couldn't be resolved", line);
+                }
+                expectations.push(Instruction {
+                    address: self.program_counter,
+                    data: expected,
+                    expected: Some(expected),
+                });
+            }
+            self.program_counter += 4;
+        }
+
+        Ok(expectations)
+    }
+
     fn parse_instruction(&self, line: &str) -> eyre::Result<Instruction> {
         let data;
 
@@ -75,6 +129,22 @@ impl<'a> Assembler<'a> {
             let imm =
                 parse_i64_literal(imm).context("Couldn't parse immediate for lis instruction")?;
             data = build_lis_instruction(register as u8, imm as i16);
+        } else if let Some(operand) = line.strip_prefix("li ") {
+            let mut splits = operand.split(',').map(|s| s.trim());
+            let register = splits
+                .next()
+                .ok_or_else(|| eyre::eyre!("Expected register"))?;
+            if !register.starts_with('r') {
+                eyre::bail!("Unexpected register: \"{}\"", register);
+            }
+            let register =
+                parse_i64_literal(&register[1..]).context("Couldn't parse the register index")?;
+            let imm = splits
+                .next()
+                .ok_or_else(|| eyre::eyre!("Expected immediate for li instruction"))?;
+            let imm =
+                parse_i64_literal(imm).context("Couldn't parse immediate for li instruction")?;
+            data = build_addi_instruction(register as u8, 0, imm as i16);
         } else if line == "nop" {
             data = 0x60000000;
         } else {
@@ -84,6 +154,7 @@ impl<'a> Assembler<'a> {
         Ok(Instruction {
             address: self.program_counter,
             data,
+            expected: None,
         })
     }
 
@@ -96,13 +167,55 @@ impl<'a> Assembler<'a> {
             return Ok(symbol);
         }
 
-        if let Some(&symbol) = self.prelinked_symbols.get(symbol) {
+        if let Some(symbol) = lookup(self.prelinked_symbols, symbol) {
             return Ok(symbol);
         }
 
         eyre::bail!(format!("The symbol \"{}\" wasn't found", symbol))
     }
 
+    /// Finds the only address in the text sections of the DOL where the byte pattern matches.
+    /// The pattern is made of hexadecimal bytes, where `??` matches any byte.
+    fn scan_signature(&self, signature: &str) -> eyre::Result<u32> {
+        let dol = self
+            .dol
+            .ok_or_else(|| eyre::eyre!("There's no DOL to scan for signatures"))?;
+        let pattern = parse_signature(signature)
+            .context(format!("Couldn't parse the signature \"{}\"", signature))?;
+
+        let mut matches = Vec::new();
+        for section in &dol.text_sections {
+            if section.data.len() < pattern.len() {
+                continue;
+            }
+            for offset in (0..=section.data.len() - pattern.len()).step_by(4) {
+                let bytes = &section.data[offset..offset + pattern.len()];
+                if pattern
+                    .iter()
+                    .zip(bytes)
+                    .all(|(p, b)| p.is_none() || *p == Some(*b))
+                {
+                    matches.push(section.address + offset as u32);
+                }
+            }
+        }
+
+        match matches[..] {
+            [address] => Ok(address),
+            [] => eyre::bail!("The signature \"{}\" wasn't found", signature),
+            _ => eyre::bail!(
+                "The signature \"{}\" was found {} times, at {}",
+                signature,
+                matches.len(),
+                matches
+                    .iter()
+                    .map(|a| format!("{:08X}", a))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
     fn parse_program_counter_label(&self, line: &str) -> eyre::Result<u32> {
         let mut line = line[..line.len() - 1].trim_start();
         let mut address = 0u32;
@@ -148,7 +261,11 @@ impl<'a> Assembler<'a> {
                         .sum();
                     let symbol = &line[..len];
                     line = &line[len + 1..];
-                    self.resolve_symbol(symbol)?
+                    if let Some(signature) = symbol.strip_prefix("sig:") {
+                        self.scan_signature(signature)?
+                    } else {
+                        self.resolve_symbol(symbol)?
+                    }
                 }
                 _ => eyre::bail!("Expected integer literal or symbol"),
             };
@@ -177,6 +294,62 @@ fn reduce_line_to_code(line: &str) -> &str {
     line.trim()
 }
 
+/// Splits the `address:` label off a line, leaving the instruction that may follow it
+fn split_label(line: &str) -> (Option<&str>, &str) {
+    let mut depth = 0;
+    for (index, c) in line.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ':' if depth == 0 => return (Some(&line[..=index]), line[index + 1..].trim()),
+            _ => {}
+        }
+    }
+    (None, line)
+}
+
+/// Parses the `; expect XXXXXXXX` comment of a line
+fn parse_expectation(line: &str) -> eyre::Result<Option<u32>> {
+    let Some(index) = line.find(';') else {
+        return Ok(None);
+    };
+    match line[index + 1..].trim().strip_prefix("expect ") {
+        Some(value) => {
+            let value = value.trim();
+            let digits = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix("0X"))
+                .unwrap_or(value);
+            Ok(Some(u32::from_str_radix(digits, 16).context(format!(
+                "Couldn't parse the expected value \"{}\"",
+                value
+            ))?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_signature(signature: &str) -> eyre::Result<Vec<Option<u8>>> {
+    let digits = signature
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<_>>();
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        eyre::bail!("Expected a whole number of bytes");
+    }
+    digits
+        .chunks(2)
+        .map(|byte| match byte {
+            ['?', '?'] => Ok(None),
+            [high, low] => Ok(Some(
+                u8::from_str_radix(&format!("{}{}", high, low), 16)
+                    .context(format!("Invalid byte \"{}{}\"", high, low))?,
+            )),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
 fn parse_i64_literal(literal: &str) -> Result<i64, ParseError> {
     let val: syn::LitInt = syn::parse_str(literal)?;
     val.base10_parse::<i64>()
@@ -202,9 +375,112 @@ fn build_lis_instruction(register: u8, imm: i16) -> u32 {
     build_addis_instruction(register, 0, imm)
 }
 
+fn build_addi_instruction(reg_d: u8, reg_a: u8, imm: i16) -> u32 {
+    0x3800_0000
+        | ((reg_d as u32 & 0b11111) << 21)
+        | ((reg_a as u32 & 0b11111) << 16)
+        | (imm as u32 & 0xFFFF)
+}
+
 fn build_addis_instruction(reg_d: u8, reg_a: u8, imm: i16) -> u32 {
     0x3C00_0000
         | ((reg_d as u32 & 0b11111) << 21)
         | ((reg_a as u32 & 0b11111) << 16)
         | (imm as u32 & 0xFFFF)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::dol::Section;
+
+    fn dol() -> DolFile {
+        DolFile {
+            text_sections: vec![Section {
+                address: 0x8000_3100,
+                data: vec![
+                    0x7C, 0x08, 0x02, 0xA6, 0x94, 0x21, 0xFF, 0xF0, 0x4E, 0x80, 0x00, 0x20,
+                ]
+                .into_boxed_slice(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signature_labels_are_resolved() {
+        let dol = dol();
+        let symbols = HashMap::new();
+        let assembler = Assembler::new(None, &symbols, Some(&dol));
+
+        assert_eq!(
+            assembler
+                .parse_program_counter_label("[sig:7C0802A6 9421????]+0x8:")
+                .unwrap(),
+            0x8000_3108
+        );
+        assert!(assembler
+            .parse_program_counter_label("[sig:38600001]:")
+            .is_err());
+        assert!(assembler.parse_program_counter_label("[sig:??]:").is_err());
+    }
+
+    #[test]
+    fn inline_labels_expect_original_values() {
+        let dol = dol();
+        let symbols = HashMap::new();
+        let lines = [
+            "0x80003100: li r3, 0 ; expect 7C0802A6",
+            "nop ; expect 38600001",
+            "[sig:4E800020]: nop ; expect 4E800020",
+        ];
+        let instructions = Assembler::new(None, &symbols, Some(&dol))
+            .assemble_all_lines(&lines)
+            .unwrap();
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|i| (i.address, i.data, i.expected))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x8000_3100, 0x3860_0000, Some(0x7C08_02A6)),
+                (0x8000_3104, 0x6000_0000, Some(0x3860_0001)),
+                (0x8000_3108, 0x6000_0000, Some(0x4E80_0020)),
+            ]
+        );
+        let mismatches = dol.verify(&instructions);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].address, 0x8000_3104);
+
+        let expectations = Assembler::new(None, &symbols, Some(&dol))
+            .expectations(&lines)
+            .unwrap();
+        assert_eq!(dol.verify(&expectations).len(), 1);
+    }
+
+    #[test]
+    fn hook_entries_expect_original_values() {
+        let mut dol = dol();
+        let symbols = HashMap::new();
+        let rom_hack = BTreeMap::from([("on_frame", 0x8040_0000)]);
+        let lines = [
+            "0x80003100:",
+            "bl on_frame ; expect 7C0802A6",
+            "0x80003104: b on_frame ; expect 38600001",
+        ];
+        let hooks = Assembler::new(Some(rom_hack), &symbols, Some(&dol))
+            .assemble_all_lines(&lines)
+            .unwrap();
+        assert_eq!(hooks[1].data, 0x483F_CEFC);
+        assert!(dol.patch(&hooks).is_err());
+
+        // Verifying doesn't need the Rom Hack's symbols
+        let expectations = Assembler::new(None, &symbols, Some(&dol))
+            .expectations(&lines)
+            .unwrap();
+        let mismatches = dol.verify(&expectations);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].address, 0x8000_3104);
+        assert_eq!(mismatches[0].found, Some(0x9421_FFF0));
+    }
+}
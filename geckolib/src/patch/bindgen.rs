@@ -0,0 +1,307 @@
+//! Generates a Rust module to call into the game, from the symbols of its symbol map.
+//!
+//! Functions get declared in an `extern` block when their parameters are known from their
+//! C++ signature. Every symbol also gets an address constant, so functions with unknown
+//! parameters and globals can still be reached.
+
+use crate::patch::demangle::{parse_signature, Qualifiers, Type};
+use crate::patch::dol::DolFile;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+enum Declaration {
+    Function { params: String, return_type: String },
+    Static,
+}
+
+struct Binding<'a> {
+    name: &'a str,
+    address: u32,
+    ident: String,
+    declaration: Option<Declaration>,
+}
+
+/// Turns a symbol name into a Rust identifier
+fn sanitize(name: &str) -> String {
+    let mut ident = String::with_capacity(name.len());
+    for c in name.replace("::", "__").chars() {
+        let c = if c.is_ascii_alphanumeric() { c } else { '_' };
+        if c == '_' && ident.ends_with("__") {
+            continue;
+        }
+        ident.push(c);
+    }
+    let mut ident = ident.trim_end_matches('_').to_owned();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if KEYWORDS.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+    ident
+}
+
+fn is_const(typ: &Type) -> bool {
+    match typ {
+        Type::Normal(q, _) | Type::Path(q, _) | Type::Pointer(q, _) | Type::Reference(q, _) => {
+            q.is_const
+        }
+        Type::Array(_, typ) => is_const(typ),
+        _ => false,
+    }
+}
+
+/// Translates a parameter or return type, `None` if it can't be passed through the C ABI
+fn rust_type(typ: &Type, is_pointee: bool) -> Option<String> {
+    Some(match typ {
+        Type::Normal(_, name) => match name.as_ref() {
+            "bool" | "i8" | "u8" | "i16" | "u16" | "i32" | "u32" | "i64" | "u64" | "f32"
+            | "f64" => name.to_string(),
+            "long double" => "f64".to_owned(),
+            "wchar_t" => "u16".to_owned(),
+            _ if is_pointee => "c_void".to_owned(),
+            _ => return None,
+        },
+        Type::Path(..) if is_pointee => "c_void".to_owned(),
+        Type::Pointer(_, pointee) | Type::Reference(_, pointee) => {
+            if let Type::Function(_, return_type, params) = pointee.as_ref() {
+                let function = format!(
+                    "unsafe extern \"C\" fn({}){}",
+                    rust_params(params)?.join(", "),
+                    rust_return_type(return_type)?
+                );
+                return Some(if let Type::Pointer(..) = typ {
+                    format!("Option<{}>", function)
+                } else {
+                    function
+                });
+            }
+            let mutability = if is_const(pointee) { "const" } else { "mut" };
+            format!("*{} {}", mutability, rust_type(pointee, true)?)
+        }
+        Type::Array(count, element) if is_pointee => {
+            format!("[{}; {}]", rust_type(element, false)?, count)
+        }
+        Type::Array(_, element) => {
+            let mutability = if is_const(element) { "const" } else { "mut" };
+            format!("*{} {}", mutability, rust_type(element, true)?)
+        }
+        _ => return None,
+    })
+}
+
+fn rust_params(params: &[Type]) -> Option<Vec<String>> {
+    params
+        .iter()
+        .filter(|p| !p.is_void())
+        .enumerate()
+        .map(|(i, param)| match param {
+            Type::VarArgs => Some("...".to_owned()),
+            param => Some(format!("_{}: {}", i, rust_type(param, false)?)),
+        })
+        .collect()
+}
+
+fn rust_return_type(return_type: &Type) -> Option<String> {
+    if return_type.is_void() {
+        Some(String::new())
+    } else {
+        Some(format!(" -> {}", rust_type(return_type, false)?))
+    }
+}
+
+/// Creates the binding of a symbol, without making its identifier unique yet
+fn binding<'a>(
+    name: &'a str,
+    address: u32,
+    classes: &HashSet<String>,
+    dol: &DolFile,
+) -> Binding<'a> {
+    let Ok(signature) = parse_signature(name) else {
+        return Binding {
+            name,
+            address,
+            ident: sanitize(name),
+            declaration: None,
+        };
+    };
+
+    let mut class = signature.class;
+    let member = match signature.base_name.as_str() {
+        "__ct" => "ct".to_owned(),
+        "__dt" => "dt".to_owned(),
+        // Constructors are demangled without their name, like `Path::Class()`
+        base_name
+            if signature.function.is_some()
+                && classes.contains(name.split('(').next().unwrap_or(name)) =>
+        {
+            class.push(base_name.to_owned());
+            "ct".to_owned()
+        }
+        base_name if base_name.starts_with("__") => format!("operator{}", base_name),
+        base_name => base_name.to_owned(),
+    };
+    let is_member = !class.is_empty();
+    class.push(member);
+    let ident = sanitize(&class.join("::"));
+
+    let declaration = match signature.function {
+        Some((qualifiers, params, return_type)) => {
+            let this = is_member.then(|| {
+                let Qualifiers { is_const, .. } = qualifiers;
+                format!("this: *{} c_void", if is_const { "const" } else { "mut" })
+            });
+            let void = Type::Normal(Qualifiers::default(), "void".into());
+            rust_params(&params)
+                .zip(rust_return_type(return_type.as_ref().unwrap_or(&void)))
+                .map(|(params, return_type)| Declaration::Function {
+                    params: this
+                        .into_iter()
+                        .chain(params)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    return_type,
+                })
+        }
+        None => {
+            let is_code = dol.text_sections.iter().any(|s| s.contains(address));
+            (!is_code).then_some(Declaration::Static)
+        }
+    };
+
+    Binding {
+        name,
+        address,
+        ident,
+        declaration,
+    }
+}
+
+/// Writes the bindings to the symbols of the game, found in `dol`
+pub fn generate<W: Write>(
+    file: &mut W,
+    symbols: &HashMap<String, u32>,
+    dol: &DolFile,
+) -> io::Result<()> {
+    let classes = symbols
+        .keys()
+        .filter_map(|name| parse_signature(name).ok())
+        .flat_map(|signature| {
+            (1..=signature.class.len())
+                .map(|len| signature.class[..len].join("::"))
+                .collect::<Vec<_>>()
+        })
+        .collect::<HashSet<_>>();
+
+    let mut by_ident = BTreeMap::<String, Vec<Binding>>::new();
+    for (name, &address) in symbols {
+        let binding = binding(name, address, &classes, dol);
+        by_ident
+            .entry(binding.ident.clone())
+            .or_default()
+            .push(binding);
+    }
+
+    // Overloads share an identifier, so they get numbered in the order of their names
+    let mut bindings = Vec::with_capacity(symbols.len());
+    for (_, mut overloads) in by_ident {
+        overloads.sort_by_key(|b| b.name);
+        if overloads.len() > 1 {
+            for (i, binding) in overloads.iter_mut().enumerate() {
+                binding.ident = format!("{}_{}", binding.ident, i);
+            }
+        }
+        bindings.extend(overloads);
+    }
+
+    writeln!(
+        file,
+        "//! Bindings to the game's symbols, generated from its symbol map by `romhack bindgen`."
+    )?;
+    writeln!(
+        file,
+        "//! Functions with unknown parameters only have an address in `addresses`."
+    )?;
+    writeln!(file)?;
+    writeln!(file, "#![allow(non_upper_case_globals, non_snake_case, dead_code, unused_imports, improper_ctypes, clippy::all)]")?;
+    writeln!(file)?;
+    writeln!(file, "use core::ffi::c_void;")?;
+    writeln!(file)?;
+    writeln!(file, "/// Addresses of the game's symbols")?;
+    writeln!(file, "pub mod addresses {{")?;
+    for binding in &bindings {
+        writeln!(
+            file,
+            "    pub const {}: u32 = 0x{:08X};",
+            binding.ident, binding.address
+        )?;
+    }
+    writeln!(file, "}}")?;
+    writeln!(file)?;
+    writeln!(file, "unsafe extern \"C\" {{")?;
+    for binding in &bindings {
+        if let Some(declaration) = &binding.declaration {
+            if binding.ident != binding.name {
+                writeln!(file, "    #[link_name = {:?}]", binding.name)?;
+            }
+            match declaration {
+                Declaration::Function {
+                    params,
+                    return_type,
+                } => writeln!(
+                    file,
+                    "    pub fn {}({}){};",
+                    binding.ident, params, return_type
+                )?,
+                Declaration::Static => {
+                    writeln!(file, "    pub static mut {}: [u8; 0];", binding.ident)?
+                }
+            }
+        }
+    }
+    writeln!(file, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::dol::Section;
+
+    #[test]
+    fn bindings_are_generated() {
+        let dol = DolFile {
+            text_sections: vec![Section {
+                address: 0x8000_3100,
+                data: vec![0; 0x100].into_boxed_slice(),
+            }],
+            ..Default::default()
+        };
+        let mut symbols = HashMap::new();
+        symbols.insert("OSReport".to_owned(), 0x8000_3100);
+        symbols.insert("dAcPy_c::setDamage(i32)".to_owned(), 0x8000_3120);
+        symbols.insert("dAcPy_c::setDamage(f32)".to_owned(), 0x8000_3140);
+        symbols.insert("dAcPy_c()".to_owned(), 0x8000_3160);
+        symbols.insert("dAcPy_c::sInstance".to_owned(), 0x8040_0000);
+
+        let mut buf = Vec::new();
+        generate(&mut buf, &symbols, &dol).unwrap();
+        let bindings = String::from_utf8(buf).unwrap();
+
+        assert!(bindings.contains("pub const OSReport: u32 = 0x80003100;"));
+        assert!(!bindings.contains("pub fn OSReport"));
+        assert!(bindings.contains(
+            "#[link_name = \"dAcPy_c::setDamage(f32)\"]\n    pub fn dAcPy_c__setDamage_0(this: *mut c_void, _0: f32);"
+        ));
+        assert!(bindings.contains("pub fn dAcPy_c__setDamage_1(this: *mut c_void, _0: i32);"));
+        assert!(bindings.contains("pub fn dAcPy_c__ct(this: *mut c_void);"));
+        assert!(bindings.contains("pub static mut dAcPy_c__sInstance: [u8; 0];"));
+    }
+}
@@ -1,19 +1,144 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::{self, Display};
 
-type IsConst = bool;
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub(crate) struct Qualifiers {
+    pub is_const: bool,
+    pub is_volatile: bool,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-enum Type<'a> {
-    Path(IsConst, Vec<&'a str>),
-    Normal(IsConst, &'a str),
-    Function(IsConst, Box<Type<'a>>, Vec<Type<'a>>),
+pub(crate) enum Type<'a> {
+    Path(Qualifiers, Vec<Cow<'a, str>>),
+    Normal(Qualifiers, Cow<'a, str>),
+    Function(Qualifiers, Box<Type<'a>>, Vec<Type<'a>>),
     Array(usize, Box<Type<'a>>),
-    Pointer(IsConst, Box<Type<'a>>),
-    Reference(IsConst, Box<Type<'a>>),
+    Pointer(Qualifiers, Box<Type<'a>>),
+    Reference(Qualifiers, Box<Type<'a>>),
+    /// Pointer to a member of the class
+    MemberPointer(Qualifiers, Box<Type<'a>>, Box<Type<'a>>),
     VarArgs,
 }
 
+/// Mangled special function names and the operator they stand for
+const OPERATORS: &[(&str, &str)] = &[
+    ("__nwa", " new[]"),
+    ("__dla", " delete[]"),
+    ("__nw", " new"),
+    ("__dl", " delete"),
+    ("__apl", "+="),
+    ("__ami", "-="),
+    ("__amu", "*="),
+    ("__adv", "/="),
+    ("__amd", "%="),
+    ("__aer", "^="),
+    ("__aad", "&="),
+    ("__aor", "|="),
+    ("__als", "<<="),
+    ("__ars", ">>="),
+    ("__pl", "+"),
+    ("__mi", "-"),
+    ("__ml", "*"),
+    ("__dv", "/"),
+    ("__md", "%"),
+    ("__er", "^"),
+    ("__ad", "&"),
+    ("__or", "|"),
+    ("__co", "~"),
+    ("__nt", "!"),
+    ("__as", "="),
+    ("__lt", "<"),
+    ("__gt", ">"),
+    ("__ls", "<<"),
+    ("__rs", ">>"),
+    ("__eq", "=="),
+    ("__ne", "!="),
+    ("__le", "<="),
+    ("__ge", ">="),
+    ("__aa", "&&"),
+    ("__oo", "||"),
+    ("__pp", "++"),
+    ("__mm", "--"),
+    ("__cm", ","),
+    ("__rm", "->*"),
+    ("__rf", "->"),
+    ("__cl", "()"),
+    ("__vc", "[]"),
+];
+
+/// Mangled fundamental types and the names they are demangled to. Alternative spellings
+/// accepted by the mangler come after the first one.
+const FUNDAMENTALS: &[(&str, &[&str])] = &[
+    ("v", &["void"]),
+    ("b", &["bool"]),
+    ("c", &["i8", "char", "s8"]),
+    ("Sc", &["signed char"]),
+    ("Uc", &["u8", "unsigned char"]),
+    ("s", &["i16", "short", "s16", "signed short"]),
+    ("Us", &["u16", "unsigned short"]),
+    ("i", &["i32", "int", "s32", "signed", "signed int"]),
+    ("l", &["long", "signed long"]),
+    ("Ui", &["u32", "unsigned int", "unsigned"]),
+    ("Ul", &["unsigned long"]),
+    ("x", &["i64", "long long", "s64", "signed long long"]),
+    ("Ux", &["u64", "unsigned long long"]),
+    ("f", &["f32", "float"]),
+    ("d", &["f64", "double"]),
+    ("r", &["long double"]),
+    ("w", &["wchar_t"]),
+];
+
+impl Display for Qualifiers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_const {
+            write!(f, " const")?;
+        }
+        if self.is_volatile {
+            write!(f, " volatile")?;
+        }
+        Ok(())
+    }
+}
+
+impl Qualifiers {
+    fn mangle(&self) -> &'static str {
+        match (self.is_const, self.is_volatile) {
+            (true, true) => "CV",
+            (true, false) => "C",
+            (false, true) => "V",
+            (false, false) => "",
+        }
+    }
+}
+
+impl<'a> Type<'a> {
+    pub fn is_void(&self) -> bool {
+        matches!(self, Type::Normal(q, name) if *q == Qualifiers::default() && name == "void")
+    }
+
+    /// Whether pointers to this type need parentheses, like `void (*)(i32)`
+    fn is_compound(&self) -> bool {
+        matches!(self, Type::Function(..) | Type::Array(..))
+    }
+
+    fn qualify(self, qualifiers: Qualifiers) -> Result<Self, Cow<'static, str>> {
+        let merge = |q: Qualifiers| Qualifiers {
+            is_const: q.is_const || qualifiers.is_const,
+            is_volatile: q.is_volatile || qualifiers.is_volatile,
+        };
+        Ok(match self {
+            Type::Normal(q, name) => Type::Normal(merge(q), name),
+            Type::Path(q, elements) => Type::Path(merge(q), elements),
+            Type::Function(q, return_type, params) => Type::Function(merge(q), return_type, params),
+            Type::Pointer(q, typ) => Type::Pointer(merge(q), typ),
+            Type::Reference(q, typ) => Type::Reference(merge(q), typ),
+            Type::MemberPointer(q, class, typ) => Type::MemberPointer(merge(q), class, typ),
+            t => return Err(format!("Unexpected qualified type {}", t).into()),
+        })
+    }
+}
+
 struct DisplayLeft<'a>(&'a Type<'a>);
 struct DisplayRight<'a>(&'a Type<'a>);
 
@@ -22,41 +147,31 @@ impl<'a> Display for DisplayLeft<'a> {
         use self::Type::*;
         let &DisplayLeft(typ) = self;
         match *typ {
-            Path(is_const, ref p) => {
+            Path(qualifiers, ref p) => {
                 for (i, element) in p.iter().enumerate() {
                     if i != 0 {
                         write!(f, "::")?;
                     }
                     write!(f, "{}", element)?;
                 }
-                if is_const {
-                    write!(f, " const")?;
-                }
-                Ok(())
-            }
-            Normal(is_const, t) => {
-                write!(f, "{}", t)?;
-                if is_const {
-                    write!(f, " const")?;
-                }
-                Ok(())
-            }
-            Function(_, ref r, _) => write!(f, "{} (", DisplayLeft(r)),
-            Array(_, ref t) => write!(f, "{} (", DisplayLeft(t)),
-            Pointer(is_const, ref t) => {
-                write!(f, "{}*", DisplayLeft(t))?;
-                if is_const {
-                    write!(f, " const")?;
-                }
-                Ok(())
+                write!(f, "{}", qualifiers)
             }
-            Reference(is_const, ref t) => {
-                write!(f, "{}&", DisplayLeft(t))?;
-                if is_const {
-                    write!(f, " const")?;
+            Normal(qualifiers, ref t) => write!(f, "{}{}", t, qualifiers),
+            Function(_, ref r, _) => write!(f, "{} ", DisplayLeft(r)),
+            Array(_, ref t) => write!(f, "{}", DisplayLeft(t)),
+            Pointer(qualifiers, ref t) | Reference(qualifiers, ref t) => {
+                let symbol = if let Pointer(..) = typ { '*' } else { '&' };
+                match **t {
+                    Function(..) => write!(f, "{}({}{}", DisplayLeft(t), symbol, qualifiers),
+                    Array(..) => write!(f, "{} ({}{}", DisplayLeft(t), symbol, qualifiers),
+                    _ => write!(f, "{}{}{}", DisplayLeft(t), symbol, qualifiers),
                 }
-                Ok(())
             }
+            MemberPointer(qualifiers, ref class, ref t) => match **t {
+                Function(..) => write!(f, "{}({}::*{}", DisplayLeft(t), class, qualifiers),
+                Array(..) => write!(f, "{} ({}::*{}", DisplayLeft(t), class, qualifiers),
+                _ => write!(f, "{} {}::*{}", DisplayLeft(t), class, qualifiers),
+            },
             VarArgs => write!(f, "..."),
         }
     }
@@ -68,8 +183,8 @@ impl<'a> Display for DisplayRight<'a> {
         let &DisplayRight(typ) = self;
         match *typ {
             Path(_, _) | Normal(_, _) | VarArgs => Ok(()),
-            Function(is_const, ref r, ref p) => {
-                write!(f, ")(")?;
+            Function(qualifiers, ref r, ref p) => {
+                write!(f, "(")?;
 
                 for (i, param) in p.iter().enumerate() {
                     if i != 0 {
@@ -78,15 +193,15 @@ impl<'a> Display for DisplayRight<'a> {
                     write!(f, "{}", param)?;
                 }
 
-                if is_const {
-                    write!(f, ") const{}", DisplayRight(r))
-                } else {
-                    write!(f, "){}", DisplayRight(r))
+                write!(f, "){}{}", qualifiers, DisplayRight(r))
+            }
+            Array(count, ref t) => write!(f, "[{}]{}", count, DisplayRight(t)),
+            Pointer(_, ref t) | Reference(_, ref t) | MemberPointer(_, _, ref t) => {
+                if t.is_compound() {
+                    write!(f, ")")?;
                 }
+                write!(f, "{}", DisplayRight(t))
             }
-            Array(count, ref t) => write!(f, ")[{}]{}", count, DisplayRight(t)),
-            Pointer(_, ref t) => write!(f, "{}", DisplayRight(t)),
-            Reference(_, ref t) => write!(f, "{}", DisplayRight(t)),
         }
     }
 }
@@ -110,32 +225,90 @@ fn parse_count(text: &str) -> Result<(usize, &str), Cow<'static, str>> {
     Ok((count, text))
 }
 
+/// Splits template arguments on the commas which aren't part of a nested template
+fn split_template_args(args: &str) -> Vec<&str> {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut split = Vec::new();
+    for (i, c) in args.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&args[start..]);
+    split
+}
+
+fn template_depth(text: &str) -> i32 {
+    text.chars().fold(0, |depth, c| match c {
+        '<' => depth + 1,
+        '>' => depth - 1,
+        _ => depth,
+    })
+}
+
+/// Demangles the template arguments of a name like `TVec3<f>`
+fn demangle_template(name: &str) -> Cow<'_, str> {
+    let Some(start) = name.find('<') else {
+        return name.into();
+    };
+    if !name.ends_with('>') {
+        return name.into();
+    }
+
+    let mut result = String::with_capacity(name.len());
+    result.push_str(&name[..start]);
+    result.push('<');
+    for (i, arg) in split_template_args(&name[start + 1..name.len() - 1])
+        .into_iter()
+        .enumerate()
+    {
+        if i != 0 {
+            result.push_str(", ");
+        }
+        match parse_type(arg) {
+            Ok((Some(typ), "")) => result.push_str(&typ.to_string()),
+            _ => result.push_str(arg),
+        }
+    }
+    result.push('>');
+    result.into()
+}
+
 fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
     let original_text = text;
     let mut text = text;
     let first_char = text.chars().next();
-    if first_char.is_some() {
-        text = &text[1..];
+    if let Some(c) = first_char {
+        text = &text[c.len_utf8()..];
     }
 
     let typ = match first_char {
-        Some('v') => Type::Normal(false, "void"),
-        Some('c') => Type::Normal(false, "i8"),
-        Some('s') => Type::Normal(false, "i16"),
-        Some('i') | Some('l') => Type::Normal(false, "i32"),
-        Some('x') => Type::Normal(false, "i64"),
-        Some('f') => Type::Normal(false, "f32"),
-        Some('d') => Type::Normal(false, "f64"),
-        Some('b') => Type::Normal(false, "bool"),
+        Some('v') => Type::Normal(Qualifiers::default(), "void".into()),
+        Some('c') => Type::Normal(Qualifiers::default(), "i8".into()),
+        Some('s') => Type::Normal(Qualifiers::default(), "i16".into()),
+        Some('i') | Some('l') => Type::Normal(Qualifiers::default(), "i32".into()),
+        Some('x') => Type::Normal(Qualifiers::default(), "i64".into()),
+        Some('f') => Type::Normal(Qualifiers::default(), "f32".into()),
+        Some('d') => Type::Normal(Qualifiers::default(), "f64".into()),
+        Some('r') => Type::Normal(Qualifiers::default(), "long double".into()),
+        Some('b') => Type::Normal(Qualifiers::default(), "bool".into()),
+        Some('w') => Type::Normal(Qualifiers::default(), "wchar_t".into()),
         Some('e') => Type::VarArgs,
         Some('0'..='9') => {
             let (count, remaining) = parse_count(original_text)?;
             text = remaining;
 
-            let type_name = &text[..count];
+            let type_name = text.get(..count).ok_or("Type name is out of bounds")?;
             text = &text[count..];
 
-            Type::Normal(false, type_name)
+            Type::Normal(Qualifiers::default(), demangle_template(type_name))
         }
         Some('F') => {
             let mut parameters = Vec::new();
@@ -161,16 +334,16 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
             };
 
             Type::Function(
-                false,
-                Box::new(typ.unwrap_or_else(|| Type::Normal(false, "void"))),
+                Qualifiers::default(),
+                Box::new(typ.unwrap_or_else(|| Type::Normal(Qualifiers::default(), "void".into()))),
                 parameters,
             )
         }
         Some('A') => {
             let (count, remaining) = parse_count(text)?;
-            text = remaining;
-
-            text = &text[1..];
+            text = remaining
+                .strip_prefix('_')
+                .ok_or("Expected _ after the Array count")?;
 
             let (typ, remaining) = parse_type(text)?;
             text = remaining;
@@ -184,20 +357,55 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
             let (typ, remaining) = parse_type(text)?;
             text = remaining;
 
-            Type::Pointer(false, Box::new(typ.ok_or("Expected Type of Pointer")?))
+            Type::Pointer(
+                Qualifiers::default(),
+                Box::new(typ.ok_or("Expected Type of Pointer")?),
+            )
         }
         Some('R') => {
             let (typ, remaining) = parse_type(text)?;
             text = remaining;
 
-            Type::Reference(false, Box::new(typ.ok_or("Expected Type of Reference")?))
+            Type::Reference(
+                Qualifiers::default(),
+                Box::new(typ.ok_or("Expected Type of Reference")?),
+            )
+        }
+        Some('M') => {
+            let (class, remaining) = parse_type(text)?;
+            text = remaining;
+            let class = class.ok_or("Expected Class of Member Pointer")?;
+            if !matches!(class, Type::Normal(..) | Type::Path(..)) {
+                return Err(format!("Unexpected Member Pointer Class {}", class).into());
+            }
+
+            let (typ, remaining) = parse_type(text)?;
+            text = remaining;
+            let mut typ = typ.ok_or("Expected Type of Member Pointer")?;
+
+            // Member functions take the object as a hidden first parameter
+            if let Type::Function(qualifiers, _, params) = &mut typ {
+                if let Some(Type::Pointer(_, this)) = params.first() {
+                    if let Type::Normal(this_qualifiers, name) = this.as_ref() {
+                        if name == "void" {
+                            *qualifiers = *this_qualifiers;
+                            params.remove(0);
+                        }
+                    }
+                }
+            }
+
+            Type::MemberPointer(Qualifiers::default(), Box::new(class), Box::new(typ))
         }
         Some('Q') => {
-            let skip_index = text.char_indices().nth(1).unwrap().0;
-            let (count, _) = parse_count(&text[..skip_index])?;
-            text = &text[skip_index..];
+            let count = text
+                .chars()
+                .next()
+                .and_then(|c| c.to_digit(10))
+                .ok_or("Expected count of Path Elements")?;
+            text = &text[1..];
 
-            let mut elements = Vec::with_capacity(count);
+            let mut elements = Vec::with_capacity(count as usize);
 
             for _ in 0..count {
                 let (typ, remaining) = parse_type(text)?;
@@ -211,34 +419,30 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
                 }
             }
 
-            Type::Path(false, elements)
+            Type::Path(Qualifiers::default(), elements)
         }
         Some('U') => {
             let first_char = text.chars().next();
             let first_char = first_char.ok_or("Expected some unsigned type")?;
-            text = &text[1..];
+            text = &text[first_char.len_utf8()..];
 
             match first_char {
-                'c' => Type::Normal(false, "u8"),
-                's' => Type::Normal(false, "u16"),
-                'i' | 'l' => Type::Normal(false, "u32"),
-                'x' => Type::Normal(false, "u64"),
+                'c' => Type::Normal(Qualifiers::default(), "u8".into()),
+                's' => Type::Normal(Qualifiers::default(), "u16".into()),
+                'i' | 'l' => Type::Normal(Qualifiers::default(), "u32".into()),
+                'x' => Type::Normal(Qualifiers::default(), "u64".into()),
                 c => return Err(format!("Unexpected unsigned type {}", c).into()),
             }
         }
-        Some('C') => {
+        Some(c @ ('C' | 'V')) => {
             let (typ, remaining) = parse_type(text)?;
             text = remaining;
-            let typ = typ.ok_or("Expected Constant Type")?;
-
-            match typ {
-                Type::Normal(_, name) => Type::Normal(true, name),
-                Type::Path(_, elements) => Type::Path(true, elements),
-                Type::Function(_, return_type, params) => Type::Function(true, return_type, params),
-                Type::Pointer(_, typ) => Type::Pointer(true, typ),
-                Type::Reference(_, typ) => Type::Reference(true, typ),
-                t => return Err(format!("Unexpected const type {}", t).into()),
-            }
+            let typ = typ.ok_or("Expected Qualified Type")?;
+
+            typ.qualify(Qualifiers {
+                is_const: c == 'C',
+                is_volatile: c == 'V',
+            })?
         }
         Some('S') => return parse_type(text),
         Some(c) => return Err(format!("Unexpected token {}", c).into()),
@@ -248,23 +452,26 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
     Ok((Some(typ), text))
 }
 
-fn base_name(function: &str) -> (&str, Option<&str>) {
-    if let Some(underscore_index) = function.rfind("__") {
-        (
-            &function[..underscore_index],
-            Some(&function[underscore_index + 2..]),
-        )
-    } else {
-        (function, None)
+fn demangle_base_name(base_name: &str) -> Result<Cow<'_, str>, Cow<'static, str>> {
+    if let Some(typ) = base_name.strip_prefix("__op") {
+        return match parse_type(typ)? {
+            (Some(typ), "") => Ok(format!("operator {}", typ).into()),
+            _ => Err(format!("Unexpected conversion operator {}", base_name).into()),
+        };
     }
+    if let Some((_, operator)) = OPERATORS.iter().find(|(m, _)| *m == base_name) {
+        return Ok(format!("operator{}", operator).into());
+    }
+    Ok(demangle_template(base_name))
 }
 
-pub fn demangle(function: &str) -> Result<Cow<str>, Cow<'static, str>> {
+/// Demangles the symbol, split between its base name and the mangled class and parameters
+fn demangle_parts(base_name: &str, text: &str) -> Result<String, Cow<'static, str>> {
     fn extend_by_params(signature: &mut String, typ: Type) -> Result<(), Cow<'static, str>> {
-        if let Type::Function(is_const, return_value, params) = typ {
+        if let Type::Function(qualifiers, return_value, params) = typ {
             signature.push('(');
             for (i, param) in params.iter().enumerate() {
-                if param == &Type::Normal(false, "void") {
+                if param.is_void() {
                     continue;
                 }
                 if i != 0 {
@@ -274,14 +481,12 @@ pub fn demangle(function: &str) -> Result<Cow<str>, Cow<'static, str>> {
             }
             signature.push(')');
 
-            if return_value.as_ref() != &Type::Normal(false, "void") {
+            if !return_value.is_void() {
                 signature.push_str(" -> ");
                 signature.push_str(&return_value.to_string());
             }
 
-            if is_const {
-                signature.push_str(" const");
-            }
+            signature.push_str(&qualifiers.to_string());
 
             Ok(())
         } else {
@@ -305,21 +510,14 @@ pub fn demangle(function: &str) -> Result<Cow<str>, Cow<'static, str>> {
         }
     }
 
-    let (base_name, path) = base_name(function);
-
-    if base_name.is_empty() {
-        return Ok(function.into());
-    }
-
-    if let Some(mut text) = path {
-        let mut result = String::new();
+    let mut text = text;
+    let mut result = String::new();
 
+    if text.starts_with(|c: char| c.is_ascii_digit() || c == 'Q') {
         let (typ, remaining) = parse_type(text)?;
         text = remaining;
 
-        let typ = typ.ok_or("Expected path")?;
-
-        match typ {
+        match typ.ok_or("Expected path")? {
             Type::Path(_, path) => {
                 for (i, element) in path.iter().enumerate() {
                     if i != 0 {
@@ -327,30 +525,639 @@ pub fn demangle(function: &str) -> Result<Cow<str>, Cow<'static, str>> {
                     }
                     result.push_str(element);
                 }
-                extend_by_base_name(&mut result, base_name);
+            }
+            Type::Normal(_, name) => result.push_str(&name),
+            t => return Err(format!("Unexpected class {}", t).into()),
+        }
+    } else if text.is_empty() {
+        return Err("Expected path".into());
+    }
 
-                let (typ, _) = parse_type(text)?;
-                if let Some(typ) = typ {
-                    extend_by_params(&mut result, typ)?;
+    if base_name == "__ct" || base_name == "__dt" {
+        extend_by_base_name(&mut result, base_name);
+    } else {
+        extend_by_base_name(&mut result, &demangle_base_name(base_name)?);
+    }
+
+    if !text.is_empty() {
+        let (typ, remaining) = parse_type(text)?;
+        if !remaining.is_empty() {
+            return Err(format!("Unexpected trailing characters {}", remaining).into());
+        }
+        extend_by_params(&mut result, typ.ok_or("Expected parameters")?)?;
+    }
+
+    Ok(result)
+}
+
+pub fn demangle(function: &str) -> Result<Cow<str>, Cow<'static, str>> {
+    let mut error = None;
+
+    // The base name may itself contain double underscores, so the first split which
+    // demangles as a whole wins
+    for (index, _) in function.match_indices("__") {
+        if index == 0 || template_depth(&function[..index]) != 0 {
+            continue;
+        }
+        match demangle_parts(&function[..index], &function[index + 2..]) {
+            Ok(demangled) => return Ok(demangled.into()),
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+    }
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(function.into()),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    Ident(String),
+    Number(usize),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &["::", "->", "...", "(", ")", "[", "]", ",", "*", "&", "~"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Cow<'static, str>> {
+    let mut tokens = Vec::new();
+    let mut text = text.trim_start();
+
+    while let Some(c) = text.chars().next() {
+        if c.is_ascii_alphabetic() || c == '_' {
+            let len = text
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(text.len());
+            let mut ident = text[..len].to_owned();
+            text = &text[len..];
+
+            if ident == "operator" {
+                let rest = text.trim_start();
+                if let Some(operator) = OPERATORS
+                    .iter()
+                    .map(|(_, o)| *o)
+                    .filter(|o| !o.starts_with(' '))
+                    .filter(|o| rest.starts_with(o))
+                    .max_by_key(|o| o.len())
+                {
+                    ident.push_str(operator);
+                    text = &rest[operator.len()..];
                 }
+            } else if text.starts_with('<') {
+                let mut depth = 0;
+                let len = text
+                    .char_indices()
+                    .find(|&(_, c)| {
+                        match c {
+                            '<' => depth += 1,
+                            '>' => depth -= 1,
+                            _ => {}
+                        }
+                        depth == 0
+                    })
+                    .map(|(i, _)| i + 1)
+                    .ok_or("Unclosed template arguments")?;
+                ident.push_str(&text[..len]);
+                text = &text[len..];
             }
-            Type::Normal(_, name) => {
-                result.push_str(name);
-                extend_by_base_name(&mut result, base_name);
+            tokens.push(Token::Ident(ident));
+        } else if c.is_ascii_digit() {
+            let (count, remaining) = parse_count(text)?;
+            tokens.push(Token::Number(count));
+            text = remaining;
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| text.starts_with(*s)) {
+            tokens.push(Token::Symbol(symbol));
+            text = &text[symbol.len()..];
+        } else {
+            return Err(format!("Unexpected character {}", c).into());
+        }
+        text = text.trim_start();
+    }
 
-                let (typ, _) = parse_type(text)?;
-                if let Some(typ) = typ {
-                    extend_by_params(&mut result, typ)?;
+    Ok(tokens)
+}
+
+/// Parses readable signatures, as written by the demangler or in C++
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.position + offset)
+    }
+
+    fn is_symbol(&self, offset: usize, symbol: &str) -> bool {
+        matches!(self.peek_at(offset), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn is_ident(&self, offset: usize, ident: &str) -> bool {
+        matches!(self.peek_at(offset), Some(Token::Ident(i)) if i == ident)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let is_symbol = self.is_symbol(0, symbol);
+        if is_symbol {
+            self.position += 1;
+        }
+        is_symbol
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Cow<'static, str>> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected {} but found {:?}", symbol, self.peek()).into())
+        }
+    }
+
+    fn parse_qualifiers(&mut self) -> Qualifiers {
+        let mut qualifiers = Qualifiers::default();
+        loop {
+            if self.is_ident(0, "const") {
+                qualifiers.is_const = true;
+            } else if self.is_ident(0, "volatile") {
+                qualifiers.is_volatile = true;
+            } else {
+                return qualifiers;
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Parses the `::` separated components of a name, stopping before `::*`
+    fn parse_name(&mut self) -> Result<Vec<String>, Cow<'static, str>> {
+        let mut components = Vec::new();
+        loop {
+            let is_destructor = self.eat("~");
+            let Some(Token::Ident(ident)) = self.peek().cloned() else {
+                return Err(format!("Expected name but found {:?}", self.peek()).into());
+            };
+            self.position += 1;
+
+            let mut component = if is_destructor {
+                format!("~{}", ident)
+            } else {
+                ident
+            };
+            if component == "operator" {
+                for operator in ["new", "delete"] {
+                    if self.is_ident(0, operator) {
+                        self.position += 1;
+                        component = format!("operator {}", operator);
+                        if self.is_symbol(0, "[") && self.is_symbol(1, "]") {
+                            self.position += 2;
+                            component.push_str("[]");
+                        }
+                    }
                 }
             }
-            t => {
-                extend_by_base_name(&mut result, base_name);
-                extend_by_params(&mut result, t)?;
+            components.push(component);
+
+            let continues = self.is_symbol(0, "::")
+                && (self.is_symbol(1, "~") || matches!(self.peek_at(1), Some(Token::Ident(_))));
+            if !continues {
+                return Ok(components);
+            }
+            self.position += 1;
+        }
+    }
+
+    fn parse_params(&mut self) -> Result<Vec<Type<'static>>, Cow<'static, str>> {
+        let mut params = Vec::new();
+        if self.eat(")") {
+            return Ok(params);
+        }
+        loop {
+            params.push(self.parse_type()?);
+            if self.eat(")") {
+                return Ok(params);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn parse_base_type(&mut self) -> Result<Type<'static>, Cow<'static, str>> {
+        const KEYWORDS: &[&str] = &["unsigned", "signed", "short", "long", "int", "char"];
+
+        if self.eat("...") {
+            return Ok(Type::VarArgs);
+        }
+
+        let mut qualifiers = self.parse_qualifiers();
+        let mut words = Vec::new();
+        while let Some(Token::Ident(ident)) = self.peek() {
+            if !KEYWORDS.contains(&ident.as_str()) {
+                break;
+            }
+            words.push(ident.clone());
+            self.position += 1;
+        }
+
+        let typ = if words.is_empty() {
+            let mut name = self
+                .parse_name()?
+                .into_iter()
+                .map(Cow::Owned)
+                .collect::<Vec<_>>();
+            if name.len() == 1 {
+                Type::Normal(Qualifiers::default(), name.remove(0))
+            } else {
+                Type::Path(Qualifiers::default(), name)
+            }
+        } else {
+            if words.len() > 1 {
+                words.retain(|w| w != "int");
+            }
+            Type::Normal(Qualifiers::default(), words.join(" ").into())
+        };
+
+        let after = self.parse_qualifiers();
+        qualifiers.is_const |= after.is_const;
+        qualifiers.is_volatile |= after.is_volatile;
+        typ.qualify(qualifiers)
+    }
+
+    fn parse_type(&mut self) -> Result<Type<'static>, Cow<'static, str>> {
+        let mut typ = self.parse_base_type()?;
+
+        loop {
+            if self.eat("*") {
+                typ = Type::Pointer(self.parse_qualifiers(), Box::new(typ));
+            } else if self.eat("&") {
+                typ = Type::Reference(self.parse_qualifiers(), Box::new(typ));
+            } else {
+                break;
+            }
+        }
+
+        if self.is_symbol(0, "(") {
+            // Pointer to a function or an array, like `void (*)(i32)` or `i32 (C::*)[4]`
+            self.position += 1;
+            let class = if self.is_symbol(0, "*") || self.is_symbol(0, "&") {
+                None
+            } else {
+                let mut name = self
+                    .parse_name()?
+                    .into_iter()
+                    .map(Cow::Owned)
+                    .collect::<Vec<_>>();
+                self.expect("::")?;
+                Some(if name.len() == 1 {
+                    Type::Normal(Qualifiers::default(), name.remove(0))
+                } else {
+                    Type::Path(Qualifiers::default(), name)
+                })
+            };
+            let is_reference = class.is_none() && self.eat("&");
+            if !is_reference {
+                self.expect("*")?;
+            }
+            let qualifiers = self.parse_qualifiers();
+            self.expect(")")?;
+
+            let pointee = if self.eat("[") {
+                let Some(Token::Number(count)) = self.peek().cloned() else {
+                    return Err("Expected Array count".into());
+                };
+                self.position += 1;
+                self.expect("]")?;
+                Type::Array(count, Box::new(typ))
+            } else {
+                self.expect("(")?;
+                let params = self.parse_params()?;
+                Type::Function(self.parse_qualifiers(), Box::new(typ), params)
+            };
+
+            typ = match class {
+                Some(class) => Type::MemberPointer(qualifiers, Box::new(class), Box::new(pointee)),
+                None if is_reference => Type::Reference(qualifiers, Box::new(pointee)),
+                None => Type::Pointer(qualifiers, Box::new(pointee)),
+            };
+        }
+
+        while self.eat("[") {
+            let Some(Token::Number(count)) = self.peek().cloned() else {
+                return Err("Expected Array count".into());
+            };
+            self.position += 1;
+            self.expect("]")?;
+            typ = Type::Array(count, Box::new(typ));
+        }
+
+        Ok(typ)
+    }
+}
+
+fn strip_template(name: &str) -> &str {
+    name.find('<').map_or(name, |i| &name[..i])
+}
+
+/// Mangles the template arguments of a name like `TVec3<f32>`
+fn mangle_name(name: &str) -> Result<String, Cow<'static, str>> {
+    let Some(start) = name.find('<') else {
+        return Ok(name.to_owned());
+    };
+
+    let mut result = name[..start].to_owned();
+    result.push('<');
+    for (i, arg) in split_template_args(&name[start + 1..name.len() - 1])
+        .into_iter()
+        .enumerate()
+    {
+        if i != 0 {
+            result.push(',');
+        }
+        let arg = arg.trim();
+        let typ = tokenize(arg).ok().and_then(|tokens| {
+            let mut parser = Parser {
+                tokens,
+                position: 0,
+            };
+            let typ = parser.parse_type().ok()?;
+            (parser.position == parser.tokens.len()).then_some(typ)
+        });
+        match typ {
+            Some(typ) => result.push_str(&mangle_type(&typ)?),
+            None => result.push_str(arg),
+        }
+    }
+    result.push('>');
+    Ok(result)
+}
+
+fn mangle_class(components: &[Cow<str>]) -> Result<String, Cow<'static, str>> {
+    let mut result = String::new();
+    if components.len() > 9 {
+        return Err("Too many nested names".into());
+    }
+    if components.len() > 1 {
+        result.push_str(&format!("Q{}", components.len()));
+    }
+    for component in components {
+        let name = mangle_name(component)?;
+        result.push_str(&format!("{}{}", name.len(), name));
+    }
+    Ok(result)
+}
+
+fn mangle_function(
+    qualifiers: Qualifiers,
+    return_type: Option<&Type>,
+    params: &[Type],
+    this: bool,
+) -> Result<String, Cow<'static, str>> {
+    let mut result = format!("{}F", if this { "" } else { qualifiers.mangle() });
+    if this {
+        result.push_str(if qualifiers.is_const { "PCv" } else { "Pv" });
+    }
+    let params = params.iter().filter(|p| !p.is_void()).collect::<Vec<_>>();
+    if params.is_empty() && !this {
+        result.push('v');
+    }
+    for param in params {
+        result.push_str(&mangle_type(param)?);
+    }
+    if let Some(return_type) = return_type {
+        result.push('_');
+        result.push_str(&mangle_type(return_type)?);
+    }
+    Ok(result)
+}
+
+fn mangle_type(typ: &Type) -> Result<String, Cow<'static, str>> {
+    Ok(match typ {
+        Type::Normal(qualifiers, name) => {
+            let fundamental = FUNDAMENTALS
+                .iter()
+                .find(|(_, names)| names.contains(&name.as_ref()));
+            match fundamental {
+                Some((mangled, _)) => format!("{}{}", qualifiers.mangle(), mangled),
+                None => format!(
+                    "{}{}",
+                    qualifiers.mangle(),
+                    mangle_class(std::slice::from_ref(name))?
+                ),
             }
         }
+        Type::Path(qualifiers, components) => {
+            format!("{}{}", qualifiers.mangle(), mangle_class(components)?)
+        }
+        Type::Function(qualifiers, return_type, params) => {
+            mangle_function(*qualifiers, Some(return_type), params, false)?
+        }
+        Type::Array(count, typ) => format!("A{}_{}", count, mangle_type(typ)?),
+        Type::Pointer(qualifiers, typ) => format!("{}P{}", qualifiers.mangle(), mangle_type(typ)?),
+        Type::Reference(qualifiers, typ) => {
+            format!("{}R{}", qualifiers.mangle(), mangle_type(typ)?)
+        }
+        Type::MemberPointer(qualifiers, class, typ) => {
+            let pointee = match typ.as_ref() {
+                Type::Function(function_qualifiers, return_type, params) => {
+                    mangle_function(*function_qualifiers, Some(return_type), params, true)?
+                }
+                t => mangle_type(t)?,
+            };
+            format!("{}M{}{}", qualifiers.mangle(), mangle_type(class)?, pointee)
+        }
+        Type::VarArgs => "e".to_owned(),
+    })
+}
+
+/// A readable signature split into the parts that get mangled
+pub(crate) struct Signature {
+    /// Classes and namespaces the symbol belongs to
+    pub class: Vec<String>,
+    /// Mangled base name, like `__ct` for constructors or `__pl` for `operator+`
+    pub base_name: String,
+    /// Qualifiers, parameters and return type, if the symbol is a function
+    pub function: Option<(Qualifiers, Vec<Type<'static>>, Option<Type<'static>>)>,
+}
+
+pub(crate) fn parse_signature(signature: &str) -> Result<Signature, Cow<'static, str>> {
+    let mut parser = Parser {
+        tokens: tokenize(signature)?,
+        position: 0,
+    };
+
+    let mut components = parser.parse_name()?;
+    let conversion = if components.last().map(String::as_str) == Some("operator") {
+        Some(parser.parse_type()?)
+    } else {
+        None
+    };
+
+    let function = if parser.eat("(") {
+        let params = parser.parse_params()?;
+        let qualifiers = parser.parse_qualifiers();
+        let return_type = if parser.eat("->") {
+            Some(parser.parse_type()?)
+        } else {
+            None
+        };
+        Some((qualifiers, params, return_type))
+    } else {
+        None
+    };
+    if let Some(token) = parser.peek() {
+        return Err(format!("Unexpected {:?}", token).into());
+    }
+
+    let name = components.pop().ok_or("Expected name")?;
+    let base_name = if let Some(typ) = conversion {
+        format!("__op{}", mangle_type(&typ)?)
+    } else if let Some(class) = name.strip_prefix('~') {
+        if components.is_empty() {
+            // Destructors are demangled as `~Path::Class()`
+            components.push(class.to_owned());
+        }
+        "__dt".to_owned()
+    } else if let Some(first) = components.first_mut().filter(|c| c.starts_with('~')) {
+        first.remove(0);
+        components.push(name);
+        "__dt".to_owned()
+    } else if components.last().map(|c| strip_template(c)) == Some(strip_template(&name)) {
+        "__ct".to_owned()
+    } else if let Some(operator) = name.strip_prefix("operator") {
+        OPERATORS
+            .iter()
+            .find(|(_, o)| o.trim_start() == operator.trim_start())
+            .map(|(m, _)| m.to_string())
+            .ok_or_else(|| format!("Unknown operator {}", name))?
+    } else {
+        mangle_name(&name)?
+    };
+
+    Ok(Signature {
+        class: components,
+        base_name,
+        function,
+    })
+}
 
-        Ok(result.into())
+/// Mangles a readable signature like `dAcPy_c::setDamage(int)` with the CodeWarrior scheme.
+/// Names which aren't functions or class members are returned as is.
+pub fn mangle(signature: &str) -> Result<String, Cow<'static, str>> {
+    let Signature {
+        class,
+        base_name,
+        function,
+    } = parse_signature(signature)?;
+
+    let class = class.into_iter().map(Cow::Owned).collect::<Vec<_>>();
+    let class = if class.is_empty() {
+        String::new()
     } else {
-        Ok(base_name.into())
+        mangle_class(&class)?
+    };
+
+    match function {
+        Some((qualifiers, params, return_type)) => Ok(format!(
+            "{}__{}{}",
+            base_name,
+            class,
+            mangle_function(qualifiers, return_type.as_ref(), &params, false)?
+        )),
+        None if class.is_empty() => Ok(base_name),
+        None => Ok(format!("{}__{}", base_name, class)),
+    }
+}
+
+/// Looks up a symbol of the game by its name as found in the symbol map, its mangled name or
+/// its readable signature, like `dAcPy_c::setDamage(int)`
+pub fn lookup(symbols: &HashMap<String, u32>, name: &str) -> Option<u32> {
+    if let Some(&address) = symbols.get(name) {
+        return Some(address);
+    }
+    if let Ok(demangled) = demangle(name) {
+        if let Some(&address) = symbols.get(demangled.as_ref()) {
+            return Some(address);
+        }
+    }
+    let mangled = mangle(name).ok()?;
+    if let Some(&address) = symbols.get(&mangled) {
+        return Some(address);
+    }
+    symbols.get(demangle(&mangled).ok()?.as_ref()).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{demangle, lookup, mangle};
+    use std::collections::HashMap;
+
+    #[test]
+    fn symbols_are_demangled() {
+        let cases = [
+            ("setDamage__7dAcPy_cFi", "dAcPy_c::setDamage(i32)"),
+            ("__ct__7dAcPy_cFv", "dAcPy_c()"),
+            ("__dt__Q23abc3defFv", "~abc::def()"),
+            ("getPos__4BaseCFv", "Base::getPos() const"),
+            ("wait__4BaseCVFv", "Base::wait() const volatile"),
+            ("sInstance__7dAcPy_c", "dAcPy_c::sInstance"),
+            ("OSReport__FPCce", "OSReport(i8 const*, ...)"),
+            ("__pl__3VecCFRC3Vec", "Vec::operator+(Vec const&) const"),
+            ("__opb__3VecCFv", "Vec::operator bool() const"),
+            ("__nwa__FUl", "operator new[](u32)"),
+            (
+                "set__Q29JGeometry8TVec3<f>Fff",
+                "JGeometry::TVec3<f32>::set(f32, f32)",
+            ),
+            ("call__FPFi_v", "call(void (*)(i32))"),
+            (
+                "call__FM6dActorFPCvPv_v",
+                "call(void (dActor::*)(void*) const)",
+            ),
+            ("fill__FPA4_i", "fill(i32 (*)[4])"),
+            ("OSInit", "OSInit"),
+        ];
+        for (mangled, demangled) in cases {
+            assert_eq!(demangle(mangled).unwrap(), demangled, "{}", mangled);
+        }
+    }
+
+    #[test]
+    fn signatures_are_mangled() {
+        let cases = [
+            ("dAcPy_c::setDamage(int)", "setDamage__7dAcPy_cFi"),
+            ("dAcPy_c::dAcPy_c()", "__ct__7dAcPy_cFv"),
+            ("abc::def::~def()", "__dt__Q23abc3defFv"),
+            ("Base::getPos() const", "getPos__4BaseCFv"),
+            ("OSReport(const char*, ...)", "OSReport__FPCce"),
+            ("Vec::operator+(const Vec&) const", "__pl__3VecCFRC3Vec"),
+            (
+                "JGeometry::TVec3<float>::set(f32, f32)",
+                "set__Q29JGeometry8TVec3<f>Fff",
+            ),
+            (
+                "call(void (dActor::*)(void*) const)",
+                "call__FM6dActorFPCvPv_v",
+            ),
+            ("dAcPy_c::sInstance", "sInstance__7dAcPy_c"),
+            ("OSInit", "OSInit"),
+        ];
+        for (signature, mangled) in cases {
+            assert_eq!(mangle(signature).unwrap(), mangled, "{}", signature);
+        }
+    }
+
+    #[test]
+    fn readable_signatures_are_looked_up() {
+        let mut symbols = HashMap::new();
+        symbols.insert("dAcPy_c::setDamage(i32)".to_owned(), 0x8000_3100);
+        assert_eq!(
+            lookup(&symbols, "dAcPy_c::setDamage(int)"),
+            Some(0x8000_3100)
+        );
+        assert_eq!(lookup(&symbols, "setDamage__7dAcPy_cFi"), Some(0x8000_3100));
+        assert_eq!(lookup(&symbols, "dAcPy_c::setDamage(float)"), None);
     }
 }
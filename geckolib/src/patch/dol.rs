@@ -1,14 +1,23 @@
 use crate::patch::assembler::Instruction;
 use async_std::io::{prelude::*, Read as AsyncRead, ReadExt, Seek as AsyncSeek};
 use byteorder::{ByteOrder, BE};
+use eyre::Context;
 use std::fmt::{self, Debug};
 
+/// Number of text sections a DOL header can describe
+pub const MAX_TEXT_SECTIONS: usize = 7;
+/// Number of data sections a DOL header can describe
+pub const MAX_DATA_SECTIONS: usize = 11;
+/// Largest gap that gets zero-filled to extend an existing section when the section table is full
+pub const MAX_SECTION_GAP: u32 = 0x1_0000;
+
+#[derive(Clone)]
 pub struct Section {
     pub address: u32,
     pub data: Box<[u8]>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct DolFile {
     pub text_sections: Vec<Section>,
     pub data_sections: Vec<Section>,
@@ -17,6 +26,30 @@ pub struct DolFile {
     pub entry_point: u32,
 }
 
+/// A patched address whose original value isn't the one the patch expects
+pub struct Mismatch {
+    pub address: u32,
+    pub expected: u32,
+    pub found: Option<u32>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.found {
+            Some(found) => write!(
+                formatter,
+                "{:08X}: expected {:08X} but found {:08X}",
+                self.address, self.expected, found
+            ),
+            None => write!(
+                formatter,
+                "{:08X}: expected {:08X} but the address isn't part of the DOL",
+                self.address, self.expected
+            ),
+        }
+    }
+}
+
 pub struct DolHeader {
     pub text_section_offsets: [u32; 7],
     pub data_section_offsets: [u32; 11],
@@ -29,6 +62,69 @@ pub struct DolHeader {
     pub entry_point: u32,
 }
 
+impl Section {
+    /// Address following the section, an error when it's past the 32-bit address space
+    pub fn end(&self) -> eyre::Result<u32> {
+        u32::try_from(self.data.len())
+            .ok()
+            .and_then(|len| self.address.checked_add(len))
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "The section at {:08x} of 0x{:x} bytes ends past the 32-bit address space",
+                    self.address,
+                    self.data.len()
+                )
+            })
+    }
+
+    /// Address following the section, even past the 32-bit address space
+    fn wide_end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        self.address <= address && (address as u64) < self.wide_end()
+    }
+
+    /// Whether the section holds the 4 bytes at `address`
+    fn contains_word(&self, address: u32) -> bool {
+        self.address <= address && address as u64 + 4 <= self.wide_end()
+    }
+
+    fn overlaps(&self, start: u32, end: u32) -> bool {
+        self.address < end && (start as u64) < self.wide_end()
+    }
+
+    /// Returns the number of bytes between this section and `other`, if they don't overlap
+    fn gap_to(&self, other: &Section) -> Option<u32> {
+        let (end, other_end) = (self.end().ok()?, other.end().ok()?);
+        if end <= other.address {
+            Some(other.address - end)
+        } else if other_end <= self.address {
+            Some(self.address - other_end)
+        } else {
+            None
+        }
+    }
+
+    /// Merges two non-overlapping sections, filling the space between them with zeros
+    fn merge(self, other: Section) -> Section {
+        let (low, high) = if self.address < other.address {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut data = Vec::with_capacity((high.address - low.address) as usize + high.data.len());
+        data.extend(low.data.iter());
+        data.resize((high.address - low.address) as usize, 0);
+        data.extend(high.data.iter());
+        Section {
+            address: low.address,
+            data: data.into_boxed_slice(),
+        }
+    }
+}
+
 impl Debug for Section {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(formatter, "{:x}", self.address)
@@ -93,6 +189,13 @@ async fn read_sections<R: AsyncRead + AsyncSeek + Unpin>(
         if length == 0 {
             break;
         }
+        if address.checked_add(length).is_none() {
+            eyre::bail!(
+                "The section at {:08x} of 0x{:x} bytes ends past the 32-bit address space",
+                address,
+                length
+            );
+        }
         let section_data = read(data, offset as usize, length as usize)
             .await?
             .into_boxed_slice();
@@ -105,12 +208,87 @@ async fn read_sections<R: AsyncRead + AsyncSeek + Unpin>(
     Ok(sections)
 }
 
+/// Inserts `section` into `sections`, merging it with a neighbouring section when they touch,
+/// or when the table is full and the gap to the closest section can be zero-filled.
+fn insert_section(
+    sections: &mut Vec<Section>,
+    others: &[Section],
+    section: Section,
+    max: usize,
+    kind: &str,
+) -> eyre::Result<()> {
+    if section.data.is_empty() {
+        return Ok(());
+    }
+    let end = section
+        .end()
+        .context(format!("Couldn't insert the {} section", kind))?;
+    if let Some(existing) = sections
+        .iter()
+        .chain(others.iter())
+        .find(|s| s.overlaps(section.address, end))
+    {
+        eyre::bail!(
+            "The {} section at {:08x} overlaps the existing section at {:08x}",
+            kind,
+            section.address,
+            existing.address
+        );
+    }
+
+    let closest = sections
+        .iter()
+        .enumerate()
+        .filter_map(|(index, s)| s.gap_to(&section).map(|gap| (index, gap)))
+        .filter(|&(index, gap)| {
+            let (start, gap_end) = if sections[index].address < section.address {
+                (section.address - gap, section.address)
+            } else {
+                (end, sections[index].address)
+            };
+            gap <= MAX_SECTION_GAP
+                && !sections
+                    .iter()
+                    .chain(others.iter())
+                    .any(|s| s.overlaps(start, gap_end))
+        })
+        .min_by_key(|&(_, gap)| gap);
+
+    match closest {
+        Some((index, gap)) if gap == 0 || sections.len() >= max => {
+            let existing = sections.remove(index);
+            let merged = existing.merge(section);
+            // The merged section may now touch another one
+            insert_section(sections, others, merged, max, kind)
+        }
+        _ if sections.len() < max => {
+            sections.push(section);
+            Ok(())
+        }
+        _ => eyre::bail!(
+            "All {} {} sections of the DOL are used and the {} section at {:08x} can't be merged \
+             into an existing one",
+            max,
+            kind,
+            kind,
+            section.address
+        ),
+    }
+}
+
 impl DolFile {
     pub async fn parse<R: AsyncRead + AsyncSeek + Unpin>(data: &mut R) -> eyre::Result<Self> {
         let text_sections = read_sections(data, 0x0, 0x48, 0x90, 7).await?;
         let data_sections = read_sections(data, 0x1c, 0x64, 0xac, 11).await?;
         let bss_address = read_u32(data, 0xd8).await?;
         let bss_size = read_u32(data, 0xdc).await?;
+        if bss_address.checked_add(bss_size).is_none() {
+            eyre::bail!(
+                "The BSS at {:08x} of 0x{:x} bytes ends past the 32-bit address space",
+                bss_address,
+                bss_size
+            );
+        }
         let entry_point = read_u32(data, 0xe0).await?;
 
         Ok(DolFile {
@@ -122,12 +300,116 @@ impl DolFile {
         })
     }
 
-    pub fn append(&mut self, other: DolFile) {
-        self.text_sections.extend(other.text_sections);
-        self.data_sections.extend(other.data_sections);
+    /// Adds the sections of `other` to this DOL.
+    ///
+    /// Sections are merged with an adjacent section when possible. When the section table is
+    /// full, the closest section of the same kind is extended over the new one instead. The BSS
+    /// range is shrunk (or split) so that it doesn't cover any of the new sections.
+    pub fn append(&mut self, other: DolFile) -> eyre::Result<()> {
+        for section in other.text_sections {
+            self.carve_bss(section.address, section.end()?)?;
+            insert_section(
+                &mut self.text_sections,
+                &self.data_sections,
+                section,
+                MAX_TEXT_SECTIONS,
+                "text",
+            )?;
+        }
+        for section in other.data_sections {
+            self.carve_bss(section.address, section.end()?)?;
+            insert_section(
+                &mut self.data_sections,
+                &self.text_sections,
+                section,
+                MAX_DATA_SECTIONS,
+                "data",
+            )?;
+        }
+        Ok(())
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Removes the range `start..end` from the BSS.
+    ///
+    /// When the range is in the middle of the BSS, the larger part is kept as the BSS and the
+    /// smaller one is turned into a zero-filled data section.
+    fn carve_bss(&mut self, start: u32, end: u32) -> eyre::Result<()> {
+        let bss_end = self.bss_address.checked_add(self.bss_size).ok_or_else(|| {
+            eyre::eyre!(
+                "The BSS at {:08x} of 0x{:x} bytes ends past the 32-bit address space",
+                self.bss_address,
+                self.bss_size
+            )
+        })?;
+        if start >= end || self.bss_size == 0 || end <= self.bss_address || bss_end <= start {
+            return Ok(());
+        }
+
+        let lower = (self.bss_address, start.max(self.bss_address));
+        let upper = (end.min(bss_end), bss_end);
+        let (kept, split) = if lower.1 - lower.0 >= upper.1 - upper.0 {
+            (lower, upper)
+        } else {
+            (upper, lower)
+        };
+        crate::debug!(
+            "Section {:08x}..{:08x} overlaps the BSS {:08x}..{:08x}",
+            start,
+            end,
+            self.bss_address,
+            bss_end
+        );
+
+        self.bss_address = kept.0;
+        self.bss_size = kept.1 - kept.0;
+
+        if split.0 < split.1 {
+            let zeroed = Section {
+                address: split.0,
+                data: vec![0; (split.1 - split.0) as usize].into_boxed_slice(),
+            };
+            let is_free = !self
+                .text_sections
+                .iter()
+                .chain(self.data_sections.iter())
+                .any(|s| s.overlaps(split.0, split.1));
+            if !is_free
+                || insert_section(
+                    &mut self.data_sections,
+                    &self.text_sections,
+                    zeroed,
+                    MAX_DATA_SECTIONS,
+                    "data",
+                )
+                .is_err()
+            {
+                // The game's own runtime still clears its BSS, so only the loader loses track of it
+                crate::warn!(
+                    "BSS range {:08x}..{:08x} is no longer declared in the DOL header",
+                    split.0,
+                    split.1
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        if self.text_sections.len() > MAX_TEXT_SECTIONS {
+            eyre::bail!(
+                "The DOL has {} text sections but only {} are supported",
+                self.text_sections.len(),
+                MAX_TEXT_SECTIONS
+            );
+        }
+        if self.data_sections.len() > MAX_DATA_SECTIONS {
+            eyre::bail!(
+                "The DOL has {} data sections but only {} are supported",
+                self.data_sections.len(),
+                MAX_DATA_SECTIONS
+            );
+        }
+
         let mut header = DolHeader::new();
         header.bss_address = self.bss_address;
         header.bss_size = self.bss_size;
@@ -162,19 +444,52 @@ impl DolFile {
         let mut bytes = header.to_bytes();
         bytes.extend(data);
 
-        bytes
+        Ok(bytes)
+    }
+
+    fn read_word(&self, address: u32) -> Option<u32> {
+        self.text_sections
+            .iter()
+            .chain(self.data_sections.iter())
+            .find(|d| d.contains_word(address))
+            .map(|d| BE::read_u32(&d.data[(address - d.address) as usize..]))
+    }
+
+    /// Lists the instructions whose expected original value doesn't match the DOL
+    pub fn verify(&self, instructions: &[Instruction]) -> Vec<Mismatch> {
+        instructions
+            .iter()
+            .filter_map(|instruction| {
+                let expected = instruction.expected?;
+                let found = self.read_word(instruction.address);
+                (found != Some(expected)).then_some(Mismatch {
+                    address: instruction.address,
+                    expected,
+                    found,
+                })
+            })
+            .collect()
     }
 
     pub fn patch(&mut self, instructions: &[Instruction]) -> eyre::Result<()> {
+        let mismatches = self.verify(instructions);
+        if !mismatches.is_empty() {
+            eyre::bail!(
+                "The game doesn't match the patch's expected values:\n{}",
+                mismatches
+                    .iter()
+                    .map(|m| m.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+
         for instruction in instructions {
             let section = self
                 .text_sections
                 .iter_mut()
                 .chain(self.data_sections.iter_mut())
-                .find(|d| {
-                    d.address <= instruction.address
-                        && d.address + d.data.len() as u32 > instruction.address
-                });
+                .find(|d| d.contains_word(instruction.address));
 
             if let Some(section) = section {
                 let index = (instruction.address - section.address) as usize;
@@ -252,3 +567,148 @@ impl DolHeader {
         data
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{DolFile, Section, MAX_TEXT_SECTIONS};
+    use crate::patch::assembler::Instruction;
+
+    fn section(address: u32, len: usize) -> Section {
+        Section {
+            address,
+            data: vec![0xAA; len].into_boxed_slice(),
+        }
+    }
+
+    fn full_dol() -> DolFile {
+        DolFile {
+            text_sections: (0..MAX_TEXT_SECTIONS as u32)
+                .map(|i| section(0x8000_0000 + i * 0x10_0000, 0x100))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn adjacent_sections_are_merged() {
+        let mut dol = full_dol();
+        let other = DolFile {
+            text_sections: vec![section(0x8000_0100, 0x20)],
+            ..Default::default()
+        };
+        dol.append(other).unwrap();
+        assert_eq!(dol.text_sections.len(), MAX_TEXT_SECTIONS);
+        assert!(dol
+            .text_sections
+            .iter()
+            .any(|s| s.address == 0x8000_0000 && s.data.len() == 0x120));
+        assert!(dol.to_bytes().is_ok());
+    }
+
+    #[test]
+    fn full_table_extends_closest_section() {
+        let mut dol = full_dol();
+        let other = DolFile {
+            text_sections: vec![section(0x8060_0200, 0x20)],
+            ..Default::default()
+        };
+        dol.append(other).unwrap();
+        let extended = dol
+            .text_sections
+            .iter()
+            .find(|s| s.address == 0x8060_0000)
+            .unwrap();
+        assert_eq!(extended.data.len(), 0x220);
+        assert_eq!(extended.data[0x150], 0);
+    }
+
+    #[test]
+    fn full_table_errors_when_too_far() {
+        let mut dol = full_dol();
+        let other = DolFile {
+            text_sections: vec![section(0x8100_0000, 0x20)],
+            ..Default::default()
+        };
+        assert!(dol.append(other).is_err());
+    }
+
+    #[test]
+    fn sections_past_the_address_space_are_errors() {
+        let mut dol = full_dol();
+        let other = DolFile {
+            text_sections: vec![section(0xFFFF_FF00, 0x200)],
+            ..Default::default()
+        };
+        assert!(dol.append(other).is_err());
+        assert!(section(0xFFFF_FF00, 0x100).end().is_err());
+    }
+
+    #[test]
+    fn words_past_the_sections_end_are_not_patched() {
+        let mut dol = DolFile {
+            text_sections: vec![section(0xFFFF_FF00, 0x100), section(0x8000_0000, 0x10)],
+            ..Default::default()
+        };
+        assert!(dol.text_sections[0].contains(0xFFFF_FFFF));
+        for address in [0xFFFF_FFFE, 0x8000_000E] {
+            let instruction = Instruction {
+                address,
+                data: 0x6000_0000,
+                expected: None,
+            };
+            assert!(dol.patch(&[instruction]).is_err());
+        }
+    }
+
+    #[test]
+    fn bss_is_split_around_new_sections() {
+        let mut dol = DolFile {
+            bss_address: 0x8040_0000,
+            bss_size: 0x1000,
+            ..Default::default()
+        };
+        let other = DolFile {
+            data_sections: vec![section(0x8040_0100, 0x100)],
+            ..Default::default()
+        };
+        dol.append(other).unwrap();
+        assert_eq!(dol.bss_address, 0x8040_0200);
+        assert_eq!(dol.bss_size, 0xE00);
+        // The lower part of the BSS becomes zeros merged with the new section
+        assert_eq!(dol.data_sections.len(), 1);
+        assert_eq!(dol.data_sections[0].address, 0x8040_0000);
+        assert_eq!(dol.data_sections[0].data.len(), 0x200);
+        assert!(dol.data_sections[0].data[..0x100].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn mismatching_expectations_prevent_patching() {
+        let mut dol = full_dol();
+        let instructions = [
+            Instruction {
+                address: 0x8000_0000,
+                data: 0x6000_0000,
+                expected: Some(0xAAAA_AAAA),
+            },
+            Instruction {
+                address: 0x8000_0004,
+                data: 0x6000_0000,
+                expected: Some(0x3860_0001),
+            },
+            Instruction {
+                address: 0x8100_0000,
+                data: 0x6000_0000,
+                expected: Some(0x3860_0001),
+            },
+        ];
+        let mismatches = dol.verify(&instructions);
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].found, Some(0xAAAA_AAAA));
+        assert_eq!(mismatches[1].found, None);
+
+        assert!(dol.patch(&instructions).is_err());
+        assert_eq!(dol.text_sections[0].data[0], 0xAA);
+        assert!(dol.patch(&instructions[..1]).is_ok());
+        assert_eq!(dol.text_sections[0].data[0], 0x60);
+    }
+}
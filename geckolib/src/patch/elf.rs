@@ -0,0 +1,520 @@
+use crate::patch::dol::{DolFile, Section};
+use byteorder::{ByteOrder, BE};
+use goblin::elf::{header, program_header, section_header, sym, Elf};
+use std::collections::HashMap;
+
+const ELF_HEADER_SIZE: usize = 0x34;
+const PROGRAM_HEADER_SIZE: usize = 0x20;
+const SECTION_HEADER_SIZE: usize = 0x28;
+const SYMBOL_SIZE: usize = 0x10;
+
+/// The start and end addresses of `len` bytes at `address`
+fn address_range(address: u64, len: u64) -> eyre::Result<(u32, u32)> {
+    u32::try_from(address)
+        .ok()
+        .zip(u32::try_from(len).ok())
+        .and_then(|(start, len)| Some((start, start.checked_add(len)?)))
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "The 0x{:x} bytes at {:08x} end past the 32-bit address space",
+                len,
+                address
+            )
+        })
+}
+
+/// Builds a DOL from a fully linked executable ELF.
+///
+/// The loadable segments are used when the ELF has program headers, otherwise the allocated
+/// sections are used. The uninitialized part of every segment is folded into the DOL's BSS.
+pub fn elf2dol(buf: &[u8]) -> eyre::Result<DolFile> {
+    let elf = Elf::parse(buf)?;
+    if elf.header.e_machine != header::EM_PPC {
+        eyre::bail!("The ELF isn't a PowerPC executable");
+    }
+    if elf.header.e_type != header::ET_EXEC {
+        eyre::bail!("The ELF isn't fully linked");
+    }
+
+    let mut sections = DolFile::default();
+    let mut bss: Option<(u32, u32)> = None;
+    let mut add_bss = |start: u32, end: u32| {
+        if start < end {
+            bss = Some(bss.map_or((start, end), |(s, e)| (s.min(start), e.max(end))));
+        }
+    };
+
+    let loads = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == program_header::PT_LOAD && ph.p_memsz > 0)
+        .collect::<Vec<_>>();
+    if !loads.is_empty() {
+        for ph in loads {
+            let (address, end) = address_range(ph.p_vaddr, ph.p_memsz)?;
+            let (_, file_end) = address_range(ph.p_vaddr, ph.p_filesz)?;
+            let data = buf
+                .get(ph.p_offset as usize..)
+                .and_then(|b| b.get(..ph.p_filesz as usize))
+                .ok_or_else(|| eyre::eyre!("Segment at {:08x} is out of bounds", address))?;
+            let section = Section {
+                address,
+                data: data.into(),
+            };
+            if ph.is_executable() {
+                sections.text_sections.push(section);
+            } else {
+                sections.data_sections.push(section);
+            }
+            add_bss(file_end, end);
+        }
+    } else {
+        for sh in &elf.section_headers {
+            if !sh.is_alloc() || sh.sh_size == 0 {
+                continue;
+            }
+            let (address, end) = address_range(sh.sh_addr, sh.sh_size)?;
+            if sh.sh_type == section_header::SHT_NOBITS {
+                add_bss(address, end);
+                continue;
+            }
+            let data = buf
+                .get(sh.file_range().unwrap_or_default())
+                .ok_or_else(|| eyre::eyre!("Section at {:08x} is out of bounds", address))?;
+            let section = Section {
+                address,
+                data: data.into(),
+            };
+            if sh.is_executable() {
+                sections.text_sections.push(section);
+            } else {
+                sections.data_sections.push(section);
+            }
+        }
+    }
+    sections.text_sections.sort_by_key(|s| s.address);
+    sections.data_sections.sort_by_key(|s| s.address);
+
+    let mut dol = DolFile::default();
+    dol.append(sections)
+        .map_err(|e| eyre::eyre!("Couldn't convert the ELF into a DOL: {}", e))?;
+    if let Some((start, end)) = bss {
+        dol.bss_address = start;
+        dol.bss_size = end - start;
+    }
+    dol.entry_point = elf.header.e_entry as u32;
+
+    Ok(dol)
+}
+
+struct ElfSection {
+    name: String,
+    kind: u32,
+    flags: u32,
+    address: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    align: u32,
+    entsize: u32,
+}
+
+impl ElfSection {
+    /// Address following the section, which may be past the 32-bit address space
+    fn end(&self) -> u64 {
+        self.address as u64 + self.size as u64
+    }
+
+    fn write(&self, buf: &mut Vec<u8>, name_offset: u32) {
+        let mut raw = [0u8; SECTION_HEADER_SIZE];
+        BE::write_u32_into(
+            &[
+                name_offset,
+                self.kind,
+                self.flags,
+                self.address,
+                self.offset,
+                self.size,
+                self.link,
+                self.info,
+                self.align,
+                self.entsize,
+            ],
+            &mut raw,
+        );
+        buf.extend_from_slice(&raw);
+    }
+}
+
+fn add_string(table: &mut Vec<u8>, name: &str) -> u32 {
+    let offset = table.len() as u32;
+    table.extend_from_slice(name.as_bytes());
+    table.push(0);
+    offset
+}
+
+fn align(buf: &mut Vec<u8>, alignment: usize) {
+    buf.resize(buf.len().div_ceil(alignment) * alignment, 0);
+}
+
+/// The parts of the BSS no section covers, as addresses and sizes. The BSS of retail DOLs
+/// covers their small data sections.
+fn bss_gaps(dol: &DolFile) -> Vec<(u32, u32)> {
+    let mut sections = dol
+        .text_sections
+        .iter()
+        .chain(&dol.data_sections)
+        .map(|s| (s.address as u64, s.address as u64 + s.data.len() as u64))
+        .collect::<Vec<_>>();
+    sections.sort_unstable();
+
+    let end = (dol.bss_address as u64 + dol.bss_size as u64).min(u32::MAX as u64);
+    let mut start = dol.bss_address as u64;
+    let mut gaps = Vec::new();
+    for (section_start, section_end) in sections {
+        if section_end <= start || end <= section_start {
+            continue;
+        }
+        if start < section_start {
+            gaps.push((start, section_start));
+        }
+        start = section_end;
+    }
+    if start < end {
+        gaps.push((start, end));
+    }
+    gaps.into_iter()
+        .map(|(start, end)| (start as u32, (end - start) as u32))
+        .collect()
+}
+
+/// Wraps a DOL into an executable ELF, so that it can be loaded into standard tools.
+///
+/// Every DOL section becomes an ELF section and a loadable segment, like every part of the BSS
+/// no section covers. `symbols` (for instance from
+/// [`framework_map::parse`](crate::patch::framework_map::parse)) is written as the symbol table.
+pub fn dol2elf(dol: &DolFile, symbols: &HashMap<String, u32>) -> Vec<u8> {
+    let bss = bss_gaps(dol);
+    let n_segments = dol.text_sections.len() + dol.data_sections.len() + bss.len();
+
+    let mut body = Vec::new();
+    let data_start = ELF_HEADER_SIZE + n_segments * PROGRAM_HEADER_SIZE;
+    let mut sections = vec![ElfSection {
+        name: String::new(),
+        kind: section_header::SHT_NULL,
+        flags: 0,
+        address: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 0,
+        entsize: 0,
+    }];
+
+    let loaded = dol
+        .text_sections
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (format!(".text{}", i), true, s))
+        .chain(
+            dol.data_sections
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (format!(".data{}", i), false, s)),
+        );
+    for (name, is_text, section) in loaded {
+        align(&mut body, 32);
+        sections.push(ElfSection {
+            name,
+            kind: section_header::SHT_PROGBITS,
+            flags: if is_text {
+                section_header::SHF_ALLOC | section_header::SHF_EXECINSTR
+            } else {
+                section_header::SHF_ALLOC | section_header::SHF_WRITE
+            },
+            address: section.address,
+            offset: (data_start + body.len()) as u32,
+            size: section.data.len() as u32,
+            link: 0,
+            info: 0,
+            align: 32,
+            entsize: 0,
+        });
+        body.extend_from_slice(&section.data);
+    }
+    for (i, &(address, size)) in bss.iter().enumerate() {
+        sections.push(ElfSection {
+            name: match bss.len() {
+                1 => ".bss".into(),
+                _ => format!(".bss{}", i),
+            },
+            kind: section_header::SHT_NOBITS,
+            flags: section_header::SHF_ALLOC | section_header::SHF_WRITE,
+            address,
+            offset: (data_start + body.len()) as u32,
+            size,
+            link: 0,
+            info: 0,
+            align: 32,
+            entsize: 0,
+        });
+    }
+    let n_loaded = sections.len();
+
+    // Symbols, sorted by address so that their size is the distance to the next one
+    let mut sorted = symbols.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; SYMBOL_SIZE];
+    for (i, &(name, &address)) in sorted.iter().enumerate() {
+        let section_index = sections[1..n_loaded]
+            .iter()
+            .position(|s| s.address <= address && (address as u64) < s.end())
+            .map(|i| i + 1);
+        let size = match section_index {
+            Some(index) => {
+                let end = sections[index].end();
+                let next = sorted[i + 1..]
+                    .iter()
+                    .map(|&(_, &a)| a as u64)
+                    .find(|&a| a > address as u64)
+                    .unwrap_or(end);
+                (next.min(end) - address as u64) as u32
+            }
+            None => 0,
+        };
+        let kind = match section_index {
+            Some(index) if sections[index].flags & section_header::SHF_EXECINSTR != 0 => {
+                sym::STT_FUNC
+            }
+            Some(_) => sym::STT_OBJECT,
+            None => sym::STT_NOTYPE,
+        };
+
+        let mut raw = [0u8; SYMBOL_SIZE];
+        BE::write_u32(&mut raw[0x0..], add_string(&mut strtab, name));
+        BE::write_u32(&mut raw[0x4..], address);
+        BE::write_u32(&mut raw[0x8..], size);
+        raw[0xC] = (sym::STB_GLOBAL << 4) | kind;
+        raw[0xD] = 0;
+        BE::write_u16(
+            &mut raw[0xE..],
+            section_index.map_or(section_header::SHN_ABS as u16, |i| i as u16),
+        );
+        symtab.extend_from_slice(&raw);
+    }
+
+    align(&mut body, 4);
+    let symtab_index = sections.len() as u32;
+    sections.push(ElfSection {
+        name: ".symtab".into(),
+        kind: section_header::SHT_SYMTAB,
+        flags: 0,
+        address: 0,
+        offset: (data_start + body.len()) as u32,
+        size: symtab.len() as u32,
+        link: symtab_index + 1,
+        // Only the null symbol is local
+        info: 1,
+        align: 4,
+        entsize: SYMBOL_SIZE as u32,
+    });
+    body.extend_from_slice(&symtab);
+    sections.push(ElfSection {
+        name: ".strtab".into(),
+        kind: section_header::SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: (data_start + body.len()) as u32,
+        size: strtab.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    body.extend_from_slice(&strtab);
+
+    let mut shstrtab = Vec::new();
+    let name_offsets = sections
+        .iter()
+        .map(|s| add_string(&mut shstrtab, &s.name))
+        .collect::<Vec<_>>();
+    let shstrtab_name = add_string(&mut shstrtab, ".shstrtab");
+    let shstrtab_index = sections.len();
+    sections.push(ElfSection {
+        name: ".shstrtab".into(),
+        kind: section_header::SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: (data_start + body.len()) as u32,
+        size: shstrtab.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    body.extend_from_slice(&shstrtab);
+    align(&mut body, 4);
+    let section_headers_offset = data_start + body.len();
+
+    // ELF header
+    let mut elf = vec![0u8; ELF_HEADER_SIZE];
+    elf[..4].copy_from_slice(header::ELFMAG);
+    elf[header::EI_CLASS] = header::ELFCLASS32;
+    elf[header::EI_DATA] = header::ELFDATA2MSB;
+    elf[header::EI_VERSION] = header::EV_CURRENT;
+    BE::write_u16(&mut elf[0x10..], header::ET_EXEC);
+    BE::write_u16(&mut elf[0x12..], header::EM_PPC);
+    BE::write_u32(&mut elf[0x14..], header::EV_CURRENT as u32);
+    BE::write_u32(&mut elf[0x18..], dol.entry_point);
+    BE::write_u32(&mut elf[0x1C..], ELF_HEADER_SIZE as u32);
+    BE::write_u32(&mut elf[0x20..], section_headers_offset as u32);
+    BE::write_u16(&mut elf[0x28..], ELF_HEADER_SIZE as u16);
+    BE::write_u16(&mut elf[0x2A..], PROGRAM_HEADER_SIZE as u16);
+    BE::write_u16(&mut elf[0x2C..], n_segments as u16);
+    BE::write_u16(&mut elf[0x2E..], SECTION_HEADER_SIZE as u16);
+    BE::write_u16(&mut elf[0x30..], sections.len() as u16);
+    BE::write_u16(&mut elf[0x32..], shstrtab_index as u16);
+
+    // Program headers
+    for section in &sections[1..n_loaded] {
+        let is_bss = section.kind == section_header::SHT_NOBITS;
+        let mut raw = [0u8; PROGRAM_HEADER_SIZE];
+        BE::write_u32_into(
+            &[
+                program_header::PT_LOAD,
+                section.offset,
+                section.address,
+                section.address,
+                if is_bss { 0 } else { section.size },
+                section.size,
+                if section.flags & section_header::SHF_EXECINSTR != 0 {
+                    program_header::PF_R | program_header::PF_X
+                } else {
+                    program_header::PF_R | program_header::PF_W
+                },
+                section.align,
+            ],
+            &mut raw,
+        );
+        elf.extend_from_slice(&raw);
+    }
+
+    elf.extend(body);
+    for (section, &name_offset) in sections.iter().zip(&name_offsets) {
+        section.write(&mut elf, name_offset);
+    }
+    sections[shstrtab_index].write(&mut elf, shstrtab_name);
+
+    elf
+}
+
+#[cfg(test)]
+mod test {
+    use super::{address_range, dol2elf, elf2dol};
+    use crate::patch::dol::{DolFile, Section};
+    use std::collections::HashMap;
+
+    #[test]
+    fn dol_round_trips_through_elf() {
+        let dol = DolFile {
+            text_sections: vec![Section {
+                address: 0x8000_3100,
+                data: vec![0x60, 0, 0, 0, 0x4E, 0x80, 0, 0x20].into_boxed_slice(),
+            }],
+            data_sections: vec![Section {
+                address: 0x8040_0000,
+                data: vec![1, 2, 3, 4].into_boxed_slice(),
+            }],
+            bss_address: 0x8040_0020,
+            bss_size: 0x100,
+            entry_point: 0x8000_3100,
+        };
+        let mut symbols = HashMap::new();
+        symbols.insert("__start".to_owned(), 0x8000_3100);
+        symbols.insert("data".to_owned(), 0x8040_0000);
+
+        let elf = dol2elf(&dol, &symbols);
+        let parsed = goblin::elf::Elf::parse(&elf).unwrap();
+        let names = parsed
+            .syms
+            .iter()
+            .filter_map(|s| parsed.strtab.get_at(s.st_name))
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"__start"));
+
+        let converted = elf2dol(&elf).unwrap();
+        assert_eq!(converted.entry_point, dol.entry_point);
+        assert_eq!(converted.bss_address, dol.bss_address);
+        assert_eq!(converted.bss_size, dol.bss_size);
+        assert_eq!(converted.text_sections[0].data, dol.text_sections[0].data);
+        assert_eq!(converted.data_sections[0].address, 0x8040_0000);
+    }
+
+    #[test]
+    fn bss_segments_skip_the_sections_it_covers() {
+        let dol = DolFile {
+            data_sections: vec![Section {
+                address: 0x8040_0800,
+                data: vec![1; 0x100].into_boxed_slice(),
+            }],
+            bss_address: 0x8040_0000,
+            bss_size: 0x1000,
+            entry_point: 0x8040_0800,
+            ..Default::default()
+        };
+        let elf = dol2elf(&dol, &HashMap::new());
+        let parsed = goblin::elf::Elf::parse(&elf).unwrap();
+        let mut loads = parsed
+            .program_headers
+            .iter()
+            .map(|ph| (ph.p_vaddr, ph.p_memsz))
+            .collect::<Vec<_>>();
+        loads.sort_unstable();
+        assert_eq!(
+            loads,
+            vec![
+                (0x8040_0000, 0x800),
+                (0x8040_0800, 0x100),
+                (0x8040_0900, 0x700)
+            ]
+        );
+
+        let converted = elf2dol(&elf).unwrap();
+        assert_eq!(converted.bss_address, dol.bss_address);
+        assert_eq!(converted.bss_size, dol.bss_size);
+    }
+
+    #[test]
+    fn symbols_at_the_end_of_the_address_space_are_sized() {
+        let dol = DolFile {
+            text_sections: vec![Section {
+                address: 0xFFFF_FF00,
+                data: vec![0x60; 0x100].into_boxed_slice(),
+            }],
+            entry_point: 0xFFFF_FF00,
+            ..Default::default()
+        };
+        let symbols = HashMap::from([("last".to_owned(), 0xFFFF_FF80)]);
+        let elf = dol2elf(&dol, &symbols);
+        let parsed = goblin::elf::Elf::parse(&elf).unwrap();
+        let last = parsed
+            .syms
+            .iter()
+            .find(|s| s.st_value == 0xFFFF_FF80)
+            .unwrap();
+        assert_eq!(last.st_size, 0x80);
+    }
+
+    #[test]
+    fn ranges_past_the_address_space_are_errors() {
+        assert_eq!(
+            address_range(0x8000_0000, 0x100).unwrap(),
+            (0x8000_0000, 0x8000_0100)
+        );
+        assert!(address_range(0xFFFF_FF00, 0x200).is_err());
+        assert!(address_range(0x1_0000_0000, 0).is_err());
+    }
+}
@@ -1,8 +1,10 @@
-use crate::config::Config;
+use crate::config::{Config, SymbolFormat};
 use crate::patch::demangle::demangle as demangle_tww;
-use crate::patch::linker::{LinkedSection, SectionKind};
+use crate::patch::dol::DolFile;
+use crate::patch::linker::{Linked, LinkedSection, SectionKind};
 use async_std::io::{Read as AsyncRead, ReadExt, Seek as AsyncSeek};
 use eyre::Context;
+use goblin::elf::{section_header, sym, Elf};
 use regex::{Captures, Regex};
 use rustc_demangle::demangle as demangle_rust;
 use std::collections::HashMap;
@@ -10,9 +12,32 @@ use std::fs::File;
 use std::io::{prelude::*, BufWriter};
 use std::str;
 
+/// Demangles a Rust symbol name, without its hash
+fn demangle_rust_name(name: &str) -> String {
+    let demangled = demangle_rust(name).to_string();
+    if demangled.len() >= 19 && &demangled[demangled.len() - 19..][..3] == "::h" {
+        demangled[..demangled.len() - 19].to_owned()
+    } else {
+        demangled
+    }
+}
+
+/// Formats of the game's symbol map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    CodeWarrior,
+    /// decomp-toolkit's `symbols.txt`
+    SymbolsTxt,
+    Elf,
+}
+
+/// Writes the symbol map of the Rom Hack's sections, followed by the game's symbols: the
+/// original map when it's a CodeWarrior map, its parsed symbols otherwise
 pub fn create(
     config: &Config,
-    original: Option<&[u8]>,
+    original: Option<(MapFormat, &[u8])>,
+    original_symbols: &HashMap<String, u32>,
+    dol: &DolFile,
     sections: &[LinkedSection],
 ) -> eyre::Result<()> {
     let path = match &config.build.map {
@@ -22,77 +47,532 @@ pub fn create(
 
     let mut file = BufWriter::new(File::create(path).context("Couldn't create the symbol map")?);
 
-    writeln!(file, ".text section layout")?;
+    for (kind, layout_name) in [
+        (SectionKind::TextSection, ".text"),
+        (SectionKind::DataSection, ".data"),
+        (SectionKind::BlockStartedBySymbol, ".bss"),
+    ] {
+        writeln!(file, "{} section layout", layout_name)?;
 
-    for section in sections {
-        let section_name_buf;
-        let section_name = section.section_name;
-        let section_name = if section_name.starts_with(".text.")
-            && section.kind == SectionKind::TextSection
-        {
-            section_name_buf = demangle_rust(&section_name[".text.".len()..]).to_string();
-            let mut section_name: &str = &section_name_buf;
-            if section_name.len() >= 19 && &section_name[section_name.len() - 19..][..3] == "::h" {
-                section_name = &section_name[..section_name.len() - 19];
+        for section in sections.iter().filter(|s| s.kind == kind) {
+            let section_name = match section.section_name.strip_prefix(".text.") {
+                Some(name) if section.kind == SectionKind::TextSection => demangle_rust_name(name),
+                _ => section.section_name.to_owned(),
+            };
+            writeln!(
+                file,
+                "  00000000 {:06x} {:08x}  4 {} \t{}",
+                section.len - section.sym_offset,
+                section.address + section.sym_offset,
+                section_name,
+                section.member_name
+            )?;
+
+            for (index, symbol) in section.symbols.iter().enumerate() {
+                let size = if symbol.size != 0 {
+                    symbol.size
+                } else {
+                    section.symbols[index + 1..]
+                        .iter()
+                        .map(|s| s.address)
+                        .find(|&a| a > symbol.address)
+                        .unwrap_or(section.address + section.len)
+                        - symbol.address
+                };
+                writeln!(
+                    file,
+                    "  {:08x} {:06x} {:08x}  4 {} \t{}",
+                    symbol.address - section.address,
+                    size,
+                    symbol.address,
+                    demangle_rust_name(symbol.name),
+                    section.member_name
+                )?;
             }
-            section_name
-        } else {
-            section_name
-        };
+        }
 
-        writeln!(
-            file,
-            "  00000000 {:06x} {:08x}  4 {} \t{}",
-            section.len - section.sym_offset,
-            section.address + section.sym_offset,
-            section_name,
-            section.member_name
-        )?;
+        writeln!(file)?;
     }
 
-    if let Some(original) = original {
-        let regex = Regex::new(r"(\s{2}\d\s)(.*)(\s{2}.*)").unwrap();
+    match original {
+        Some((MapFormat::CodeWarrior, original)) => {
+            let regex = Regex::new(r"(\s{2}\d\s)(.*)(\s{2}.*)").unwrap();
 
+            writeln!(file)?;
+
+            for line in str::from_utf8(original)?.lines() {
+                let line = regex.replace(line, |c: &Captures| {
+                    let demangled = demangle_tww(&c[2]);
+                    format!("{}{}{}", &c[1], demangled.unwrap_or(c[2].into()), &c[3])
+                });
+
+                writeln!(file, "{}", line)?;
+            }
+        }
+        Some(_) => {
+            writeln!(file)?;
+            let declared = original.map_or_else(HashMap::new, |(_, buf)| declarations(buf));
+            let symbols = symbol_table(original_symbols, &declared, None, dol);
+            write_game_symbols(&mut file, &symbols, dol)?;
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Writes the game's symbols in CodeWarrior map syntax, by the DOL section they belong to
+fn write_game_symbols<W: Write>(
+    file: &mut W,
+    symbols: &[Symbol],
+    dol: &DolFile,
+) -> eyre::Result<()> {
+    for (kind, layout_name) in [
+        (SymbolKind::Function, ".text"),
+        (SymbolKind::Object, ".data"),
+    ] {
+        writeln!(file, "{} section layout", layout_name)?;
+        for symbol in symbols.iter().filter(|s| s.kind == kind) {
+            let section_address = dol
+                .text_sections
+                .iter()
+                .chain(&dol.data_sections)
+                .find(|s| s.contains(symbol.address))
+                .map_or(symbol.address, |s| s.address);
+            writeln!(
+                file,
+                "  {:08x} {:06x} {:08x}  4 {} \tgame",
+                symbol.address - section_address,
+                symbol.size,
+                symbol.address,
+                symbol.name
+            )?;
+        }
         writeln!(file)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    pub kind: SymbolKind,
+}
+
+/// The size and kind a game's symbol is declared with in a `symbols.txt`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Declaration {
+    pub size: Option<u32>,
+    pub kind: Option<SymbolKind>,
+}
+
+/// Combines the game's symbols with the symbols of the linked Rom Hack, sorted by address.
+///
+/// The game's symbols are sized and kinded as `declared`. Otherwise their size is the distance
+/// to the next symbol, within the section of `dol` they belong to.
+pub fn symbol_table(
+    original: &HashMap<String, u32>,
+    declared: &HashMap<String, Declaration>,
+    linked: Option<&Linked>,
+    dol: &DolFile,
+) -> Vec<Symbol> {
+    let mut symbols = Vec::with_capacity(original.len());
+
+    if let Some(linked) = linked {
+        for section in &linked.sections {
+            let kind = if section.kind == SectionKind::TextSection {
+                SymbolKind::Function
+            } else {
+                SymbolKind::Object
+            };
+            for (index, symbol) in section.symbols.iter().enumerate() {
+                let size = if symbol.size != 0 {
+                    symbol.size
+                } else {
+                    section.symbols[index + 1..]
+                        .iter()
+                        .map(|s| s.address)
+                        .find(|&a| a > symbol.address)
+                        .unwrap_or(section.address + section.len)
+                        - symbol.address
+                };
+                symbols.push(Symbol {
+                    name: demangle_rust_name(symbol.name),
+                    address: symbol.address,
+                    size,
+                    kind,
+                });
+            }
+        }
+        // Linked symbols which aren't part of a section's symbols, if any
+        for (&name, &address) in &linked.symbol_table {
+            if !symbols.iter().any(|s| s.address == address) {
+                symbols.push(Symbol {
+                    name: demangle_rust_name(name),
+                    address,
+                    size: 0,
+                    kind: SymbolKind::Function,
+                });
+            }
+        }
+    }
+
+    let mut game = original.iter().collect::<Vec<_>>();
+    game.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+    for (index, (name, &address)) in game.iter().enumerate() {
+        let section = dol
+            .text_sections
+            .iter()
+            .map(|s| (s, SymbolKind::Function))
+            .chain(dol.data_sections.iter().map(|s| (s, SymbolKind::Object)))
+            .find(|(s, _)| s.contains(address));
+        let next = game[index + 1..]
+            .iter()
+            .map(|&(_, &a)| a)
+            .find(|&a| a > address);
+        let (inferred_size, inferred_kind) = match section {
+            Some((section, kind)) => (
+                next.unwrap_or(u32::MAX)
+                    .min(section.end().unwrap_or(u32::MAX))
+                    - address,
+                kind,
+            ),
+            None => (next.map_or(0, |next| next - address), SymbolKind::Object),
+        };
+        let declaration = declared.get(*name).copied().unwrap_or_default();
+        symbols.push(Symbol {
+            name: name.to_string(),
+            address,
+            size: declaration.size.unwrap_or(inferred_size),
+            kind: declaration.kind.unwrap_or(inferred_kind),
+        });
+    }
+
+    symbols.sort_by_key(|s| s.address);
+    symbols
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_dolphin<W: Write>(file: &mut W, symbols: &[Symbol]) -> eyre::Result<()> {
+    for (kind, layout_name) in [
+        (SymbolKind::Function, ".text"),
+        (SymbolKind::Object, ".data"),
+    ] {
+        writeln!(file, "{} section layout", layout_name)?;
+        for symbol in symbols.iter().filter(|s| s.kind == kind) {
+            writeln!(
+                file,
+                "{:08x} {:08x} {:08x} 0 {}",
+                symbol.address, symbol.size, symbol.address, symbol.name
+            )?;
+        }
         writeln!(file)?;
+    }
+    Ok(())
+}
 
-        for line in str::from_utf8(original)?.lines() {
-            let line = regex.replace(line, |c: &Captures| {
-                let demangled = demangle_tww(&c[2]);
-                format!("{}{}{}", &c[1], demangled.unwrap_or(c[2].into()), &c[3])
-            });
+fn write_ghidra<W: Write>(file: &mut W, symbols: &[Symbol]) -> eyre::Result<()> {
+    writeln!(file, "# Imports the symbols of the game and of the Rom Hack")?;
+    writeln!(file, "# @category Symbol")?;
+    writeln!(file, "from ghidra.program.model.symbol import SourceType")?;
+    writeln!(file, "from ghidra.program.model.symbol import SymbolUtilities")?;
+    writeln!(file)?;
+    writeln!(file, "symbols = [")?;
+    for symbol in symbols {
+        writeln!(
+            file,
+            "    (0x{:08x}, \"{}\", {}),",
+            symbol.address,
+            escape(&symbol.name),
+            if symbol.kind == SymbolKind::Function {
+                "True"
+            } else {
+                "False"
+            }
+        )?;
+    }
+    writeln!(file, "]")?;
+    writeln!(file)?;
+    writeln!(file, "for address, name, is_function in symbols:")?;
+    writeln!(file, "    addr = toAddr(address)")?;
+    writeln!(file, "    name = SymbolUtilities.replaceInvalidChars(name, True)")?;
+    writeln!(file, "    if is_function and getFunctionAt(addr) is None:")?;
+    writeln!(file, "        disassemble(addr)")?;
+    writeln!(file, "        createFunction(addr, None)")?;
+    writeln!(file, "    createLabel(addr, name, True, SourceType.IMPORTED)")?;
+    Ok(())
+}
 
-            writeln!(file, "{}", line)?;
+fn write_ida<W: Write>(file: &mut W, symbols: &[Symbol]) -> eyre::Result<()> {
+    writeln!(file, "#include <idc.idc>")?;
+    writeln!(file)?;
+    writeln!(file, "static main()")?;
+    writeln!(file, "{{")?;
+    for symbol in symbols {
+        if symbol.kind == SymbolKind::Function {
+            writeln!(file, "    add_func(0x{:08X}, BADADDR);", symbol.address)?;
         }
+        writeln!(
+            file,
+            "    set_name(0x{:08X}, \"{}\", SN_NOWARN | SN_NOCHECK);",
+            symbol.address,
+            escape(&symbol.name)
+        )?;
     }
+    writeln!(file, "}}")?;
+    Ok(())
+}
 
+/// Writes the symbol tables requested in the build section of the config
+pub fn export(config: &Config, symbols: &[Symbol]) -> eyre::Result<()> {
+    for export in &config.build.symbols {
+        let mut file = BufWriter::new(File::create(&export.path).context(format!(
+            "Couldn't create the symbol table \"{}\"",
+            export.path.display()
+        ))?);
+        match export.format {
+            SymbolFormat::Dolphin => write_dolphin(&mut file, symbols)?,
+            SymbolFormat::Ghidra => write_ghidra(&mut file, symbols)?,
+            SymbolFormat::Ida => write_ida(&mut file, symbols)?,
+        }
+        file.flush()?;
+    }
     Ok(())
 }
 
-pub async fn parse<R: AsyncRead + AsyncSeek + Unpin>(
-    buf: &mut R,
-) -> eyre::Result<HashMap<String, u32>> {
+fn demangle_game_name(name: &str) -> String {
+    demangle_tww(name)
+        .map(|n| n.into_owned())
+        .unwrap_or_else(|_| name.to_owned())
+}
+
+/// Parses a CodeWarrior linker map
+fn parse_codewarrior(text: &str) -> eyre::Result<HashMap<String, u32>> {
     let mut symbols = HashMap::new();
     let regex = Regex::new(r"\s{2}\w{8}\s\w{6}\s(\w{8}).{4}(.*)\s{2}").unwrap();
-    let text = {
-        let mut text = String::new();
-        std::pin::pin!(buf).read_to_string(&mut text).await?;
-        text
-    };
     for line in text.lines() {
         if let Some(captures) = regex.captures(line) {
             let name = captures.get(2).unwrap().as_str();
             if !name.starts_with('.') {
                 let address = u32::from_str_radix(captures.get(1).unwrap().as_str(), 16)?;
 
-                symbols.insert(
-                    demangle_tww(name)
-                        .map(|n| n.into_owned())
-                        .unwrap_or_else(|_| name.to_owned()),
-                    address,
-                );
+                symbols.insert(demangle_game_name(name), address);
             }
         }
     }
     Ok(symbols)
 }
+
+/// Parses a decomp-toolkit `symbols.txt`, made of lines like
+/// `name = .text:0x80003100; // type:function size:0x20`
+fn parse_symbols_txt(text: &str, regex: &Regex) -> eyre::Result<HashMap<String, u32>> {
+    let mut symbols = HashMap::new();
+    for captures in text.lines().filter_map(|line| regex.captures(line)) {
+        let name = captures.get(1).unwrap().as_str();
+        if !name.starts_with('.') {
+            let address = u32::from_str_radix(captures.get(2).unwrap().as_str(), 16)?;
+
+            symbols.insert(demangle_game_name(name), address);
+        }
+    }
+    Ok(symbols)
+}
+
+/// Reads the defined function and object symbols of an ELF file
+fn parse_elf(buf: &[u8]) -> eyre::Result<HashMap<String, u32>> {
+    let elf = Elf::parse(buf).context("Couldn't parse the ELF file")?;
+    let mut symbols = HashMap::new();
+    for symbol in elf.syms.iter() {
+        if symbol.st_shndx == section_header::SHN_UNDEF as usize
+            || !matches!(
+                symbol.st_type(),
+                sym::STT_FUNC | sym::STT_OBJECT | sym::STT_NOTYPE
+            )
+        {
+            continue;
+        }
+        match elf.strtab.get_at(symbol.st_name) {
+            Some(name) if !name.is_empty() && !name.starts_with('.') => {
+                symbols.insert(demangle_game_name(name), symbol.st_value as u32);
+            }
+            _ => {}
+        }
+    }
+    Ok(symbols)
+}
+
+fn symbols_txt_regex() -> Regex {
+    Regex::new(r"^\s*(\S+)\s*=\s*(?:[\w.]+:)?0x([0-9A-Fa-f]{1,8});").unwrap()
+}
+
+/// Reads the `type:` and `size:` attributes of the symbols of a decomp-toolkit `symbols.txt`.
+/// Other formats declare none.
+pub fn declarations(buf: &[u8]) -> HashMap<String, Declaration> {
+    let mut declared = HashMap::new();
+    if buf.starts_with(b"\x7fELF") {
+        return declared;
+    }
+
+    let text = String::from_utf8_lossy(buf);
+    let symbols_txt = symbols_txt_regex();
+    let attributes = Regex::new(r"\b(type|size):(\S+)").unwrap();
+    for line in text.lines() {
+        let (name, comment) = match (symbols_txt.captures(line), line.split_once("//")) {
+            (Some(captures), Some((_, comment))) => (captures.get(1).unwrap().as_str(), comment),
+            _ => continue,
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let mut declaration = Declaration::default();
+        for attribute in attributes.captures_iter(comment) {
+            match (&attribute[1], &attribute[2]) {
+                ("type", "function") => declaration.kind = Some(SymbolKind::Function),
+                ("type", "object") => declaration.kind = Some(SymbolKind::Object),
+                ("size", size) => {
+                    declaration.size = size
+                        .strip_prefix("0x")
+                        .and_then(|size| u32::from_str_radix(size, 16).ok())
+                }
+                _ => {}
+            }
+        }
+        if declaration != Declaration::default() {
+            declared.insert(demangle_game_name(name), declaration);
+        }
+    }
+    declared
+}
+
+/// Parses the game's symbols out of a CodeWarrior linker map, a decomp-toolkit `symbols.txt`
+/// or an ELF file. The format is detected from the content.
+pub async fn parse<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut R,
+) -> eyre::Result<HashMap<String, u32>> {
+    let buf = {
+        let mut buf = Vec::new();
+        std::pin::pin!(reader).read_to_end(&mut buf).await?;
+        buf
+    };
+    Ok(parse_map(&buf)?.1)
+}
+
+/// Parses the game's symbols like [`parse`], along with the format of the map
+pub fn parse_map(buf: &[u8]) -> eyre::Result<(MapFormat, HashMap<String, u32>)> {
+    if buf.starts_with(b"\x7fELF") {
+        return Ok((MapFormat::Elf, parse_elf(buf)?));
+    }
+
+    let text = String::from_utf8_lossy(buf);
+    let symbols_txt = symbols_txt_regex();
+    let (format, symbols) = if text.lines().any(|line| symbols_txt.is_match(line)) {
+        (
+            MapFormat::SymbolsTxt,
+            parse_symbols_txt(&text, &symbols_txt)?,
+        )
+    } else {
+        (MapFormat::CodeWarrior, parse_codewarrior(&text)?)
+    };
+    if symbols.is_empty() && !text.trim().is_empty() {
+        crate::warn!("No symbols found in the map, its format may not be supported");
+    }
+    Ok((format, symbols))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_txt_is_detected() {
+        let text = "fn_80003100 = .text:0x80003100; // type:function size:0x20\n\
+                    gameData = .data:0x803C0000; // type:object size:0x4\n\
+                    .text = .text:0x80003100; // type:label\n";
+        let symbols =
+            async_std::task::block_on(parse(&mut async_std::io::Cursor::new(text))).unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols["fn_80003100"], 0x8000_3100);
+        assert_eq!(symbols["gameData"], 0x803C_0000);
+    }
+
+    fn create_from(name: &str, map: &[u8]) -> String {
+        let dol = DolFile {
+            text_sections: vec![crate::patch::dol::Section {
+                address: 0x8000_3100,
+                data: vec![0; 0x100].into_boxed_slice(),
+            }],
+            data_sections: vec![crate::patch::dol::Section {
+                address: 0x8040_0000,
+                data: vec![0; 0x10].into_boxed_slice(),
+            }],
+            bss_address: 0x8040_0020,
+            bss_size: 0x100,
+            entry_point: 0x8000_3100,
+        };
+        let path = std::env::temp_dir().join(format!(
+            "geckolib-{}-{}.map",
+            name,
+            std::process::id()
+        ));
+        let config: Config = toml::from_str(&format!(
+            "[src]\niso = \"game.iso\"\n[build]\niso = \"out.iso\"\nmap = {:?}\n",
+            path
+        ))
+        .unwrap();
+
+        let (format, symbols) = parse_map(map).unwrap();
+        create(&config, Some((format, map)), &symbols, &dol, &[]).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (format, reparsed) = parse_map(written.as_bytes()).unwrap();
+        assert_eq!(format, MapFormat::CodeWarrior);
+        assert_eq!(reparsed, symbols);
+        written
+    }
+
+    #[test]
+    fn elf_maps_are_written_as_codewarrior_maps() {
+        let dol = DolFile {
+            text_sections: vec![crate::patch::dol::Section {
+                address: 0x8000_3100,
+                data: vec![0; 0x100].into_boxed_slice(),
+            }],
+            data_sections: Vec::new(),
+            bss_address: 0,
+            bss_size: 0,
+            entry_point: 0x8000_3100,
+        };
+        let mut symbols = HashMap::new();
+        symbols.insert("__start".to_owned(), 0x8000_3100);
+        symbols.insert("main".to_owned(), 0x8000_3180);
+        let elf = crate::patch::elf::dol2elf(&dol, &symbols);
+
+        let written = create_from("elf", &elf);
+        assert!(written.contains("  00000080 000080 80003180  4 main \tgame"));
+    }
+
+    #[test]
+    fn symbols_txt_maps_are_written_as_codewarrior_maps() {
+        let text = "fn_80003100 = .text:0x80003100; // type:function size:0x20\n\
+                    fn_80003180 = .text:0x80003180; // type:function\n\
+                    gameData = .data:0x80400004; // type:object size:0x4\n";
+
+        let written = create_from("symbols", text.as_bytes());
+        assert!(!written.contains("type:function"));
+        assert!(written.contains("  00000000 000020 80003100  4 fn_80003100 \tgame"));
+        assert!(written.contains("  00000080 000080 80003180  4 fn_80003180 \tgame"));
+        assert!(written.contains("  00000004 000004 80400004  4 gameData \tgame"));
+    }
+}
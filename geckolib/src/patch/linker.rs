@@ -1,3 +1,4 @@
+use crate::patch::demangle::lookup;
 use crate::patch::dol::{DolFile, Section};
 use crate::{info, UPDATER};
 use byteorder::{ByteOrder, BE};
@@ -80,6 +81,44 @@ pub struct LinkedSection<'a> {
     pub section_name: &'a str,
     pub sym_offset: u32,
     pub kind: SectionKind,
+    pub symbols: Vec<LinkedSymbol<'a>>,
+}
+
+pub struct LinkedSymbol<'a> {
+    pub name: &'a str,
+    pub address: u32,
+    pub size: u32,
+}
+
+fn named_symbols_for_section<'a>(
+    section_index: usize,
+    section_address: u32,
+    elf: &Elf<'a>,
+) -> Vec<LinkedSymbol<'a>> {
+    let mut symbols = elf
+        .syms
+        .iter()
+        .filter(|sym| {
+            section_index == sym.st_shndx
+                && matches!(
+                    sym.st_type(),
+                    sym::STT_FUNC | sym::STT_OBJECT | sym::STT_NOTYPE
+                )
+        })
+        .filter_map(|sym| {
+            let name = elf.strtab.get_at(sym.st_name)?;
+            if name.is_empty() || name.starts_with('.') {
+                return None;
+            }
+            Some(LinkedSymbol {
+                name,
+                address: section_address + sym.st_value as u32,
+                size: sym.st_size as u32,
+            })
+        })
+        .collect::<Vec<_>>();
+    symbols.sort_by_key(|s| s.address);
+    symbols
 }
 
 fn resolve_archive_symbol_to_member<'a, 'b: 'a>(
@@ -143,7 +182,7 @@ fn traverse_global<'a>(
                 parsed_elfs,
                 visited_sections,
             );
-        } else if !prelinked_symbols.contains_key(&symbol) {
+        } else if lookup(prelinked_symbols, &symbol).is_none() {
             return Err(eyre::eyre!("Unresolved symbol `{}`", symbol));
         }
     }
@@ -398,7 +437,7 @@ fn relocate_and_collect<'a>(
                                 located_section_address
                                     .wrapping_add(reloc.r_offset as u32)
                             );
-                            (prelinked_symbols[archive_symbol_name], 0)
+                            (lookup(prelinked_symbols, archive_symbol_name).unwrap(), 0)
                         }
                     });
 
@@ -556,6 +595,7 @@ pub fn link<'a>(
                     section_name,
                     kind: s.section_info.kind,
                     sym_offset,
+                    symbols: named_symbols_for_section(section_index, s.address, elf),
                 }
             })
             .collect(),
@@ -1,6 +1,9 @@
 pub mod assembler;
 pub mod banner;
+pub mod bindgen;
 pub mod demangle;
 pub mod dol;
+pub mod elf;
 pub mod framework_map;
-pub mod linker;
\ No newline at end of file
+pub mod linker;
+pub mod port;
\ No newline at end of file
@@ -0,0 +1,369 @@
+//! Locates the functions of a game's symbol map in another version of the same game.
+//!
+//! Functions are fingerprinted with masks that ignore the immediates which usually change
+//! between versions (branch targets, halves of absolute addresses, small data offsets).
+//! Functions whose fingerprint is found as is are ported first. The remaining ones are then
+//! searched for between their closest ported neighbours and scored by similarity.
+
+use crate::patch::dol::{DolFile, Section};
+use byteorder::{ByteOrder, BE};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// Functions shorter than this are too common to be told apart
+const MIN_FUNCTION_WORDS: usize = 3;
+/// Lowest share of matching instructions for a function found between its neighbours
+const MIN_SIMILARITY: f32 = 0.5;
+const HASH_BASE: u64 = 0x0000_0100_0000_01B3;
+const BLR: u32 = 0x4E80_0020;
+
+pub struct PortedSymbol {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    /// How sure the match is, from 0 to 1
+    pub confidence: f32,
+}
+
+/// Masks out the parts of an instruction which depend on where code and data got linked
+fn mask(instruction: u32) -> u32 {
+    match instruction >> 26 {
+        // b, bl
+        18 => instruction & 0xFC00_0003,
+        // addi, addis, ori, oris
+        14 | 15 | 24 | 25 => instruction & 0xFFFF_0000,
+        // Integer and float loads and stores
+        32..=55 => instruction & 0xFFFF_0000,
+        // psq_l, psq_lu, psq_st, psq_stu
+        56 | 57 | 60 | 61 => instruction & 0xFFFF_F000,
+        _ => instruction,
+    }
+}
+
+fn hash(words: &[u32]) -> u64 {
+    words.iter().fold(0, |hash, &word| {
+        hash.wrapping_mul(HASH_BASE).wrapping_add(word as u64 + 1)
+    })
+}
+
+fn similarity(a: &[u32], b: &[u32]) -> f32 {
+    let matching = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    matching as f32 / a.len().max(b.len()) as f32
+}
+
+struct Code {
+    address: u32,
+    raw: Vec<u32>,
+    words: Vec<u32>,
+    /// Hashes of every prefix of `words`, to hash any window in constant time
+    prefixes: Vec<u64>,
+}
+
+impl Code {
+    fn new(section: &Section) -> Self {
+        let raw = section
+            .data
+            .chunks_exact(4)
+            .map(BE::read_u32)
+            .collect::<Vec<_>>();
+        let words = raw.iter().copied().map(mask).collect::<Vec<_>>();
+        let mut prefixes = Vec::with_capacity(words.len() + 1);
+        prefixes.push(0);
+        for &word in &words {
+            let previous: u64 = *prefixes.last().unwrap();
+            prefixes.push(
+                previous
+                    .wrapping_mul(HASH_BASE)
+                    .wrapping_add(word as u64 + 1),
+            );
+        }
+        Self {
+            address: section.address,
+            raw,
+            words,
+            prefixes,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.address + self.words.len() as u32 * 4
+    }
+
+    fn contains(&self, address: u32) -> bool {
+        self.address <= address && address < self.end()
+    }
+
+    fn index(&self, address: u32) -> usize {
+        ((address - self.address) / 4) as usize
+    }
+
+    fn window_hash(&self, start: usize, len: usize, powers: &[u64]) -> u64 {
+        self.prefixes[start + len].wrapping_sub(self.prefixes[start].wrapping_mul(powers[len]))
+    }
+
+    /// Finds the addresses where functions most likely start: the targets of calls and the
+    /// instructions following returns and tail calls
+    fn function_starts(&self, code: &[Code], starts: &mut Vec<u32>) {
+        starts.push(self.address);
+        for (index, &instruction) in self.raw.iter().enumerate() {
+            let address = self.address + index as u32 * 4;
+            let is_branch = instruction >> 26 == 18 && instruction & 2 == 0;
+            if is_branch && instruction & 1 == 1 {
+                let offset = ((instruction & 0x03FF_FFFC) << 6) as i32 >> 6;
+                let target = address.wrapping_add(offset as u32);
+                if code.iter().any(|c| c.contains(target)) {
+                    starts.push(target);
+                }
+            } else if instruction == BLR || is_branch {
+                // Skip the padding between functions
+                if let Some(next) = self.raw[index + 1..].iter().position(|&w| w != 0) {
+                    starts.push(address + 4 + next as u32 * 4);
+                }
+            }
+        }
+    }
+}
+
+struct Function<'a> {
+    names: Vec<&'a str>,
+    words: &'a [u32],
+    section: usize,
+}
+
+/// Splits the text sections of `dol` into the functions named by `symbols`
+fn functions<'a>(code: &'a [Code], symbols: &'a HashMap<String, u32>) -> Vec<Function<'a>> {
+    let mut by_address = BTreeMap::<u32, Vec<&str>>::new();
+    for (name, &address) in symbols {
+        by_address.entry(address).or_default().push(name);
+    }
+
+    let mut functions = Vec::new();
+    for (section, code) in code.iter().enumerate() {
+        let mut symbols = by_address.range(code.address..code.end()).peekable();
+        while let Some((&address, names)) = symbols.next() {
+            let end = symbols.peek().map_or(code.end(), |(&next, _)| next);
+            let words = &code.words[code.index(address)..code.index(end)];
+            if words.len() >= MIN_FUNCTION_WORDS {
+                let mut names = names.clone();
+                names.sort_unstable();
+                functions.push(Function {
+                    names,
+                    words,
+                    section,
+                });
+            }
+        }
+    }
+    functions
+}
+
+/// Ports the functions of `symbols`, which belong to `source`, to `target`
+pub fn port(
+    source: &DolFile,
+    symbols: &HashMap<String, u32>,
+    target: &DolFile,
+) -> Vec<PortedSymbol> {
+    let source_code = source
+        .text_sections
+        .iter()
+        .map(Code::new)
+        .collect::<Vec<_>>();
+    let target_code = target
+        .text_sections
+        .iter()
+        .map(Code::new)
+        .collect::<Vec<_>>();
+    let functions = functions(&source_code, symbols);
+
+    let mut starts = Vec::new();
+    for code in &target_code {
+        code.function_starts(&target_code, &mut starts);
+    }
+    starts.sort_unstable();
+    starts.dedup();
+    let starts = starts
+        .into_iter()
+        .filter_map(|start| {
+            let section = target_code.iter().position(|c| c.contains(start))?;
+            Some((start, section))
+        })
+        .collect::<Vec<_>>();
+
+    let max_len = target_code.iter().map(|c| c.words.len()).max().unwrap_or(0);
+    let mut powers = Vec::with_capacity(max_len + 1);
+    powers.push(1u64);
+    for _ in 0..max_len {
+        powers.push(powers.last().unwrap().wrapping_mul(HASH_BASE));
+    }
+
+    // (address, confidence, target section) for each function
+    let mut matches: Vec<Option<(u32, f32, usize)>> = vec![None; functions.len()];
+
+    // Functions found as they are
+    let mut by_len = BTreeMap::<usize, Vec<usize>>::new();
+    for (index, function) in functions.iter().enumerate() {
+        by_len.entry(function.words.len()).or_default().push(index);
+    }
+    for (len, indices) in by_len {
+        let mut windows = HashMap::<u64, Vec<(u32, usize)>>::new();
+        for &(start, section) in &starts {
+            let code = &target_code[section];
+            let index = code.index(start);
+            if index + len <= code.words.len() {
+                windows
+                    .entry(code.window_hash(index, len, &powers))
+                    .or_default()
+                    .push((start, section));
+            }
+        }
+        for index in indices {
+            let function = &functions[index];
+            let Some(candidates) = windows.get(&hash(function.words)) else {
+                continue;
+            };
+            let candidates = candidates
+                .iter()
+                .filter(|(start, section)| {
+                    let code = &target_code[*section];
+                    let start = code.index(*start);
+                    code.words[start..start + len] == *function.words
+                })
+                .collect::<Vec<_>>();
+            if let Some(&&(start, section)) = candidates.first() {
+                matches[index] = Some((start, 1.0 / candidates.len() as f32, section));
+            }
+        }
+    }
+
+    // Functions that changed, searched for between their closest unambiguous neighbours
+    let anchors = matches
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| matches!(m, Some((_, confidence, _)) if *confidence == 1.0))
+        .map(|(index, m)| (index, m.unwrap()))
+        .collect::<Vec<_>>();
+    for index in 0..functions.len() {
+        if matches[index].is_some() {
+            continue;
+        }
+        let function = &functions[index];
+        let anchor = anchors.partition_point(|&(i, _)| i < index);
+        let Some(&(previous, (previous_address, _, section))) =
+            anchor.checked_sub(1).map(|a| &anchors[a])
+        else {
+            continue;
+        };
+        if functions[previous].section != function.section {
+            continue;
+        }
+        let code = &target_code[section];
+        let low = previous_address + functions[previous].words.len() as u32 * 4;
+        let high = match anchors.get(anchor) {
+            Some(&(next, (next_address, _, next_section)))
+                if functions[next].section == function.section && next_section == section =>
+            {
+                next_address
+            }
+            _ => code.end(),
+        };
+        if low >= high {
+            continue;
+        }
+
+        let first = starts.partition_point(|&(start, _)| start < low);
+        let last = starts.partition_point(|&(start, _)| start < high);
+        let mut best = (0.0, Vec::new());
+        for &(start, _) in &starts[first..last] {
+            let begin = code.index(start);
+            let end = (begin + function.words.len()).min(code.index(high));
+            let score = similarity(function.words, &code.words[begin..end]);
+            if score > best.0 {
+                best = (score, vec![start]);
+            } else if score == best.0 {
+                best.1.push(start);
+            }
+        }
+        if best.0 >= MIN_SIMILARITY {
+            matches[index] = Some((best.1[0], best.0 / best.1.len() as f32, section));
+        }
+    }
+
+    let mut ported = Vec::new();
+    for (function, found) in functions.iter().zip(matches) {
+        if let Some((address, confidence, _)) = found {
+            for name in &function.names {
+                ported.push(PortedSymbol {
+                    name: name.to_string(),
+                    address,
+                    size: function.words.len() as u32 * 4,
+                    confidence,
+                });
+            }
+        }
+    }
+    ported.sort_by(|a, b| a.address.cmp(&b.address).then(a.name.cmp(&b.name)));
+    ported
+}
+
+/// Writes the ported symbols as a CodeWarrior style map, with the confidence of each match
+/// in place of the object file
+pub fn write_map<W: Write>(file: &mut W, symbols: &[PortedSymbol]) -> io::Result<()> {
+    writeln!(file, ".text section layout")?;
+    writeln!(file, "  Starting        Virtual")?;
+    writeln!(file, "  address  Size   address")?;
+    writeln!(file, "  -----------------------")?;
+    for symbol in symbols {
+        writeln!(
+            file,
+            "  {:08x} {:06x} {:08x}  4 {} \tconfidence {:.3}",
+            0, symbol.size, symbol.address, symbol.name, symbol.confidence
+        )?;
+    }
+    writeln!(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(address: u32, words: &[u32]) -> Section {
+        Section {
+            address,
+            data: words.iter().flat_map(|w| w.to_be_bytes()).collect(),
+        }
+    }
+
+    #[test]
+    fn functions_are_found_after_relocation() {
+        // lis r3, 0x8040; addi r3, r3, 0x1234; bl +8; blr
+        let first = [0x3C60_8040, 0x3863_1234, 0x4800_0009, BLR];
+        // mflr r0; stw r0, 4(r1); lwz r3, -0x7000(r13); blr
+        let second = [0x7C08_02A6, 0x9001_0004, 0x806D_9000, BLR];
+        let source = DolFile {
+            text_sections: vec![section(0x8000_3100, &[first.as_slice(), &second].concat())],
+            ..Default::default()
+        };
+        let mut symbols = HashMap::new();
+        symbols.insert("first".to_owned(), 0x8000_3100);
+        symbols.insert("second".to_owned(), 0x8000_3110);
+
+        // Same code moved after a padded function, with different addresses and data offsets
+        let first = [0x3C60_8041, 0x3863_5678, 0x4800_0009, BLR];
+        let second = [0x7C08_02A6, 0x9001_0004, 0x806D_9100, BLR];
+        let target = DolFile {
+            text_sections: vec![section(
+                0x8000_3100,
+                &[[BLR, 0, 0, 0, 0, 0, 0, 0].as_slice(), &first, &second].concat(),
+            )],
+            ..Default::default()
+        };
+
+        let ported = port(&source, &symbols, &target);
+        assert_eq!(ported.len(), 2);
+        assert_eq!(ported[0].name, "first");
+        assert_eq!(ported[0].address, 0x8000_3120);
+        assert_eq!(ported[1].name, "second");
+        assert_eq!(ported[1].address, 0x8000_3130);
+        assert!(ported.iter().all(|s| s.confidence == 1.0));
+    }
+}
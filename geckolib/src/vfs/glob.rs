@@ -0,0 +1,218 @@
+//! Shell-like patterns matching the paths of the disc's files.
+//!
+//! `*` matches within a directory, `**` across directories, `?` a single character, `[abc]`
+//! or `[!abc]` a character of a set and `{a,b}` one of the alternatives. Every wildcard
+//! captures the text it matched, in order, to be substituted into another pattern.
+
+use eyre::Result;
+use regex::Regex;
+
+/// A compiled pattern
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim_start_matches('/');
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("((?:[^/]*/)*)");
+                    } else {
+                        regex.push_str("(.*)");
+                    }
+                }
+                '*' => regex.push_str("([^/]*)"),
+                '?' => regex.push_str("([^/])"),
+                '[' => {
+                    let mut class = String::from("([");
+                    if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                        class.push('^');
+                    }
+                    // A `]` right after the opening one is part of the set
+                    let mut closed = false;
+                    let mut first = true;
+                    for c in chars.by_ref() {
+                        match c {
+                            ']' if !first => {
+                                closed = true;
+                                break;
+                            }
+                            '\\' | '^' | ']' | '[' | '&' | '~' => {
+                                class.push('\\');
+                                class.push(c);
+                            }
+                            c => class.push(c),
+                        }
+                        first = false;
+                    }
+                    if !closed {
+                        eyre::bail!("Unterminated character class in \"{}\"", pattern);
+                    }
+                    class.push_str("])");
+                    regex.push_str(&class);
+                }
+                '{' => {
+                    let mut alternatives = String::new();
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                        alternatives.push(c);
+                    }
+                    let alternatives = alternatives
+                        .split(',')
+                        .map(regex::escape)
+                        .collect::<Vec<_>>();
+                    regex.push_str(&format!("({})", alternatives.join("|")));
+                }
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            regex: Regex::new(&regex)
+                .map_err(|err| eyre::eyre!("Invalid pattern \"{}\": {}", pattern, err))?,
+        })
+    }
+
+    /// Whether the pattern has wildcards, rather than only matching the path it spells
+    pub fn is_glob(pattern: &str) -> bool {
+        pattern.contains(['*', '?', '[', '{'])
+    }
+
+    /// The directories the pattern starts with, before any wildcard
+    pub fn base(&self) -> &str {
+        let end = self
+            .pattern
+            .find(['*', '?', '[', '{'])
+            .unwrap_or(self.pattern.len());
+        match self.pattern[..end].rfind('/') {
+            Some(slash) => &self.pattern[..slash],
+            None => "",
+        }
+    }
+
+    /// Fills `template` with the captures of a match: `$1`, `$2`… are replaced by the capture
+    /// with this number, and each wildcard of `template` by the next capture
+    pub fn substitute(template: &str, captures: &[String]) -> Result<String> {
+        let capture = |index: usize| {
+            captures.get(index).ok_or(eyre::eyre!(
+                "\"{}\" uses more wildcards than the pattern it's mapped to",
+                template
+            ))
+        };
+        let mut result = String::new();
+        let mut next = 0;
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                    let mut number = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        number.push(digit);
+                    }
+                    let number = number.parse::<usize>()?;
+                    result.push_str(capture(number.checked_sub(1).ok_or(eyre::eyre!(
+                        "Captures are numbered from $1 in \"{}\"",
+                        template
+                    ))?)?);
+                }
+                '*' | '?' | '[' | '{' => {
+                    match c {
+                        '*' if chars.next_if_eq(&'*').is_some() => {
+                            chars.next_if_eq(&'/');
+                        }
+                        '[' => while chars.next().is_some_and(|c| c != ']') {},
+                        '{' => while chars.next().is_some_and(|c| c != '}') {},
+                        _ => {}
+                    }
+                    result.push_str(capture(next)?);
+                    next += 1;
+                }
+                c => result.push(c),
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path.trim_start_matches('/'))
+    }
+
+    /// The text matched by each wildcard, in order
+    pub fn captures(&self, path: &str) -> Option<Vec<String>> {
+        let captures = self.regex.captures(path.trim_start_matches('/'))?;
+        Some(
+            captures
+                .iter()
+                .skip(1)
+                .map(|c| c.map_or("", |c| c.as_str()).to_owned())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_are_captured_and_substituted() {
+        let glob = Glob::new("/files/**/*.{thp,bnr}").unwrap();
+        assert!(glob.is_match("files/movie.thp"));
+        assert!(glob.is_match("files/a/b/movie.bnr"));
+        assert!(!glob.is_match("files/movie.arc"));
+        assert_eq!(
+            glob.captures("files/a/b/movie.thp").unwrap(),
+            vec!["a/b/", "movie", "thp"]
+        );
+
+        let glob = Glob::new("stage?/[!b]*").unwrap();
+        assert!(glob.is_match("stage1/a.arc"));
+        assert!(!glob.is_match("stage1/b.arc"));
+        assert!(!glob.is_match("stage12/a.arc"));
+        assert!(!Glob::is_glob("files/movie.thp"));
+
+        let glob = Glob::new("textures/**/*.png").unwrap();
+        assert_eq!(glob.base(), "textures");
+        let captures = glob.captures("textures/ui/icon.png").unwrap();
+        assert_eq!(
+            Glob::substitute("res/**/*.bti", &captures).unwrap(),
+            "res/ui/icon.bti"
+        );
+        assert_eq!(
+            Glob::substitute("res/$2_$1bti", &captures).unwrap(),
+            "res/icon_ui/bti"
+        );
+        assert!(Glob::substitute("res/*/*/*", &captures).is_err());
+    }
+
+    #[test]
+    fn character_classes_are_literal_sets() {
+        let glob = Glob::new("[]^a\\]*").unwrap();
+        assert!(glob.is_match("]x"));
+        assert!(glob.is_match("^x"));
+        assert!(glob.is_match("\\x"));
+        assert!(!glob.is_match("bx"));
+
+        let glob = Glob::new("[!]]").unwrap();
+        assert!(glob.is_match("a"));
+        assert!(!glob.is_match("]"));
+
+        assert!(Glob::new("files/[ab").is_err());
+    }
+}
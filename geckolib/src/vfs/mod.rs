@@ -1,8 +1,9 @@
 use crate::crypto::Unpackable;
 use crate::iso::consts::OFFSET_DOL_OFFSET;
 use crate::iso::disc::{align_addr, DiscType};
+use crate::iso::junk::JunkGenerator;
 use crate::iso::read::DiscReader;
-use crate::iso::write::DiscWriter;
+use crate::iso::write::{DiscWriter, WiiImagePatcher};
 use crate::iso::{consts, FstEntry, FstNode, FstNodeType};
 #[cfg(feature = "progress")]
 use crate::UPDATER;
@@ -12,17 +13,25 @@ use async_std::path::PathBuf;
 use async_std::sync::Arc;
 use byteorder::{ByteOrder, BE};
 use eyre::Result;
+use flate2::read::DeflateDecoder;
 use futures::AsyncWriteExt;
 #[cfg(feature = "progress")]
 use human_bytes::human_bytes;
-use num::ToPrimitive;
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
+use std::cmp::Ordering;
 use std::io::{Error, SeekFrom};
 use std::path::Path;
 #[cfg(feature = "progress")]
 use std::sync::TryLockError;
 use std::task::{Context, Poll};
+use zip::{CompressionMethod, ZipArchive};
+
+pub mod glob;
+pub mod overlay;
+pub mod placement;
+
+use placement::{Item, Placement};
 
 pub trait Node<R> {
     fn name(&self) -> String;
@@ -83,6 +92,24 @@ pub enum NodeEnum<R> {
 pub struct GeckoFS<R> {
     pub(super) root: Directory<R>,
     pub(super) system: Directory<R>,
+    placement: Placement,
+    padding: Padding,
+    /// Offsets of the DOL and of the FST on the original disc
+    system_offsets: Option<(u64, u64)>,
+}
+
+/// What `serialize` fills the space no file uses with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Padding {
+    #[default]
+    Zeros,
+    /// The junk data of retail discs, up to the end of a GameCube disc or of the last group of
+    /// a Wii partition. Wii output still differs from retail discs: the update partition is
+    /// dropped and the data partition is written again at 0x50000 and signed again.
+    Junk,
+    /// Zeros, and the groups of a Wii partition without files are left unencrypted, for the
+    /// smallest compressed image
+    Scrub,
 }
 
 impl<R> GeckoFS<R>
@@ -93,8 +120,21 @@ where
         Self {
             root: Directory::new(""),
             system: Directory::new("&&systemdata"),
+            placement: Placement::default(),
+            padding: Padding::default(),
+            system_offsets: None,
         }
     }
+
+    /// Sets the rules placing the files' data when the FileSystem is serialized
+    pub fn set_placement(&mut self, placement: Placement) {
+        self.placement = placement;
+    }
+
+    /// Sets what the space between the files is filled with when the FileSystem is serialized
+    pub fn set_padding(&mut self, padding: Padding) {
+        self.padding = padding;
+    }
 }
 
 impl<R> GeckoFS<R>
@@ -121,7 +161,15 @@ where
                 parent_dir: _,
                 next_dir_index,
             } => {
-                let dir = parent_dir.mkdir(relative_file_name.clone());
+                // The disc's order is kept, even when it isn't sorted
+                parent_dir
+                    .children
+                    .push(Box::new(Directory::new(relative_file_name.clone())));
+                let dir = parent_dir
+                    .children
+                    .last_mut()
+                    .and_then(|c| c.as_directory_mut())
+                    .unwrap();
 
                 while *cur_index < next_dir_index - 1 {
                     *cur_index += 1;
@@ -133,14 +181,14 @@ where
                 file_offset,
                 file_size,
             } => {
-                parent_dir.add_file(File::new(FileDataSource::Reader {
+                parent_dir.children.push(Box::new(File::new(FileDataSource::Reader {
                     reader: reader.clone(),
                     fst: FstNode::File {
                         relative_file_name,
                         file_offset,
                         file_size,
                     },
-                }));
+                })));
             }
         }
     }
@@ -148,7 +196,7 @@ where
     pub async fn parse(mut reader: DiscReader<R>) -> Result<Self> {
         let mut root = Directory::new("");
         let mut system = Directory::new("&&systemdata");
-        {
+        let system_offsets = {
             let is_wii = reader.get_type() == DiscType::Wii;
             crate::debug!(
                 "{}",
@@ -271,9 +319,22 @@ where
                 GeckoFS::get_dir_structure_recursive(&mut count, &fst_entries, &mut root, &reader);
                 count += 1;
             }
-        }
+            (dol_offset as u64, fst_offset)
+        };
         crate::debug!("{} children", root.children.len());
-        Ok(Self { root, system })
+        for path in root.unsorted_directories() {
+            crate::warn!(
+                "The FST isn't sorted in \"/{}\", added files may not be where the game expects",
+                path
+            );
+        }
+        Ok(Self {
+            root,
+            system,
+            placement: Placement::default(),
+            padding: Padding::default(),
+            system_offsets: Some(system_offsets),
+        })
     }
 
     /// Visits the directory tree to calculate the length of the FST table
@@ -295,13 +356,17 @@ where
 
     fn visitor_fst_entries(
         node: &mut dyn Node<R>,
+        path: &str,
         output_fst: &mut Vec<FstEntry>,
-        files: &mut Vec<(File<R>, u64)>,
+        files: &mut Vec<(usize, String, File<R>)>,
         fst_name_bank: &mut Vec<u8>,
         cur_parent_dir_index: usize,
-        offset: &mut u64,
         is_wii: bool,
     ) -> Result<()> {
+        let path = match path {
+            "" => node.name(),
+            parent => format!("{}/{}", parent, node.name()),
+        };
         match node.as_enum_mut() {
             NodeEnumMut::Directory(dir) => {
                 let fst_entry = FstEntry::new_directory(
@@ -321,11 +386,11 @@ where
                 for child in &mut dir.children {
                     GeckoFS::visitor_fst_entries(
                         child.as_mut(),
+                        &path,
                         output_fst,
                         files,
                         fst_name_bank,
                         this_dir_index,
-                        offset,
                         is_wii,
                     )?;
                 }
@@ -334,12 +399,10 @@ where
                 output_fst[this_dir_index].set_file_size_next_dir_index(next_dir_index);
             }
             NodeEnumMut::File(file) => {
-                let pos = align_addr(*offset, 5);
-                *offset = pos;
-
+                // The offset is set once every file is placed
                 let fst_entry = FstEntry::new_file(
                     fst_name_bank.len() as u32,
-                    pos as u64,
+                    0,
                     file.len()? as u32,
                     is_wii,
                 )?;
@@ -347,35 +410,40 @@ where
                 fst_name_bank.extend_from_slice(file.name().as_bytes());
                 fst_name_bank.push(0);
 
-                *offset += file.len()? as u64;
-                *offset = align_addr(*offset, 2);
-
+                files.push((output_fst.len(), path, file.clone()));
                 output_fst.push(fst_entry);
-                files.push((file.clone(), pos));
             }
         };
         Ok(())
     }
 
-    pub async fn serialize<W>(&mut self, writer: &mut DiscWriter<W>) -> Result<()>
-    where
-        W: AsyncWrite + AsyncSeek + Unpin,
-    {
-        crate::debug!("Serializing the FileSystem");
-        let is_wii = writer.get_type() == DiscType::Wii;
-        let mut pos: u64 = 0;
+    /// Lays out the system files and the FST, returning their serialized bytes along with the
+    /// files to write after them and their offsets
+    async fn prepare(&mut self, is_wii: bool) -> Result<(Vec<u8>, Vec<(File<R>, u64)>, Layout)> {
         let header_size = self.sys().get_file("iso.hdr")?.len()?;
         let apploader_size = self.sys().get_file("AppLoader.ldr")?.len()?;
 
         // Calculate dynamic offsets
         let dol_offset_raw = header_size + apploader_size;
-        let dol_offset = align_addr(dol_offset_raw, consts::DOL_ALIGNMENT_BIT);
-        let dol_padding_size = dol_offset - dol_offset_raw;
+        let original_offsets = self
+            .system_offsets
+            .filter(|_| self.placement.preserve_offsets);
+        // The DOL and the FST stay at their original offsets when they still fit there
+        let keep = |offset: usize, original: u64, bit: usize| match usize::try_from(original) {
+            Ok(original) if original >= offset && align_addr(original, bit) == original => original,
+            _ => offset,
+        };
+        let mut dol_offset = align_addr(dol_offset_raw, consts::DOL_ALIGNMENT_BIT);
+        if let Some((original, _)) = original_offsets {
+            dol_offset = keep(dol_offset, original, consts::DOL_ALIGNMENT_BIT);
+        }
         let dol_size = self.sys().get_file("Start.dol")?.len()?;
 
         let fst_list_offset_raw = dol_offset + dol_size;
-        let fst_list_offset = align_addr(fst_list_offset_raw, consts::FST_ALIGNMENT_BIT);
-        let fst_list_padding_size = fst_list_offset - fst_list_offset_raw;
+        let mut fst_list_offset = align_addr(fst_list_offset_raw, consts::FST_ALIGNMENT_BIT);
+        if let Some((_, original)) = original_offsets {
+            fst_list_offset = keep(fst_list_offset, original, consts::FST_ALIGNMENT_BIT);
+        }
 
         let fst_len = GeckoFS::visitor_fst_len(0, &self.root) - 1;
 
@@ -388,54 +456,38 @@ where
         let mut b = vec![0u8; 0x10];
         BE::write_u32_into(&d, &mut b);
 
-        // Write header and app loader
-        let mut buf = Vec::new();
+        // Header and app loader
+        let mut head = Vec::with_capacity(fst_list_offset + fst_len);
         self.sys_mut()
             .get_file_mut("iso.hdr")?
-            .read_to_end(&mut buf)
+            .read_to_end(&mut head)
             .await?;
-        writer.write_all(&buf[..OFFSET_DOL_OFFSET]).await?;
-        writer.write_all(&b).await?;
-        writer.write_all(&buf[OFFSET_DOL_OFFSET + 0x10..]).await?;
-        pos += buf.len().to_u64().ok_or(eyre::eyre!("Buffer too large"))?;
-        buf.clear();
+        head[OFFSET_DOL_OFFSET..OFFSET_DOL_OFFSET + 0x10].copy_from_slice(&b);
         self.sys_mut()
             .get_file_mut("AppLoader.ldr")?
-            .read_to_end(&mut buf)
+            .read_to_end(&mut head)
             .await?;
-        writer.write_all(&buf).await?;
-        pos += buf.len().to_u64().ok_or(eyre::eyre!("Buffer too large"))?;
-        writer.write_all(&vec![0u8; dol_padding_size]).await?;
-        pos += dol_padding_size
-            .to_u64()
-            .ok_or(eyre::eyre!("DOL padding too large"))?;
-
-        buf.clear();
+        head.resize(dol_offset, 0);
+
         self.sys_mut()
             .get_file_mut("Start.dol")?
-            .read_to_end(&mut buf)
+            .read_to_end(&mut head)
             .await?;
-        writer.write_all(&buf).await?;
-        pos += buf.len().to_u64().ok_or(eyre::eyre!("Buffer too large"))?;
-        writer.write_all(&vec![0u8; fst_list_padding_size]).await?;
-        pos += fst_list_padding_size
-            .to_u64()
-            .ok_or(eyre::eyre!("FST list padding too large"))?;
+        head.resize(fst_list_offset, 0);
 
         let mut output_fst = vec![FstEntry::new_directory(0, 0, 0, is_wii)?];
         let mut fst_name_bank = Vec::new();
-        let mut files = Vec::new();
+        let mut entries = Vec::new();
 
-        let mut offset = (fst_list_offset + fst_len) as u64;
         for node in self.root_mut().iter_mut() {
             let l = 0;
             GeckoFS::visitor_fst_entries(
                 node.as_mut(),
+                "",
                 &mut output_fst,
-                &mut files,
+                &mut entries,
                 &mut fst_name_bank,
                 l,
-                &mut offset,
                 is_wii,
             )?;
         }
@@ -443,83 +495,265 @@ where
             let next_dir_index = output_fst.len() as u32;
             output_fst[0].set_file_size_next_dir_index(next_dir_index);
         }
-        crate::debug!("output_fst size = {}", output_fst.len());
-        crate::debug!("first fst_name entry = {}", fst_name_bank[0]);
-        #[cfg(feature = "progress")]
-        let write_total_size: u64 = output_fst
+
+        // Place the files after the FST
+        let items = entries
             .iter()
-            .filter_map(|f| {
-                if let FstNodeType::File = f.get_node_type() {
-                    Some(f.get_file_size_next_dir_index() as u64)
-                } else {
-                    None
-                }
+            .map(|(_, path, file)| {
+                Ok(Item {
+                    path: path.clone(),
+                    len: file.len()? as u64,
+                    original: file.original_offset()?,
+                })
             })
-            .sum();
+            .collect::<Result<Vec<_>>>()?;
+        let offsets = self
+            .placement
+            .place((fst_list_offset + fst_len) as u64, &items);
+        let end = items
+            .iter()
+            .zip(&offsets)
+            .map(|(item, offset)| align_addr(offset + item.len, 2))
+            .fold((fst_list_offset + fst_len) as u64, u64::max);
+        Self::check_capacity(
+            self.padded_len(end, is_wii),
+            is_wii,
+            (fst_list_offset + fst_len) as u64,
+            items
+                .iter()
+                .filter(|item| item.original.is_none())
+                .map(|item| (item.path.as_str(), item.len)),
+        )?;
+        let mut files = Vec::with_capacity(entries.len());
+        for ((index, _, file), offset) in entries.into_iter().zip(offsets) {
+            output_fst[index].set_file_offset_parent_dir(offset, is_wii)?;
+            files.push((file, offset));
+        }
+        crate::debug!("output_fst size = {}", output_fst.len());
+        crate::debug!("first fst_name entry = {}", fst_name_bank[0]);
 
         for entry in output_fst {
-            writer.write_all(&entry.pack()).await?;
-            pos += FstEntry::BLOCK_SIZE as u64;
+            head.extend_from_slice(&entry.pack());
         }
+        head.extend_from_slice(&fst_name_bank);
+
+        let layout = Layout {
+            fst_offset: fst_list_offset as u64,
+            fst: head[fst_list_offset..].to_vec(),
+            // The Rom Hack's files are only hashed when `changes` compares them
+            files: items
+                .iter()
+                .map(|item| item.original.map_or(Fingerprint::Image, Fingerprint::Disc))
+                .collect(),
+            padding: self.padding,
+            len: self.padded_len(end, is_wii),
+        };
+        Ok((head, files, layout))
+    }
 
-        writer.write_all(&fst_name_bank).await?;
-        pos += fst_name_bank
-            .len()
-            .to_u64()
-            .ok_or(eyre::eyre!("Buffer too large"))?;
+    /// Fails when the image of `len` bytes of data doesn't fit on a disc, listing the largest
+    /// of the files the Rom Hack adds, and warns when a Wii image needs a dual-layer disc
+    fn check_capacity<'a>(
+        len: u64,
+        is_wii: bool,
+        system_len: u64,
+        additions: impl Iterator<Item = (&'a str, u64)>,
+    ) -> Result<()> {
+        let mib = |len: u64| format!("{:.1} MiB", len as f64 / (1024.0 * 1024.0));
+        let image_len = image_len(len, is_wii);
+        let (capacity, disc) = if is_wii {
+            (consts::WII_DUAL_LAYER_DISC_SIZE, "a dual-layer Wii disc")
+        } else {
+            (consts::GC_DISC_SIZE, "a GameCube disc")
+        };
+        if image_len > capacity {
+            let mut additions = additions.collect::<Vec<_>>();
+            additions.sort_by_key(|&(_, len)| std::cmp::Reverse(len));
+            let mut message = format!(
+                "The image takes {}, {} more than {} holds. Largest additions:",
+                mib(image_len),
+                mib(image_len - capacity),
+                disc
+            );
+            for (path, len) in additions.into_iter().take(10) {
+                message += &format!("\n  {}: {}", path, mib(len));
+            }
+            message += &format!("\n  System files and FST: {}", mib(system_len));
+            eyre::bail!(message);
+        }
+        if is_wii && image_len > consts::WII_DISC_SIZE {
+            crate::warn!(
+                "The image takes {}, more than a single-layer Wii disc holds, it needs a dual-layer disc",
+                mib(image_len)
+            );
+        }
+        Ok(())
+    }
 
-        // Traverse the root directory tree to write all the files in order
+    /// Size of the serialized disc whose files end at `end`
+    fn padded_len(&self, end: u64, is_wii: bool) -> u64 {
+        // The disc apparently needs to be aligned to 8 bits
+        let len = align_addr(end, 8);
+        match self.padding {
+            Padding::Zeros | Padding::Scrub => len,
+            Padding::Junk if is_wii => {
+                // Up to the end of the partition's last group of sectors
+                len.next_multiple_of(crate::crypto::consts::WII_SECTOR_DATA_SIZE as u64 * 64)
+            }
+            Padding::Junk => len.max(consts::GC_DISC_SIZE),
+        }
+    }
+
+    /// Writes `len` bytes of padding found at `offset`
+    async fn write_padding<W>(
+        writer: &mut W,
+        junk: &mut Option<JunkGenerator>,
+        offset: u64,
+        len: usize,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let Some(junk) = junk else {
+            writer.write_all(&vec![0u8; len]).await?;
+            return Ok(());
+        };
+        // The junk starts on a 4 bytes boundary
+        let zeros = std::cmp::min(len as u64, offset.next_multiple_of(4) - offset) as usize;
+        writer.write_all(&vec![0u8; zeros]).await?;
+        let mut offset = offset + zeros as u64;
+        let mut rem = len - zeros;
+        while rem > 0 {
+            let mut buf = vec![0u8; std::cmp::min(rem, 1024 * 1024)];
+            junk.fill(offset, &mut buf);
+            writer.write_all(&buf).await?;
+            offset += buf.len() as u64;
+            rem -= buf.len();
+        }
+        Ok(())
+    }
+
+    /// Computes where `serialize` places the files, to later rewrite only the ones that changed
+    pub async fn layout(&mut self, is_wii: bool) -> Result<Layout> {
+        Ok(self.prepare(is_wii).await?.2)
+    }
+
+    /// Copies a file from the FileSystem to the writer
+    async fn write_file<W>(file: &mut File<R>, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        // async_std::io::copy(file, writer).await?; // way too slow
         #[cfg(feature = "progress")]
+        let mut inc_buffer = 0usize;
+        let mut rem = file.len()?;
+        file.seek(SeekFrom::Start(0)).await?;
+        loop {
+            if rem == 0 {
+                break;
+            }
+            let transfer_size = std::cmp::min(rem, 1024 * 1024);
+            let mut buf = vec![0u8; transfer_size];
+            file.read_exact(&mut buf).await?;
+            writer.write_all(&buf).await?;
+            rem -= transfer_size;
+            #[cfg(feature = "progress")]
+            match UPDATER.try_lock() {
+                Ok(mut updater) => {
+                    updater.increment(transfer_size + inc_buffer)?;
+                    inc_buffer = 0;
+                }
+                Err(TryLockError::WouldBlock) => {
+                    inc_buffer += transfer_size;
+                }
+                _ => (),
+            }
+        }
+        #[cfg(feature = "progress")]
+        if inc_buffer > 0 {
+            if let Ok(mut updater) = UPDATER.lock() {
+                updater.increment(inc_buffer)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "progress")]
+    fn start_writing(files: &[&File<R>]) -> Result<()> {
+        let write_total_size = files
+            .iter()
+            .map(|file| file.len())
+            .sum::<Result<usize>>()?;
         if let Ok(mut updater) = UPDATER.lock() {
-            updater.set_len(write_total_size as usize)?;
+            updater.set_len(write_total_size)?;
             updater.set_title("Writing virtual FileSystem".to_string())?;
             updater.set_type(crate::update::UpdaterType::Progress)?;
         }
-        let mut offset = pos.to_usize().ok_or(eyre::eyre!("Offset too large"))?;
+        Ok(())
+    }
+
+    #[cfg(feature = "progress")]
+    fn report_file(file: &File<R>) -> Result<()> {
+        if let Ok(mut updater) = UPDATER.try_lock() {
+            updater.set_message(format!(
+                "{:<32.32} ({:>8})",
+                file.name(),
+                human_bytes(file.len()? as f64)
+            ))?;
+        }
+        Ok(())
+    }
+
+    pub async fn serialize<W>(&mut self, writer: &mut DiscWriter<W>) -> Result<()>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        self.serialize_with_layout(writer).await.map(|_| ())
+    }
+
+    /// Serializes the FileSystem and returns its layout, see [`GeckoFS::layout`]
+    pub async fn serialize_with_layout<W>(&mut self, writer: &mut DiscWriter<W>) -> Result<Layout>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        crate::debug!("Serializing the FileSystem");
+        let is_wii = writer.get_type() == DiscType::Wii;
+        let (head, mut files, layout) = self.prepare(is_wii).await?;
+        // Nothing is written before the files are known to fit on the disc
+        std::pin::Pin::new(&mut *writer).init().await?;
+        files.sort_by_key(|(_, offset)| *offset);
+
+        let mut junk = match self.padding {
+            Padding::Zeros | Padding::Scrub => None,
+            Padding::Junk => Some(JunkGenerator::from_header(&head)?),
+        };
+        if self.padding == Padding::Scrub {
+            let mut used = vec![(0, head.len() as u64)];
+            for (file, offset) in &files {
+                used.push((*offset, offset + file.len()? as u64));
+            }
+            writer.scrub(used).await;
+        }
+
+        // Write the header, app loader, DOL and FST
+        writer.write_all(&head).await?;
+        let pos = head.len();
+
+        // Traverse the root directory tree to write all the files in order
         #[cfg(feature = "progress")]
-        let mut inc_buffer = 0usize;
+        GeckoFS::start_writing(&files.iter().map(|(file, _)| file).collect::<Vec<_>>())?;
+        let mut offset = pos;
         for (mut file, file_offset) in files {
             #[cfg(feature = "progress")]
-            if let Ok(mut updater) = UPDATER.try_lock() {
-                updater.set_message(format!(
-                    "{:<32.32} ({:>8})",
-                    file.name(),
-                    human_bytes(file.len()? as f64)
-                ))?;
-            }
+            GeckoFS::report_file(&file)?;
             let padding_size = file_offset as usize - offset;
-            writer.write_all(&vec![0u8; padding_size]).await?;
-            // Copy the file from the FileSystem to the Writer.
-            // async_std::io::copy(file, writer).await?; // way too slow
-            let mut rem = file.len()?;
-            file.seek(SeekFrom::Start(0)).await?;
-            loop {
-                if rem == 0 {
-                    break;
-                }
-                let transfer_size = std::cmp::min(rem, 1024 * 1024);
-                let mut buf = vec![0u8; transfer_size];
-                file.read_exact(&mut buf).await?;
-                writer.write_all(&buf).await?;
-                rem -= transfer_size;
-                #[cfg(feature = "progress")]
-                match UPDATER.try_lock() {
-                    Ok(mut updater) => {
-                        updater.increment(transfer_size + inc_buffer)?;
-                        inc_buffer = 0;
-                    }
-                    Err(TryLockError::WouldBlock) => {
-                        inc_buffer += transfer_size;
-                    }
-                    _ => (),
-                }
-            }
+            Self::write_padding(writer, &mut junk, offset as u64, padding_size).await?;
+            GeckoFS::write_file(&mut file, writer).await?;
             offset = (file_offset + file.len()? as u64) as usize;
         }
 
-        // The disc apparently needs to be aligned to 8 bits
-        let padding_size = align_addr(offset as u64, 8) as usize - offset;
-        writer.write_all(&vec![0u8; padding_size]).await?;
+        let padding_size = layout.len() as usize - offset;
+        Self::write_padding(writer, &mut junk, offset as u64, padding_size).await?;
         //offset += padding_size; // Unececssary, but kept for clarity
 
         #[cfg(feature = "progress")]
@@ -530,6 +764,125 @@ where
         writer.flush().await?;
         writer.close().await?;
 
+        Ok(layout)
+    }
+
+    /// Lists what to rewrite in an image serialized with the `previous` layout so it holds this
+    /// FileSystem: the system files and the FST at offset 0, then the files whose data changed.
+    /// Returns `None` when files moved, which takes a full rebuild.
+    ///
+    /// The Rom Hack's files are hashed here only. When the layout was read from an image, the
+    /// files are compared with the image's data, including those read from the original disc
+    /// that the previous build may have replaced.
+    pub async fn changes<RImage>(
+        &mut self,
+        is_wii: bool,
+        previous: &Layout,
+        mut image: Option<&mut DiscReader<RImage>>,
+    ) -> Result<Option<(Layout, Vec<(u64, File<R>)>)>>
+    where
+        RImage: AsyncRead + AsyncSeek + Unpin,
+    {
+        let (head, files, mut layout) = self.prepare(is_wii).await?;
+        if !previous.is_compatible(&layout) {
+            return Ok(None);
+        }
+
+        let mut changes = vec![(
+            0,
+            File::new(FileDataSource::Box {
+                data: head.into_boxed_slice(),
+                name: "&&systemdata".to_owned(),
+            }),
+        )];
+        for ((mut file, offset), (new, old)) in files
+            .into_iter()
+            .zip(layout.files.iter_mut().zip(&previous.files))
+        {
+            *new = file.fingerprint()?;
+            let is_changed = match (&*new, old, image.as_deref_mut()) {
+                // The previous build may have replaced the original disc's file
+                (Fingerprint::Disc(_) | Fingerprint::Data(_), Fingerprint::Image, Some(image)) => {
+                    let len = file.len()?;
+                    let hash = match &*new {
+                        Fingerprint::Data(hash) => *hash,
+                        _ => {
+                            file.seek(SeekFrom::Start(0)).await?;
+                            hash_data(&mut file, len).await?
+                        }
+                    };
+                    image.seek(SeekFrom::Start(offset)).await?;
+                    hash_data(image, len).await? != hash
+                }
+                (new, old, _) => new != old,
+            };
+            if is_changed {
+                changes.push((offset, file));
+            }
+        }
+        crate::debug!("{} file(s) changed", changes.len() - 1);
+
+        Ok(Some((layout, changes)))
+    }
+
+    /// Writes the changes listed by [`GeckoFS::changes`] into a GameCube image
+    pub async fn write_changes<W>(writer: &mut W, changes: Vec<(u64, File<R>)>) -> Result<()>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        #[cfg(feature = "progress")]
+        GeckoFS::start_writing(&changes.iter().map(|(_, file)| file).collect::<Vec<_>>())?;
+        for (offset, mut file) in changes {
+            #[cfg(feature = "progress")]
+            GeckoFS::report_file(&file)?;
+            writer.seek(SeekFrom::Start(offset)).await?;
+            GeckoFS::write_file(&mut file, writer).await?;
+        }
+
+        #[cfg(feature = "progress")]
+        if let Ok(mut updater) = UPDATER.lock() {
+            updater.finish()?;
+        }
+
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Writes the changes listed by [`GeckoFS::changes`] into a Wii image
+    pub async fn write_changes_wii<RImage, W>(
+        patcher: &mut WiiImagePatcher<RImage, W>,
+        changes: Vec<(u64, File<R>)>,
+    ) -> Result<()>
+    where
+        RImage: AsyncRead + AsyncSeek + Unpin,
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        #[cfg(feature = "progress")]
+        GeckoFS::start_writing(&changes.iter().map(|(_, file)| file).collect::<Vec<_>>())?;
+        for (offset, mut file) in changes {
+            #[cfg(feature = "progress")]
+            GeckoFS::report_file(&file)?;
+            let mut pos = 0;
+            let len = file.len()?;
+            file.seek(SeekFrom::Start(0)).await?;
+            while pos < len {
+                let transfer_size = std::cmp::min(len - pos, 1024 * 1024);
+                let mut buf = vec![0u8; transfer_size];
+                file.read_exact(&mut buf).await?;
+                patcher.write_at(offset + pos as u64, &buf).await?;
+                pos += transfer_size;
+                #[cfg(feature = "progress")]
+                if let Ok(mut updater) = UPDATER.lock() {
+                    updater.increment(transfer_size)?;
+                }
+            }
+        }
+
+        #[cfg(feature = "progress")]
+        if let Ok(mut updater) = UPDATER.lock() {
+            updater.finish()?;
+        }
+
         Ok(())
     }
 
@@ -548,6 +901,38 @@ where
     pub fn root_mut(&mut self) -> &mut Directory<R> {
         &mut self.root
     }
+
+    /// Removes the file or directory at `path` of the disc's files
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Result<Box<dyn Node<R>>> {
+        self.root.remove(path)
+    }
+
+    /// Renames the file or directory at `path` of the disc's files
+    pub fn rename<P: AsRef<Path>>(&mut self, path: P, name: &str) -> Result<()> {
+        self.root.rename(path, name)
+    }
+
+    /// Moves the file or directory at `from` of the disc's files to `to`
+    pub fn move_node<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> Result<()> {
+        self.root.move_node(from, to)
+    }
+}
+
+impl<R> GeckoFS<R>
+where
+    R: Clone + 'static,
+{
+    /// Copies the FileSystem without sharing the files' data, to build from a pristine copy
+    /// of the game multiple times
+    pub fn deep_clone(&self) -> Result<Self> {
+        Ok(Self {
+            root: self.root.deep_clone()?,
+            system: self.system.deep_clone()?,
+            placement: self.placement.clone(),
+            padding: self.padding,
+            system_offsets: self.system_offsets,
+        })
+    }
 }
 
 impl<R> Default for GeckoFS<R>
@@ -596,6 +981,192 @@ where
     }
 }
 
+/// What a file's data is, to tell whether it changed between two builds
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Fingerprint {
+    /// Data read from the original disc, at this offset
+    Disc(u64),
+    /// SHA-1 of data provided by the Rom Hack
+    Data([u8; 20]),
+    /// Data of an image, only hashed when compared
+    Image,
+}
+
+/// SHA-1 of the next `len` bytes of `reader`
+async fn hash_data<T: AsyncRead + Unpin>(reader: &mut T, len: usize) -> Result<[u8; 20]> {
+    let mut digest = sha1_smol::Sha1::new();
+    let mut rem = len;
+    while rem > 0 {
+        let mut buf = vec![0u8; std::cmp::min(rem, 1024 * 1024)];
+        reader.read_exact(&mut buf).await?;
+        digest.update(&buf);
+        rem -= buf.len();
+    }
+    Ok(digest.digest().bytes())
+}
+
+/// Size of the image of `len` bytes of serialized data
+fn image_len(len: u64, is_wii: bool) -> u64 {
+    if is_wii {
+        // The partition's data is hashed and encrypted by groups of 64 sectors
+        let group_data_size = crate::crypto::consts::WII_SECTOR_DATA_SIZE as u64 * 64;
+        let group_size = crate::crypto::consts::WII_SECTOR_SIZE as u64 * 64;
+        consts::WII_PARTITION_OFFSET
+            + consts::WII_PARTITION_DATA_OFFSET
+            + len.div_ceil(group_data_size) * group_size
+    } else {
+        len
+    }
+}
+
+/// A file of the FST and where its data is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacedFile {
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Where a serialized disc's files are placed, to rewrite only the files a later build changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    fst_offset: u64,
+    fst: Vec<u8>,
+    files: Vec<Fingerprint>,
+    padding: Padding,
+    len: u64,
+}
+
+impl Layout {
+    /// Reads the layout of an existing image from its FST, assuming it's padded with zeros
+    pub async fn read<R>(reader: &mut DiscReader<R>) -> Result<Self>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+    {
+        let shift = if reader.get_type() == DiscType::Wii { 2 } else { 0 };
+        let mut buf = [0u8; 8];
+        reader
+            .seek(SeekFrom::Start(consts::OFFSET_FST_OFFSET as u64))
+            .await?;
+        reader.read_exact(&mut buf).await?;
+        let fst_offset = (BE::read_u32(&buf) as u64) << shift;
+        let fst_size = (BE::read_u32(&buf[4..]) as usize) << shift;
+        let mut fst = vec![0u8; fst_size];
+        reader.seek(SeekFrom::Start(fst_offset)).await?;
+        reader.read_exact(&mut fst).await?;
+
+        if fst.len() < FstEntry::BLOCK_SIZE {
+            eyre::bail!("The previous image's FST is too short to hold its root");
+        }
+        let num_entries = BE::read_u32(&fst[8..]) as usize;
+        let entries_len = num_entries.saturating_mul(FstEntry::BLOCK_SIZE);
+        if entries_len > fst.len() {
+            eyre::bail!(
+                "The previous image's FST has {} entries, more than its 0x{:x} bytes hold",
+                num_entries,
+                fst.len()
+            );
+        }
+        let mut files = Vec::new();
+        let mut end = fst_offset + fst.len() as u64;
+        for entry in fst.chunks_exact(FstEntry::BLOCK_SIZE).take(num_entries).skip(1) {
+            let entry = FstEntry::try_from(entry)?;
+            if let FstNodeType::File = entry.get_node_type() {
+                files.push(Fingerprint::Image);
+                end = end.max(
+                    entry.get_file_offset(shift == 2)
+                        + entry.get_file_size_next_dir_index() as u64,
+                );
+            }
+        }
+        Ok(Self {
+            fst_offset,
+            fst,
+            files,
+            padding: Padding::Zeros,
+            len: align_addr(align_addr(end, 2), 8),
+        })
+    }
+
+    /// Whether the files are placed and padded the same way in both layouts, so only their
+    /// data may differ
+    pub fn is_compatible(&self, other: &Layout) -> bool {
+        self.fst_offset == other.fst_offset
+            && self.fst == other.fst
+            && self.padding == other.padding
+            && self.len == other.len
+    }
+
+    /// Size of the serialized disc, before encryption on Wii
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Size of the image once written, with the hashes and the partition of a Wii disc
+    pub fn image_len(&self, is_wii: bool) -> u64 {
+        image_len(self.len, is_wii)
+    }
+
+    pub fn fst_offset(&self) -> u64 {
+        self.fst_offset
+    }
+
+    pub fn fst_len(&self) -> u64 {
+        self.fst.len() as u64
+    }
+
+    /// Lists the files of the FST, in its order, with their offsets and sizes
+    pub fn files(&self, is_wii: bool) -> Result<Vec<PlacedFile>> {
+        let root = FstEntry::try_from(&self.fst[..])?;
+        let count = root.get_file_size_next_dir_index() as usize;
+        let names = self
+            .fst
+            .get(count * FstEntry::BLOCK_SIZE..)
+            .ok_or(eyre::eyre!("The FST is truncated"))?;
+        // Directories containing the entry, with the index of the entry after them
+        let mut parents = Vec::<(usize, String)>::new();
+        let mut files = Vec::new();
+        for index in 1..count {
+            while parents.last().is_some_and(|(end, _)| *end <= index) {
+                parents.pop();
+            }
+            let entry = FstEntry::try_from(&self.fst[index * FstEntry::BLOCK_SIZE..])?;
+            let name = names
+                .get(entry.get_file_name_offset() as usize..)
+                .and_then(|name| name.split(|&c| c == 0).next())
+                .ok_or(eyre::eyre!("The name of the FST entry #{} is out of bounds", index))?;
+            let name = String::from_utf8_lossy(name);
+            let path = match parents.last() {
+                Some((_, parent)) => format!("{}/{}", parent, name),
+                None => name.into_owned(),
+            };
+            match entry.get_node_type() {
+                FstNodeType::Directory => {
+                    parents.push((entry.get_file_size_next_dir_index() as usize, path))
+                }
+                FstNodeType::File => files.push(PlacedFile {
+                    path,
+                    offset: entry.get_file_offset(is_wii),
+                    len: entry.get_file_size_next_dir_index() as u64,
+                }),
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Orders names like the SDK's FST: case-insensitively, as if they were upper case
+pub fn fst_order(a: &str, b: &str) -> Ordering {
+    a.bytes()
+        .map(|c| c.to_ascii_uppercase())
+        .cmp(b.bytes().map(|c| c.to_ascii_uppercase()))
+        .then_with(|| a.cmp(b))
+}
+
 pub struct Directory<R> {
     name: String,
     children: Vec<Box<dyn Node<R>>>,
@@ -660,19 +1231,26 @@ where
         Some(dir)
     }
 
+    /// Inserts the node where the SDK's FST ordering puts it, and returns its index
+    fn insert_sorted(&mut self, node: Box<dyn Node<R>>) -> usize {
+        let name = node.name();
+        let index = self
+            .children
+            .partition_point(|c| fst_order(&c.name(), &name) == Ordering::Less);
+        self.children.insert(index, node);
+        index
+    }
+
     pub fn mkdir<P: AsRef<Path>>(&mut self, name: P) -> &mut Directory<R> {
         if self
             .children
             .iter()
             .all(|c| c.name() != name.as_ref().as_os_str().to_string_lossy())
         {
-            self.children.push(Box::new(Directory::new(
+            let index = self.insert_sorted(Box::new(Directory::new(
                 name.as_ref().as_os_str().to_string_lossy(),
             )));
-            self.children
-                .last_mut()
-                .map(|x| x.as_directory_mut().unwrap())
-                .unwrap()
+            self.children[index].as_directory_mut().unwrap()
         } else {
             self.children
                 .iter_mut()
@@ -708,11 +1286,8 @@ where
 
     pub fn add_file(&mut self, file: File<R>) -> &mut File<R> {
         if self.children.iter().all(|c| c.name() != file.name()) {
-            self.children.push(Box::new(file));
-            self.children
-                .last_mut()
-                .map(|c| c.as_file_mut().unwrap())
-                .unwrap()
+            let index = self.insert_sorted(Box::new(file));
+            self.children[index].as_file_mut().unwrap()
         } else {
             self.children
                 .iter_mut()
@@ -827,6 +1402,118 @@ where
             .as_directory_mut()
             .ok_or(eyre::eyre!("\"{:?}\" is not a Directory!", path.as_ref()))
     }
+
+    /// Paths of every file and directory below, each directory before its content
+    pub fn paths(&self) -> Vec<String> {
+        fn traverse<R>(dir: &Directory<R>, prefix: &str, paths: &mut Vec<String>) {
+            for child in &dir.children {
+                let path = format!("{}{}", prefix, child.name());
+                paths.push(path.clone());
+                if let Some(dir) = child.as_directory_ref() {
+                    traverse(dir, &format!("{}/", path), paths);
+                }
+            }
+        }
+        let mut paths = Vec::new();
+        traverse(self, "", &mut paths);
+        paths
+    }
+
+    /// Paths of the directories below, including this one, whose children aren't in the SDK's
+    /// FST order
+    pub fn unsorted_directories(&self) -> Vec<String> {
+        let mut unsorted = Vec::new();
+        if self
+            .children
+            .windows(2)
+            .any(|pair| fst_order(&pair[0].name(), &pair[1].name()) == Ordering::Greater)
+        {
+            unsorted.push(String::new());
+        }
+        for child in &self.children {
+            if let Some(dir) = child.as_directory_ref() {
+                unsorted.extend(dir.unsorted_directories().into_iter().map(|path| {
+                    match path.is_empty() {
+                        true => dir.name.clone(),
+                        false => format!("{}/{}", dir.name, path),
+                    }
+                }));
+            }
+        }
+        unsorted
+    }
+
+    /// Splits `path` into its parent directory and the name of the node
+    fn split_path(path: &Path) -> Result<(&Path, String)> {
+        let name = path
+            .file_name()
+            .ok_or(eyre::eyre!("\"{:?}\" has no file name", path))?
+            .to_string_lossy()
+            .into_owned();
+        Ok((path.parent().unwrap_or(Path::new("")), name))
+    }
+
+    /// Removes the file or directory at `path` and returns it
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Result<Box<dyn Node<R>>> {
+        let (parent, name) = Self::split_path(path.as_ref())?;
+        let dir = self.get_dir_mut(parent)?;
+        let index = dir
+            .children
+            .iter()
+            .position(|c| c.name() == name)
+            .ok_or(eyre::eyre!("\"{:?}\" doesn't exist", path.as_ref()))?;
+        Ok(dir.children.remove(index))
+    }
+
+    /// Renames the file or directory at `path`
+    pub fn rename<P: AsRef<Path>>(&mut self, path: P, name: &str) -> Result<()> {
+        let (parent, _) = Self::split_path(path.as_ref())?;
+        self.move_node(path.as_ref(), parent.join(name))
+    }
+
+    /// Moves the file or directory at `from` to `to`, creating the missing parent directories
+    pub fn move_node<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (to_parent, name) = Self::split_path(to)?;
+        if from == to {
+            return Ok(());
+        }
+        if self.resolve_node(from).is_none() {
+            eyre::bail!("\"{:?}\" doesn't exist", from);
+        }
+        if to.starts_with(from) {
+            eyre::bail!("\"{:?}\" can't be moved inside itself", from);
+        }
+        if self.resolve_node(to).is_some() {
+            eyre::bail!("\"{:?}\" already exists", to);
+        }
+        self.mkdirs(to_parent)?;
+        let mut node = self.remove(from)?;
+        match node.as_enum_mut() {
+            NodeEnumMut::File(file) => file.set_name(name)?,
+            NodeEnumMut::Directory(dir) => dir.name = name,
+        }
+        self.get_dir_mut(to_parent)?.insert_sorted(node);
+        Ok(())
+    }
+}
+
+impl<R> Directory<R>
+where
+    R: Clone + 'static,
+{
+    /// Copies the directory tree without sharing the files' data, so it can be modified
+    /// without affecting the original
+    pub fn deep_clone(&self) -> eyre::Result<Self> {
+        let mut dir = Directory::new(self.name.clone());
+        for child in &self.children {
+            dir.children.push(match child.as_enum_ref() {
+                NodeEnumRef::File(file) => Box::new(file.deep_clone()?),
+                NodeEnumRef::Directory(child) => Box::new(child.deep_clone()?),
+            });
+        }
+        Ok(dir)
+    }
 }
 
 impl<R> Node<R> for Directory<R> {
@@ -863,17 +1550,176 @@ impl<R> Node<R> for Directory<R> {
     }
 }
 
+/// Where the data of a file provided by the host is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostSource {
+    /// A file on the host
+    File(std::path::PathBuf),
+    /// An entry of a zip on the host
+    ZipEntry { zip: std::path::PathBuf, index: usize },
+}
+
+/// An opened [`HostSource`], positioned where its next read starts
+pub struct HostReader {
+    reader: Box<dyn std::io::Read + Send>,
+    position: u64,
+}
+
+impl std::fmt::Debug for HostReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostReader")
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HostSource {
+    /// Opens the data, decompressing a zip entry while it's read
+    fn open(&self) -> io::Result<HostReader> {
+        let reader: Box<dyn std::io::Read + Send> = match self {
+            Self::File(path) => Box::new(std::io::BufReader::new(std::fs::File::open(path)?)),
+            Self::ZipEntry { zip, index } => {
+                let mut archive = ZipArchive::new(std::fs::File::open(zip)?)?;
+                let entry = archive.by_index_raw(*index)?;
+                let (start, size, compression) =
+                    (entry.data_start(), entry.compressed_size(), entry.compression());
+                drop(entry);
+                let mut file = archive.into_inner();
+                std::io::Seek::seek(&mut file, SeekFrom::Start(start))?;
+                let raw = std::io::Read::take(std::io::BufReader::new(file), size);
+                match compression {
+                    CompressionMethod::Stored => Box::new(raw),
+                    CompressionMethod::Deflated => Box::new(DeflateDecoder::new(raw)),
+                    method => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            format!("Unsupported compression method {}", method),
+                        ))
+                    }
+                }
+            }
+        };
+        Ok(HostReader {
+            reader,
+            position: 0,
+        })
+    }
+
+    /// Reads at `offset`, reusing the reader when it's already there
+    fn read_at(
+        &self,
+        reader: &mut Option<HostReader>,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        if !matches!(reader, Some(r) if r.position <= offset) {
+            *reader = Some(self.open()?);
+        }
+        let reader = reader.as_mut().unwrap();
+        if reader.position < offset {
+            let skip = offset - reader.position;
+            let skipped = std::io::copy(
+                &mut std::io::Read::take(&mut reader.reader, skip),
+                &mut std::io::sink(),
+            )?;
+            reader.position += skipped;
+            if skipped < skip {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+        }
+        let num_read = reader.reader.read(buf)?;
+        reader.position += num_read as u64;
+        Ok(num_read)
+    }
+
+    /// SHA-1 of the data, read in chunks
+    fn digest(&self) -> io::Result<[u8; 20]> {
+        let mut reader = self.open()?.reader;
+        let mut digest = sha1_smol::Sha1::new();
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => break,
+                num_read => digest.update(&buf[..num_read]),
+            }
+        }
+        Ok(digest.digest().bytes())
+    }
+}
+
 #[derive(Debug)]
 pub enum FileDataSource<R> {
     Reader { reader: DiscReader<R>, fst: FstNode },
     Box { data: Box<[u8]>, name: String },
+    /// Data read from the host only when the file is written, so it isn't held in memory.
+    /// Create it with [`FileDataSource::from_host_file`] or [`FileDataSource::from_zip_entry`].
+    Host {
+        source: HostSource,
+        name: String,
+        len: usize,
+        reader: Option<HostReader>,
+    },
 }
 
 impl<R> FileDataSource<R> {
+    /// A file on the host, whose size is taken from its metadata
+    pub fn from_host_file<P: Into<std::path::PathBuf>>(path: P, name: String) -> Result<Self> {
+        let path = path.into();
+        let len = std::fs::metadata(&path)
+            .map_err(|err| eyre::eyre!("Couldn't read the metadata of {:?}: {}", path, err))?
+            .len() as usize;
+        Ok(Self::Host {
+            source: HostSource::File(path),
+            name,
+            len,
+            reader: None,
+        })
+    }
+
+    /// The entry `index` of `archive`, opened from `zip` when it's read. Fails for entries
+    /// which aren't stored or deflated.
+    pub fn from_zip_entry<RZip, P>(
+        archive: &mut ZipArchive<RZip>,
+        zip: P,
+        index: usize,
+        name: String,
+    ) -> Result<Self>
+    where
+        RZip: std::io::Read + std::io::Seek,
+        P: Into<std::path::PathBuf>,
+    {
+        let entry = archive.by_index_raw(index)?;
+        if entry.encrypted()
+            || !matches!(
+                entry.compression(),
+                CompressionMethod::Stored | CompressionMethod::Deflated
+            )
+        {
+            eyre::bail!("\"{}\" can't be read lazily", entry.name());
+        }
+        Ok(Self::Host {
+            source: HostSource::ZipEntry {
+                zip: zip.into(),
+                index,
+            },
+            name,
+            len: entry.size() as usize,
+            reader: None,
+        })
+    }
+
     pub fn name(&self) -> String {
         match self {
             Self::Reader { fst, .. } => fst.get_relative_file_name().to_owned(),
             Self::Box { name, .. } => name.clone(),
+            Self::Host { name, .. } => name.clone(),
+        }
+    }
+
+    pub fn set_name(&mut self, new_name: String) {
+        match self {
+            Self::Reader { fst, .. } => fst.set_relative_file_name(new_name),
+            Self::Box { name, .. } | Self::Host { name, .. } => *name = new_name,
         }
     }
 
@@ -881,6 +1727,7 @@ impl<R> FileDataSource<R> {
         match self {
             Self::Reader { fst, .. } => fst.get_file_size().unwrap(),
             Self::Box { data, .. } => data.len(),
+            Self::Host { len, .. } => *len,
         }
     }
 
@@ -903,6 +1750,14 @@ where
                 data: data.clone(),
                 name: name.clone(),
             },
+            Self::Host {
+                source, name, len, ..
+            } => Self::Host {
+                source: source.clone(),
+                name: name.clone(),
+                len: *len,
+                reader: None,
+            },
         }
     }
 }
@@ -951,6 +1806,16 @@ impl<R> File<R> {
         }
     }
 
+    pub fn set_name(&mut self, name: String) -> eyre::Result<()> {
+        match self.status.lock() {
+            Ok(mut status) => {
+                status.data.set_name(name);
+                Ok(())
+            }
+            Err(_) => Err(eyre::eyre!("Failed to lock the file status")),
+        }
+    }
+
     pub fn len(&self) -> eyre::Result<usize> {
         self.status
             .lock()
@@ -961,6 +1826,48 @@ impl<R> File<R> {
     pub fn is_empty(&self) -> eyre::Result<bool> {
         Ok(self.len()? == 0)
     }
+
+    /// Offset of the data on the original disc, when it's read from there
+    fn original_offset(&self) -> eyre::Result<Option<u64>> {
+        let status = self
+            .status
+            .lock()
+            .map_err(|_| eyre::eyre!("Failed to lock the file status"))?;
+        Ok(match &status.data {
+            FileDataSource::Reader { fst, .. } => Some(fst.get_file_offset().unwrap_or_default()),
+            _ => None,
+        })
+    }
+
+    fn fingerprint(&self) -> eyre::Result<Fingerprint> {
+        let status = self
+            .status
+            .lock()
+            .map_err(|_| eyre::eyre!("Failed to lock the file status"))?;
+        Ok(match &status.data {
+            FileDataSource::Reader { fst, .. } => {
+                Fingerprint::Disc(fst.get_file_offset().unwrap_or_default())
+            }
+            FileDataSource::Box { data, .. } => {
+                Fingerprint::Data(sha1_smol::Sha1::from(data).digest().bytes())
+            }
+            FileDataSource::Host { source, .. } => Fingerprint::Data(source.digest()?),
+        })
+    }
+}
+
+impl<R> File<R>
+where
+    R: Clone,
+{
+    /// Copies the file without sharing its data, unlike `clone`
+    pub fn deep_clone(&self) -> eyre::Result<Self> {
+        let status = self
+            .status
+            .lock()
+            .map_err(|_| eyre::eyre!("Failed to lock the file status"))?;
+        Ok(Self::new(status.data.clone()))
+    }
 }
 
 impl<R> AsyncSeek for File<R>
@@ -1018,6 +1925,7 @@ where
                 SeekFrom::Current(pos)
             }
         };
+        let len = status.data.len() as i64;
         match &mut status.data {
             FileDataSource::Reader { fst, .. } => {
                 match pos {
@@ -1035,13 +1943,13 @@ where
                     }
                 }
             }
-            FileDataSource::Box { data, .. } => match pos {
+            FileDataSource::Box { .. } | FileDataSource::Host { .. } => match pos {
                 SeekFrom::Start(pos) => {
                     status.cursor = pos;
                     Poll::Ready(Ok(status.cursor))
                 }
                 SeekFrom::End(pos) => {
-                    status.cursor = (data.len() as i64 + pos) as u64;
+                    status.cursor = (len + pos) as u64;
                     Poll::Ready(Ok(status.cursor))
                 }
                 SeekFrom::Current(pos) => {
@@ -1103,8 +2011,8 @@ where
                         Poll::Pending => Poll::Pending,
                     }
                 }
-                FileDataSource::Box { ref data, .. } => {
-                    if cursor > data.len() as u64 {
+                FileDataSource::Box { .. } | FileDataSource::Host { .. } => {
+                    if cursor > status.data.len() as u64 {
                         Poll::Ready(Err(io::Error::from(io::ErrorKind::InvalidInput)))
                     } else {
                         status.state = FileState::Reading;
@@ -1135,6 +2043,21 @@ where
                     status.state = FileState::Seeking;
                     Poll::Ready(Ok(num_read))
                 }
+                FileDataSource::Host {
+                    ref source,
+                    ref mut reader,
+                    ..
+                } => match source.read_at(reader, cursor, &mut buf[..end]) {
+                    Ok(num_read) => {
+                        status.cursor += num_read as u64;
+                        status.state = FileState::Seeking;
+                        Poll::Ready(Ok(num_read))
+                    }
+                    Err(err) => {
+                        status.state = FileState::Seeking;
+                        Poll::Ready(Err(err))
+                    }
+                },
             },
         }
     }
@@ -1176,3 +2099,300 @@ impl<R> Node<R> for File<R> {
         Some(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::Cursor;
+    use async_std::task::block_on;
+
+    type Reader = Cursor<Vec<u8>>;
+
+    fn boxed(name: &str, data: Vec<u8>) -> File<Reader> {
+        File::new(FileDataSource::Box {
+            data: data.into_boxed_slice(),
+            name: name.to_owned(),
+        })
+    }
+
+    fn game() -> GeckoFS<Reader> {
+        let mut gfs = GeckoFS::new();
+        gfs.sys_mut()
+            .add_file(boxed("iso.hdr", vec![0; consts::HEADER_LENGTH]));
+        gfs.sys_mut().add_file(boxed("AppLoader.ldr", vec![1; 0x100]));
+        gfs.sys_mut().add_file(boxed("Start.dol", vec![2; 0x300]));
+        gfs.root_mut().add_file(boxed("a.bin", vec![3; 0x50]));
+        gfs.root_mut()
+            .mkdir("dir")
+            .add_file(boxed("b.bin", vec![4; 0x80]));
+        gfs
+    }
+
+    fn serialize(gfs: &mut GeckoFS<Reader>) -> (Vec<u8>, Layout) {
+        let mut writer = DiscWriter::new_gc(Cursor::new(Vec::new()));
+        let layout = block_on(gfs.serialize_with_layout(&mut writer)).unwrap();
+        let DiscWriter::Gamecube(cursor) = writer else {
+            unreachable!()
+        };
+        (cursor.into_inner(), layout)
+    }
+
+    #[test]
+    fn only_changed_files_are_rewritten() {
+        let (image, layout) = serialize(&mut game());
+        assert_eq!(image.len() as u64, layout.len());
+
+        let mut changed = game();
+        changed
+            .root_mut()
+            .get_file_mut("dir/b.bin")
+            .unwrap()
+            .set_data(vec![5; 0x80].into_boxed_slice())
+            .unwrap();
+        changed
+            .sys_mut()
+            .get_file_mut("Start.dol")
+            .unwrap()
+            .set_data(vec![6; 0x300].into_boxed_slice())
+            .unwrap();
+        let mut deep_copy = changed.deep_clone().unwrap();
+        let (expected, new_layout) = serialize(&mut deep_copy);
+        assert!(layout.is_compatible(&new_layout));
+
+        let mut reader = DiscReader::Gamecube(Cursor::new(image.clone()));
+        let (changed_layout, changes) =
+            block_on(changed.changes(false, &layout, Some(&mut reader)))
+                .unwrap()
+                .unwrap();
+        assert_eq!(changes.len(), 2);
+        let mut writer = Cursor::new(image.clone());
+        block_on(GeckoFS::write_changes(&mut writer, changes)).unwrap();
+        assert_eq!(writer.into_inner(), expected);
+
+        // The compared files are hashed, so the next build doesn't need the image
+        let (_, changes) = block_on(changed.changes::<Reader>(false, &changed_layout, None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+
+        // Comparing with the previous image finds the same changes
+        let mut reader = DiscReader::Gamecube(Cursor::new(image));
+        let image_layout = block_on(Layout::read(&mut reader)).unwrap();
+        assert!(image_layout.is_compatible(&layout));
+        assert_eq!(image_layout.len(), layout.len());
+        let (_, changes) = block_on(changed.changes(false, &image_layout, Some(&mut reader)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(changes.len(), 2);
+
+        let mut moved = game();
+        moved
+            .root_mut()
+            .get_file_mut("a.bin")
+            .unwrap()
+            .set_data(vec![3; 0x500].into_boxed_slice())
+            .unwrap();
+        assert!(block_on(moved.changes::<Reader>(false, &layout, None))
+            .unwrap()
+            .is_none());
+
+        let mut scrubbed = game();
+        scrubbed.set_padding(Padding::Scrub);
+        assert!(block_on(scrubbed.changes::<Reader>(false, &layout, None))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn restored_disc_files_are_rewritten() {
+        let (image, _) = serialize(&mut game());
+        let original = block_on(GeckoFS::parse(DiscReader::Gamecube(Cursor::new(image)))).unwrap();
+        let mut modded = original.deep_clone().unwrap();
+        modded
+            .root_mut()
+            .get_file_mut("dir/b.bin")
+            .unwrap()
+            .set_data(vec![5; 0x80].into_boxed_slice())
+            .unwrap();
+        let (modded_image, _) = serialize(&mut modded);
+        let (expected, _) = serialize(&mut original.deep_clone().unwrap());
+
+        let mut restored = original.deep_clone().unwrap();
+        let mut reader = DiscReader::Gamecube(Cursor::new(modded_image.clone()));
+        let image_layout = block_on(Layout::read(&mut reader)).unwrap();
+        let (_, changes) = block_on(restored.changes(false, &image_layout, Some(&mut reader)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(changes.len(), 2);
+        let mut writer = Cursor::new(modded_image);
+        block_on(GeckoFS::write_changes(&mut writer, changes)).unwrap();
+        assert_eq!(writer.into_inner(), expected);
+    }
+
+    #[test]
+    fn corrupt_fsts_have_no_layout() {
+        let (image, layout) = serialize(&mut game());
+        let fst_offset = layout.fst_offset() as usize;
+        let mut too_many_entries = image.clone();
+        too_many_entries[fst_offset + 8..fst_offset + 12].copy_from_slice(&[0, 1, 0, 0]);
+        let mut too_short = image;
+        too_short[consts::OFFSET_FST_SIZE..consts::OFFSET_FST_SIZE + 4]
+            .copy_from_slice(&[0, 0, 0, 8]);
+        for corrupt in [too_many_entries, too_short] {
+            let mut reader = DiscReader::Gamecube(Cursor::new(corrupt));
+            assert!(block_on(Layout::read(&mut reader)).is_err());
+        }
+    }
+
+    #[test]
+    fn system_files_keep_their_original_offsets() {
+        let mut gfs = game();
+        gfs.system_offsets = Some((0x2800, 0x3000));
+        let (_, layout) = serialize(&mut gfs.deep_clone().unwrap());
+        assert_eq!(layout.fst_offset(), 0x2900);
+
+        gfs.set_placement(Placement {
+            preserve_offsets: true,
+            ..Default::default()
+        });
+        let (image, layout) = serialize(&mut gfs);
+        assert_eq!(layout.fst_offset(), 0x3000);
+        assert_eq!(
+            &image[OFFSET_DOL_OFFSET..OFFSET_DOL_OFFSET + 8],
+            &[0, 0, 0x28, 0, 0, 0, 0x30, 0]
+        );
+    }
+
+    #[test]
+    fn host_files_are_read_lazily() {
+        use std::io::Write;
+        use zip::write::{FileOptions, ZipWriter};
+
+        let data = (0..0x30000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let dir = std::env::temp_dir().join(format!("geckolib-host-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let host = dir.join("b.bin");
+        std::fs::write(&host, &data).unwrap();
+        let zip_path = dir.join("mod.zip");
+        let mut zip = ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        zip.start_file(
+            "b.bin",
+            FileOptions::<()>::default().compression_method(CompressionMethod::Deflated),
+        )
+        .unwrap();
+        zip.write_all(&data).unwrap();
+        zip.finish().unwrap();
+
+        let mut expected = game();
+        expected
+            .root_mut()
+            .get_file_mut("dir/b.bin")
+            .unwrap()
+            .set_data(data.clone().into_boxed_slice())
+            .unwrap();
+        let (expected, _) = serialize(&mut expected);
+
+        let mut archive = ZipArchive::new(std::fs::File::open(&zip_path).unwrap()).unwrap();
+        for source in [
+            FileDataSource::from_host_file(&host, "b.bin".to_owned()).unwrap(),
+            FileDataSource::from_zip_entry(&mut archive, &zip_path, 0, "b.bin".to_owned()).unwrap(),
+        ] {
+            assert_eq!(source.len(), data.len());
+            let mut gfs = game();
+            let dir = gfs.root_mut().get_dir_mut("dir").unwrap();
+            dir.children.clear();
+            dir.add_file(File::new(source));
+            assert_eq!(serialize(&mut gfs).0, expected);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn layouts_list_the_files_of_the_fst() {
+        let layout = block_on(game().layout(false)).unwrap();
+        let files = layout.files(false).unwrap();
+        assert_eq!(
+            files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+            vec!["a.bin", "dir/b.bin"]
+        );
+        assert_eq!(files[1].len, 0x80);
+        assert!(files[0].offset >= layout.fst_offset() + layout.fst_len());
+        assert_eq!(files[1].offset, (files[0].offset + 0x50).next_multiple_of(32));
+    }
+
+    #[test]
+    fn images_larger_than_the_disc_are_refused() {
+        let mut gfs = game();
+        let big = |name: &str| {
+            File::new(FileDataSource::Reader {
+                reader: DiscReader::Gamecube(Cursor::new(Vec::new())),
+                fst: FstNode::File {
+                    relative_file_name: name.to_owned(),
+                    file_offset: 0,
+                    file_size: 0xC000_0000,
+                },
+            })
+        };
+        gfs.root_mut().add_file(big("big1.bin"));
+        let err = block_on(gfs.layout(false)).unwrap_err().to_string();
+        assert!(err.contains("dir/b.bin: 0.0 MiB"), "{}", err);
+        // Needs a dual-layer disc
+        gfs.root_mut().add_file(big("big2.bin"));
+        assert!(block_on(gfs.layout(true)).is_ok());
+        gfs.root_mut().add_file(big("big3.bin"));
+        assert!(block_on(gfs.layout(true)).is_err());
+    }
+
+    #[test]
+    fn nodes_are_removed_renamed_and_moved() {
+        let mut gfs = game();
+        gfs.root_mut().add_file(boxed("c.bin", vec![5; 0x10]));
+
+        gfs.rename("a.bin", "renamed.bin").unwrap();
+        assert!(gfs.rename("renamed.bin", "c.bin").is_err());
+        gfs.move_node("dir/b.bin", "new/dir/b.bin").unwrap();
+        assert!(gfs.move_node("new", "new/inner").is_err());
+        gfs.remove("c.bin").unwrap();
+        assert!(gfs.remove("c.bin").is_err());
+        assert_eq!(
+            gfs.root().paths(),
+            vec!["dir", "new", "new/dir", "new/dir/b.bin", "renamed.bin"]
+        );
+
+        // Files read from the disc are renamed too
+        let (image, _) = serialize(&mut gfs);
+        let mut parsed = block_on(GeckoFS::parse(DiscReader::Gamecube(Cursor::new(image)))).unwrap();
+        parsed.move_node("new/dir/b.bin", "new/b.bin").unwrap();
+        let (image, _) = serialize(&mut parsed);
+        let parsed = block_on(GeckoFS::parse(DiscReader::Gamecube(Cursor::new(image)))).unwrap();
+        assert_eq!(
+            parsed.root().paths(),
+            vec!["dir", "new", "new/b.bin", "new/dir", "renamed.bin"]
+        );
+    }
+
+    #[test]
+    fn children_are_kept_in_fst_order() {
+        let mut gfs = game();
+        let dir = gfs.root_mut().mkdir("dir");
+        for name in ["_x.bin", "B.bin", "a.bin", "A.bin"] {
+            dir.add_file(boxed(name, vec![0; 4]));
+        }
+        assert_eq!(
+            gfs.root().paths(),
+            vec!["a.bin", "dir", "dir/A.bin", "dir/a.bin", "dir/B.bin", "dir/b.bin", "dir/_x.bin"]
+        );
+        assert!(gfs.root().unsorted_directories().is_empty());
+
+        // An unsorted disc keeps its order, and is reported
+        gfs.root_mut()
+            .get_dir_mut("dir")
+            .unwrap()
+            .children
+            .reverse();
+        let (image, _) = serialize(&mut gfs);
+        let parsed = block_on(GeckoFS::parse(DiscReader::Gamecube(Cursor::new(image)))).unwrap();
+        assert_eq!(parsed.root().paths(), gfs.root().paths());
+        assert_eq!(parsed.root().unsorted_directories(), vec!["dir"]);
+    }
+}
@@ -0,0 +1,326 @@
+//! Combines the disc's FileSystem with ordered layers of replacement files.
+//!
+//! The disc is the read-only base layer. Each overlay layer, like a mod folder or a patch zip,
+//! replaces or adds files, and removes files of the layers below with whiteouts. Layers
+//! applied later take precedence, and paths touched by several layers are reported as
+//! conflicts.
+
+use super::{Directory, File, GeckoFS};
+use async_std::io::{Read as AsyncRead, Seek as AsyncSeek};
+use eyre::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Prefix of the files of a layer's folder or zip which remove the file named after it
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// What a layer does to a path of the disc
+pub enum LayerEntry<R> {
+    /// Replaces or adds a file
+    File(File<R>),
+    /// Removes a file or a directory
+    Whiteout,
+}
+
+/// A set of files replacing or removing files of the disc
+pub struct Layer<R> {
+    name: String,
+    entries: BTreeMap<String, LayerEntry<R>>,
+}
+
+/// A path of the disc touched by several layers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: String,
+    /// Names of the layers touching the path, the last one wins
+    pub layers: Vec<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "\"{}\" is replaced by several layers: {}",
+            self.path,
+            self.layers.join(", ")
+        )
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether `path` is `ancestor` or inside it
+fn is_within(path: &str, ancestor: &str) -> bool {
+    path.strip_prefix(ancestor)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl<R> Layer<R> {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds the file at `path`, named after its last segment, or turns a whiteout file name
+    /// into a whiteout
+    pub fn add_file(&mut self, path: &str, mut file: File<R>) -> Result<()> {
+        let path = normalize(path);
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", &path));
+        match name.strip_prefix(WHITEOUT_PREFIX) {
+            Some(removed) if dir.is_empty() => self.whiteout(removed),
+            Some(removed) => self.whiteout(&format!("{}/{}", dir, removed)),
+            None => {
+                file.set_name(name.to_owned())?;
+                self.entries.insert(path, LayerEntry::File(file));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the file or directory at `path` from the layers below
+    pub fn whiteout(&mut self, path: &str) {
+        self.entries.insert(normalize(path), LayerEntry::Whiteout);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &LayerEntry<R>)> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| (path.as_str(), entry))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Ordered layers applied onto the disc's FileSystem
+pub struct Overlay<R> {
+    layers: Vec<Layer<R>>,
+}
+
+impl<R> Default for Overlay<R> {
+    fn default() -> Self {
+        Self { layers: Vec::new() }
+    }
+}
+
+impl<R> Overlay<R>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Clone + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer on top of the others
+    pub fn push(&mut self, layer: Layer<R>) {
+        self.layers.push(layer);
+    }
+
+    pub fn layers(&self) -> &[Layer<R>] {
+        &self.layers
+    }
+
+    /// Lists the paths touched by several layers, including files inside a directory
+    /// removed by another layer, either below or above them
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut touched = BTreeMap::<&str, Vec<&str>>::new();
+        for (i, layer) in self.layers.iter().enumerate() {
+            for (path, entry) in layer.iter() {
+                let removes = matches!(entry, LayerEntry::Whiteout);
+                let mut layers = self.layers[..i]
+                    .iter()
+                    .filter(|below| {
+                        below.iter().any(|(other, below_entry)| {
+                            other == path
+                                || removes && is_within(other, path)
+                                || matches!(below_entry, LayerEntry::Whiteout)
+                                    && is_within(path, other)
+                        })
+                    })
+                    .map(|below| below.name())
+                    .collect::<Vec<_>>();
+                if !layers.is_empty() {
+                    layers.push(layer.name());
+                    let entry = touched.entry(path).or_default();
+                    for layer in layers {
+                        if !entry.contains(&layer) {
+                            entry.push(layer);
+                        }
+                    }
+                }
+            }
+        }
+        touched
+            .into_iter()
+            .map(|(path, layers)| Conflict {
+                path: path.to_owned(),
+                layers: layers.into_iter().map(str::to_owned).collect(),
+            })
+            .collect()
+    }
+
+    /// Applies the layers in order onto `fs`
+    pub fn apply(&self, fs: &mut GeckoFS<R>) -> Result<()> {
+        for layer in &self.layers {
+            crate::debug!("Applying layer {} ({} entries)", layer.name(), layer.len());
+            for (path, entry) in layer.iter() {
+                let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+                match entry {
+                    LayerEntry::File(file) => {
+                        let dir = fs.root_mut().mkdirs(dir)?;
+                        replace_child(dir, name, file.deep_clone()?)?;
+                    }
+                    LayerEntry::Whiteout => {
+                        if fs.remove(path).is_err() {
+                            crate::warn!(
+                                "{} removes \"{}\" which doesn't exist",
+                                layer.name(),
+                                path
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the combined FileSystem, leaving the base layer untouched
+    pub fn merge(&self, base: &GeckoFS<R>) -> Result<GeckoFS<R>> {
+        let mut fs = base.deep_clone()?;
+        self.apply(&mut fs)?;
+        Ok(fs)
+    }
+}
+
+/// Puts the file in place of the child with the same name, keeping its position in the FST
+fn replace_child<R: 'static>(dir: &mut Directory<R>, name: &str, file: File<R>) -> Result<()> {
+    match dir.children.iter().position(|c| c.name() == name) {
+        Some(index) if dir.children[index].as_directory_ref().is_some() => {
+            eyre::bail!(
+                "\"{}\" is a directory and can't be replaced by a file",
+                name
+            )
+        }
+        Some(index) => dir.children[index] = Box::new(file),
+        None => {
+            dir.add_file(file);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::FileDataSource;
+    use async_std::io::Cursor;
+
+    type Reader = Cursor<Vec<u8>>;
+
+    fn boxed(name: &str, data: &[u8]) -> File<Reader> {
+        File::new(FileDataSource::Box {
+            data: data.into(),
+            name: name.to_owned(),
+        })
+    }
+
+    fn data(fs: &GeckoFS<Reader>, path: &str) -> Option<Vec<u8>> {
+        let file = fs.root().resolve_node(path)?.as_file_ref()?;
+        let status = file.status.lock().unwrap();
+        match &status.data {
+            FileDataSource::Box { data, .. } => Some(data.to_vec()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn layers_are_applied_in_order() {
+        let mut base = GeckoFS::<Reader>::new();
+        base.root_mut().add_file(boxed("a.bin", b"base"));
+        base.root_mut()
+            .mkdir("dir")
+            .add_file(boxed("b.bin", b"base"));
+        base.root_mut().add_file(boxed("c.bin", b"base"));
+
+        let mut first = Layer::new("first");
+        first.add_file("a.bin", boxed("a.bin", b"first")).unwrap();
+        first
+            .add_file("dir/.wh.b.bin", boxed(".wh.b.bin", b""))
+            .unwrap();
+        let mut second = Layer::new("second");
+        second
+            .add_file("/a.bin", boxed("a.bin", b"second"))
+            .unwrap();
+        second
+            .add_file("new/d.bin", boxed("d.bin", b"second"))
+            .unwrap();
+
+        let mut overlay = Overlay::new();
+        overlay.push(first);
+        overlay.push(second);
+        assert_eq!(
+            overlay.conflicts(),
+            vec![Conflict {
+                path: "a.bin".to_owned(),
+                layers: vec!["first".to_owned(), "second".to_owned()],
+            }]
+        );
+
+        let merged = overlay.merge(&base).unwrap();
+        assert_eq!(data(&merged, "a.bin").unwrap(), b"second");
+        assert!(merged.root().resolve_node("dir/b.bin").is_none());
+        assert_eq!(data(&merged, "c.bin").unwrap(), b"base");
+        assert_eq!(data(&merged, "new/d.bin").unwrap(), b"second");
+        // Replaced files keep their position
+        assert_eq!(merged.root().iter().next().unwrap().name(), "a.bin");
+        // The base layer is left untouched
+        assert_eq!(data(&base, "a.bin").unwrap(), b"base");
+        assert!(base.root().resolve_node("dir/b.bin").is_some());
+    }
+
+    #[test]
+    fn removing_a_directory_conflicts_with_the_files_added_into_it() {
+        let mut base = GeckoFS::<Reader>::new();
+        base.root_mut().mkdir("dir");
+
+        let mut added = Layer::new("added");
+        added
+            .add_file("dir/new.bin", boxed("source.bin", b"added"))
+            .unwrap();
+        let mut removed = Layer::new("removed");
+        removed.whiteout("dir");
+
+        let mut overlay = Overlay::new();
+        overlay.push(added);
+        // The file is named after its path
+        assert_eq!(
+            data(&overlay.merge(&base).unwrap(), "dir/new.bin").unwrap(),
+            b"added"
+        );
+        overlay.push(removed);
+        assert_eq!(
+            overlay.conflicts(),
+            vec![Conflict {
+                path: "dir".to_owned(),
+                layers: vec!["added".to_owned(), "removed".to_owned()],
+            }]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Where the files' data is placed on the disc.
+//!
+//! By default the files follow the FST one after the other, aligned to 32 bytes. Files can
+//! be moved ahead to sit together, aligned further, or kept where the original disc had
+//! them so a patch of the image stays small.
+
+use super::glob::Glob;
+use eyre::Result;
+
+/// Alignment of every file's data, as the SDK's DVD functions expect
+pub const MIN_ALIGNMENT: u64 = 32;
+
+/// Rules `GeckoFS::serialize` follows to place the files
+#[derive(Debug, Clone, Default)]
+pub struct Placement {
+    /// Files placed first, by the first pattern they match; the others follow in FST order
+    pub order: Vec<Glob>,
+    /// Alignments, in bytes, of the files matching the patterns; the largest one applies
+    pub alignments: Vec<(Glob, u64)>,
+    /// Keeps the files, the DOL and the FST of the original disc at their offsets when they
+    /// still fit there
+    pub preserve_offsets: bool,
+}
+
+/// A file to place
+pub(super) struct Item {
+    pub path: String,
+    pub len: u64,
+    /// Offset of the data on the original disc, when it's unchanged
+    pub original: Option<u64>,
+}
+
+impl Placement {
+    pub fn add_alignment(&mut self, pattern: &str, alignment: u64) -> Result<()> {
+        if !alignment.is_power_of_two() {
+            eyre::bail!(
+                "The alignment of \"{}\" ({:#x}) isn't a power of two",
+                pattern,
+                alignment
+            );
+        }
+        self.alignments.push((Glob::new(pattern)?, alignment));
+        Ok(())
+    }
+
+    fn alignment(&self, path: &str) -> u64 {
+        self.alignments
+            .iter()
+            .filter(|(glob, _)| glob.is_match(path))
+            .map(|(_, alignment)| *alignment)
+            .fold(MIN_ALIGNMENT, u64::max)
+    }
+
+    /// Computes the offsets of the files, in the same order, placing them from `start`
+    pub(super) fn place(&self, start: u64, items: &[Item]) -> Vec<u64> {
+        let mut offsets = vec![None; items.len()];
+        // Ranges of the disc taken by the files, sorted
+        let mut used = Vec::<(u64, u64)>::new();
+
+        if self.preserve_offsets {
+            let mut preserved = (0..items.len())
+                .filter(|&i| items[i].original.is_some())
+                .collect::<Vec<_>>();
+            preserved.sort_by_key(|&i| items[i].original);
+            let mut end = start;
+            for i in preserved {
+                let offset = items[i].original.unwrap_or_default();
+                if offset >= end && offset & (self.alignment(&items[i].path) - 1) == 0 {
+                    offsets[i] = Some(offset);
+                    end = offset + items[i].len;
+                    used.push((offset, end));
+                }
+            }
+        }
+
+        let mut remaining = (0..items.len())
+            .filter(|&i| offsets[i].is_none())
+            .collect::<Vec<_>>();
+        remaining.sort_by_key(|&i| {
+            self.order
+                .iter()
+                .position(|glob| glob.is_match(&items[i].path))
+                .unwrap_or(self.order.len())
+        });
+        let mut cursor = start;
+        for i in remaining {
+            let alignment = self.alignment(&items[i].path);
+            // Gaps left by the preserved files are filled first, otherwise the files follow
+            // each other
+            let mut offset = cursor.next_multiple_of(alignment);
+            let mut index = used.partition_point(|&(_, used_end)| used_end <= offset);
+            while index < used.len() {
+                let (used_start, used_end) = used[index];
+                if offset + items[i].len <= used_start {
+                    break;
+                }
+                if used_end > offset {
+                    offset = used_end.next_multiple_of(alignment);
+                }
+                index += 1;
+            }
+            used.insert(index, (offset, offset + items[i].len));
+            offsets[i] = Some(offset);
+            if !self.preserve_offsets {
+                cursor = offset + items[i].len;
+            }
+        }
+
+        offsets.into_iter().map(Option::unwrap_or_default).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(path: &str, len: u64, original: Option<u64>) -> Item {
+        Item {
+            path: path.to_owned(),
+            len,
+            original,
+        }
+    }
+
+    #[test]
+    fn files_are_ordered_aligned_and_preserved() {
+        let items = [
+            item("a.bin", 0x10, Some(0x1000)),
+            item("audio/b.adp", 0x10, None),
+            item("hot/c.bin", 0x10, None),
+        ];
+
+        assert_eq!(
+            Placement::default().place(0x100, &items),
+            vec![0x100, 0x120, 0x140]
+        );
+
+        let mut placement = Placement {
+            order: vec![Glob::new("hot/*").unwrap()],
+            ..Default::default()
+        };
+        placement.add_alignment("audio/*.adp", 0x8000).unwrap();
+        assert!(placement.add_alignment("*", 0x30).is_err());
+        assert_eq!(placement.place(0x100, &items), vec![0x120, 0x8000, 0x100]);
+
+        placement.preserve_offsets = true;
+        assert_eq!(placement.place(0x100, &items), vec![0x1000, 0x8000, 0x100]);
+        // Files of the original disc overwritten by the system files are moved
+        assert_eq!(
+            placement.place(0x1008, &items),
+            vec![0x1040, 0x8000, 0x1020]
+        );
+    }
+}
@@ -94,7 +94,7 @@ async fn apply(
     iso: PathBuf,
     save: PathBuf,
 ) -> Result<(), eyre::Error> {
-    let patch = std::fs::OpenOptions::new().read(true).open(patch)?;
+    let patch_file = std::fs::OpenOptions::new().read(true).open(&patch)?;
     let iso = async_std::fs::OpenOptions::new().read(true).open(iso).await?;
     let save = async_std::fs::OpenOptions::new()
         .write(true)
@@ -102,7 +102,9 @@ async fn apply(
         .truncate(true)
         .open(save)
         .await?;
-    let mut builder = open_config_from_patch(patch, iso, save).await?;
+    let mut builder = open_config_from_patch(patch_file, iso, save)
+        .await?
+        .with_patch_path(patch);
     builder.build().await?;
     Ok(())
 }
@@ -5,7 +5,8 @@ use async_std::io::{prelude::SeekExt, ReadExt};
 use clap::{arg, command, Parser, ValueHint};
 use futures::AsyncWriteExt;
 use geckolib::{
-    iso::{read::DiscReader, write::DiscWriter}, vfs::GeckoFS
+    iso::{read::DiscReader, write::DiscWriter},
+    vfs::{placement::Placement, GeckoFS, Padding},
 };
 #[cfg(feature = "progress")]
 use geckolib::UPDATER;
@@ -26,6 +27,11 @@ struct Args {
     #[arg(value_hint = ValueHint::AnyPath)]
     /// Where to write the reprocessed file
     dest: PathBuf,
+    #[arg(long)]
+    /// Keep the files at their offsets and pad with the junk data of retail discs. Wii discs
+    /// still differ from retail ones, their data partition is rebuilt and signed again
+    /// without the update partition
+    retail: bool,
 }
 
 // Reprocesses a given iso (load iso in to a FileSystem, then save it back into an other iso)
@@ -74,6 +80,13 @@ fn main() -> color_eyre::eyre::Result<()> {
         }
 
         let mut fs = GeckoFS::parse(f).await?;
+        if args.retail {
+            fs.set_placement(Placement {
+                preserve_offsets: true,
+                ..Default::default()
+            });
+            fs.set_padding(Padding::Junk);
+        }
         #[cfg(feature = "log")]
         log::info!("Encrypting the ISO");
         #[cfg(feature = "progress")]
@@ -22,6 +22,27 @@ pub enum Commands {
         #[arg(short, long)]
         /// Legacy option kept for compatibility
         raw: bool,
+        #[arg(short, long)]
+        /// Rebuilds whenever the crate, the patch file or the replaced files change
+        watch: bool,
+        #[arg(short, long)]
+        /// Only rewrites the changed files of the previous output, unless files moved
+        in_place: bool,
+        #[arg(long, conflicts_with_all = ["patch", "watch", "in_place"])]
+        /// Goes through the build without writing anything, and lists the files, the DOL's
+        /// sections, the linked symbols and the size of the image
+        dry_run: bool,
+    },
+    /// Checks the original values expected by the patch against the game, without building
+    Verify,
+    /// Lists the files replaced in the game once the patterns of `[files]` are resolved,
+    /// without building
+    Files,
+    /// Writes Rust bindings to the game's symbols, to be re-run when the symbol map changes
+    Bindgen {
+        #[arg(short, long, default_value = "src/game.rs", value_hint = ValueHint::FilePath)]
+        /// Output path for the Rust module
+        output: PathBuf,
     },
     /// Applies a patch file to a game to create a Rom Hack
     Apply {
@@ -40,4 +61,43 @@ pub enum Commands {
         #[arg(value_hint = ValueHint::Other)]
         name: String,
     },
+    /// Converts a fully linked executable ELF into a DOL
+    Elf2Dol {
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Input path to the ELF
+        input: PathBuf,
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Output path for the DOL
+        output: PathBuf,
+    },
+    /// Converts a DOL into an ELF which can be loaded by standard tools
+    Dol2Elf {
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Input path to the DOL
+        input: PathBuf,
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Output path for the ELF
+        output: PathBuf,
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        /// Symbol map of the game to include in the ELF
+        map: Option<PathBuf>,
+    },
+    /// Ports a game's symbol map to another version of the game by matching its functions
+    Port {
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Input path to the DOL the symbol map belongs to
+        source: PathBuf,
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Input path to the symbol map
+        map: PathBuf,
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Input path to the DOL of the other version
+        target: PathBuf,
+        #[arg(value_hint = ValueHint::FilePath)]
+        /// Output path for the ported symbol map
+        output: PathBuf,
+        #[arg(long, default_value_t = 0.5)]
+        /// Leaves out the symbols matched with a lower confidence, from 0 to 1
+        min_confidence: f32,
+    },
 }
@@ -1,10 +1,16 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use async_std::task;
 use clap::Parser;
 use geckolib::{
-    iso::builder::Builder, new, open_config_from_fs_iso, open_config_from_fs_patch,
-    open_config_from_patch,
+    iso::builder::Builder,
+    bindgen, new, open_config_from_fs_iso, open_config_from_fs_iso_in_place, open_config_from_fs_patch, open_config_from_patch, plan_build, resolve_files, trim_output, verify_patch, watch,
+    patch::{
+        dol::DolFile,
+        elf::{dol2elf, elf2dol},
+        framework_map, port,
+    },
 };
 
 #[cfg(feature = "progress")]
@@ -32,7 +38,39 @@ fn main() -> color_eyre::eyre::Result<()> {
     }
 
     match args.cmd {
-        Commands::Build { patch, raw: _ } => {
+        Commands::Build { dry_run: true, .. } => {
+            task::block_on::<_, color_eyre::eyre::Result<()>>(async {
+                let plan =
+                    plan_build(&async_std::path::PathBuf::from_str("RomHack.toml")?).await?;
+                println!("{}", plan);
+                Ok(())
+            })
+        }
+        Commands::Build {
+            patch,
+            watch: true,
+            ..
+        } => task::block_on(watch(
+            &async_std::path::PathBuf::from_str("RomHack.toml")?,
+            patch,
+            |result| match result {
+                Ok(()) => println!("Build finished"),
+                Err(err) => eprintln!("Error: {:?}", err),
+            },
+        )),
+        Commands::Build {
+            patch: false,
+            in_place: true,
+            ..
+        } => task::block_on::<_, color_eyre::eyre::Result<()>>(async {
+            let mut builder = open_config_from_fs_iso_in_place(
+                &async_std::path::PathBuf::from_str("RomHack.toml")?,
+            )
+            .await?;
+            builder.build().await?;
+            trim_output(&builder)
+        }),
+        Commands::Build { patch, raw: _, .. } => {
             task::block_on::<_, color_eyre::eyre::Result<()>>(async {
                 if patch {
                     let mut builder = open_config_from_fs_patch(
@@ -49,25 +87,81 @@ fn main() -> color_eyre::eyre::Result<()> {
                 }
             })
         }
+        Commands::Verify => task::block_on::<_, color_eyre::eyre::Result<()>>(async {
+            let mismatches =
+                verify_patch(&async_std::path::PathBuf::from_str("RomHack.toml")?).await?;
+            for mismatch in &mismatches {
+                println!("{}", mismatch);
+            }
+            if !mismatches.is_empty() {
+                color_eyre::eyre::bail!("{} expected values don't match", mismatches.len());
+            }
+            Ok(())
+        }),
+        Commands::Files => task::block_on::<_, color_eyre::eyre::Result<()>>(async {
+            let files =
+                resolve_files(&async_std::path::PathBuf::from_str("RomHack.toml")?).await?;
+            for (iso_path, actual_path) in &files {
+                println!("{} <- {}", iso_path, actual_path.display());
+            }
+            Ok(())
+        }),
+        Commands::Bindgen { output } => task::block_on(bindgen(
+            &async_std::path::PathBuf::from_str("RomHack.toml")?,
+            &output,
+        )),
         Commands::Apply {
             patch,
             original_game,
             output,
         } => task::block_on::<_, color_eyre::eyre::Result<()>>(async {
             let mut builder = open_config_from_patch(
-                std::fs::OpenOptions::new().read(true).open(patch)?,
+                std::fs::OpenOptions::new().read(true).open(&patch)?,
                 async_std::fs::OpenOptions::new()
                     .read(true)
                     .open(original_game)
                     .await?,
                     async_std::fs::OpenOptions::new().write(true).create(true).truncate(true).open(output).await?,
             )
-            .await?;
+            .await?
+            .with_patch_path(patch);
             builder.build().await
         }),
         Commands::New { name } => {
             new(&name)?;
             Ok(())
         }
+        Commands::Elf2Dol { input, output } => {
+            let dol = elf2dol(&std::fs::read(input)?)?;
+            std::fs::write(output, dol.to_bytes()?)?;
+            Ok(())
+        }
+        Commands::Dol2Elf { input, output, map } => {
+            task::block_on::<_, color_eyre::eyre::Result<()>>(async {
+                let dol = DolFile::parse(&mut async_std::fs::File::open(input).await?).await?;
+                let symbols = match map {
+                    Some(map) => framework_map::parse(&mut async_std::fs::File::open(map).await?).await?,
+                    None => HashMap::new(),
+                };
+                std::fs::write(output, dol2elf(&dol, &symbols))?;
+                Ok(())
+            })
+        }
+        Commands::Port {
+            source,
+            map,
+            target,
+            output,
+            min_confidence,
+        } => task::block_on::<_, color_eyre::eyre::Result<()>>(async {
+            let source = DolFile::parse(&mut async_std::fs::File::open(source).await?).await?;
+            let target = DolFile::parse(&mut async_std::fs::File::open(target).await?).await?;
+            let symbols = framework_map::parse(&mut async_std::fs::File::open(map).await?).await?;
+            let mut ported = port::port(&source, &symbols, &target);
+            ported.retain(|s| s.confidence >= min_confidence);
+            let mut file = std::io::BufWriter::new(std::fs::File::create(output)?);
+            port::write_map(&mut file, &ported)?;
+            Ok(())
+        }),
     }
 }